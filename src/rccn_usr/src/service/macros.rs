@@ -1,11 +1,17 @@
 #[macro_export]
 macro_rules! impl_verification_sender {
-    ($name:ident, $state_in:ty, $state_out:ty) => {
+    ($name:ident, $state_in:ty, $state_out:ty, $ack_flag:expr, $skip:expr) => {
         paste::paste! {
+            /// Sends the success report if the TC requested it with its acknowledgement flags.
             pub fn [<send_ $name _success>](
                 &self,
                 token:  $state_in,
             ) -> Result<$state_out, EcssTmtcError> {
+                if self.ack_flags & $ack_flag == 0 {
+                    let skip: fn($state_in) -> $state_out = $skip;
+                    return Ok(skip(token));
+                }
+
                 let tm_sender = self.get_tm_sender();
                 let reporter = self.app.verification_reporter.clone();
                 let timestamp = self.app.timestamp_helper.stamp();
@@ -4,19 +4,24 @@ pub mod util;
 use crate::{
     impl_verification_sender,
     time::TimestampHelper,
-    types::{RccnEcssTmSender, Sender},
+    types::{MsgCounterPolicy, MsgCounters, RccnEcssTmSender, Sender, SharedMsgCounters},
 };
 use satrs::{
     pus::verification::{TcStateAccepted, TcStateStarted},
     spacepackets::{
-        ecss::{tc::PusTcReader, PusError, PusPacket},
+        ecss::{
+            self,
+            tc::{PusTcReader, ACK_ALL},
+            PusError, PusPacket,
+        },
         CcsdsPacket,
     },
 };
 use satrs::{
+    pool::{PoolAddr, SharedStaticMemoryPool},
     pus::{
         verification::{
-            FailParams, TcStateNone, VerificationReporter, VerificationReporterCfg,
+            FailParams, FailParamsWithStep, TcStateNone, VerificationReporter, VerificationReporterCfg,
             VerificationReportingProvider, VerificationToken,
         },
         EcssTmSender, EcssTmtcError,
@@ -30,7 +35,25 @@ use satrs::{
     },
     ComponentId,
 };
-use std::sync::{Arc, Mutex};
+use futures::future::BoxFuture;
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Acknowledgement flags of a TC, requesting the success reports of the execution stages.
+/// Failures are reported regardless of the flags.
+///
+/// spacepackets only exports the combined [`ACK_ALL`], the single flags are kept private,
+/// so they are defined here and checked against it at compile time.
+pub const ACK_ACCEPTANCE: u8 = 0b1000;
+pub const ACK_START: u8 = 0b0100;
+pub const ACK_PROGRESS: u8 = 0b0010;
+pub const ACK_COMPLETION: u8 = 0b0001;
+const _: () = assert!(ACK_ACCEPTANCE | ACK_START | ACK_PROGRESS | ACK_COMPLETION == ACK_ALL);
 
 #[derive(Clone)]
 pub struct PusAppBase {
@@ -38,7 +61,12 @@ pub struct PusAppBase {
     pub verification_reporter: VerificationReporter,
     pub timestamp_helper: TimestampHelper,
     pub component_id: ComponentId,
-    pub msg_counter: Arc<Mutex<u16>>,
+    /// Message type counters of all TM of the APID, shared with the async handlers
+    pub msg_counters: SharedMsgCounters,
+    /// Pool for TM sent with [`CommandReplyBase::send_stored_tm`]
+    pub tm_pool: Option<SharedStaticMemoryPool>,
+    /// Spacecraft time reference status (4 bits) of all TM, may change while the app runs
+    pub time_ref_status: Arc<AtomicU8>,
 }
 
 impl PusAppBase {
@@ -57,15 +85,25 @@ impl PusAppBase {
                 &verification_reporter_cfg,
             ),
             component_id,
-            msg_counter: Arc::new(Mutex::new(0u16)),
+            msg_counters: Arc::new(Mutex::new(MsgCounters::default())),
+            tm_pool: None,
+            time_ref_status: Arc::new(AtomicU8::new(0)),
         }
     }
 
+    /// Changes which TM share a message type counter, restarting all counters at 0.
+    pub fn set_msg_counter_policy(&self, policy: MsgCounterPolicy) {
+        *self.msg_counters.lock().unwrap() = MsgCounters::new(policy);
+    }
+
     pub fn new_reply(&self, service: u8, tx: Sender) -> CommandReplyBase {
         CommandReplyBase {
             app: self.clone(),
             service,
-            tx
+            tx,
+            ack_flags: ACK_ALL,
+            dest_id: 0,
+            started: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -75,28 +113,94 @@ pub struct CommandReplyBase {
     pub app: PusAppBase,
     pub service: u8,
     pub tx: Sender,
+    /// Acknowledgement flags of the TC this is a reply to
+    pub ack_flags: u8,
+    /// Destination ID of all TM, the source ID of the TC this is a reply to
+    pub dest_id: u16,
+    /// Set when the execution of the TC has started, shared by all clones of the base
+    started: Arc<AtomicBool>,
 }
 
 impl CommandReplyBase {
     pub fn get_tm_sender(&self) -> RccnEcssTmSender {
         RccnEcssTmSender {
             channel: self.tx.clone(),
-            msg_counter: self.app.msg_counter.clone(),
+            msg_counters: self.app.msg_counters.clone(),
+            tm_pool: self.app.tm_pool.clone(),
+            dest_id: self.dest_id,
+            time_ref_status: self.app.time_ref_status.load(Ordering::Relaxed),
         }
     }
 
+    /// Whether the execution of the TC has started, i.e. a [`AcceptedTc`] method has passed
+    /// the start stage, even if the start report was not requested.
+    ///
+    /// A failure after the start is a completion failure (TM[1,8]), before it a start failure (TM[1,4]).
+    pub fn execution_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    /// Replies with the acknowledgement flags of `tc`, to the ground application which sent it.
+    pub fn for_tc(mut self, tc: &PusTcReader) -> Self {
+        // Not imported, its service() clashes with PusPacket::service()
+        use ecss::tc::GenericPusTcSecondaryHeader as TcHeader;
+        self.ack_flags = TcHeader::ack_flags(tc);
+        self.dest_id = TcHeader::source_id(tc);
+        self
+    }
+
     #[rustfmt::skip]
-    impl_verification_sender!(acceptance, VerificationToken<TcStateNone>, VerificationToken<TcStateAccepted>);
+    impl_verification_sender!(acceptance, VerificationToken<TcStateNone>, VerificationToken<TcStateAccepted>,
+        ACK_ACCEPTANCE, |t| VerificationToken::<TcStateAccepted>::new_accepted_state(t.request_id()));
     #[rustfmt::skip]
-    impl_verification_sender!(start, VerificationToken<TcStateAccepted>, VerificationToken<TcStateStarted>);
-    impl_verification_sender!(completion, VerificationToken<TcStateStarted>, ());
+    impl_verification_sender!(start, VerificationToken<TcStateAccepted>, VerificationToken<TcStateStarted>,
+        ACK_START, |t| VerificationToken::<TcStateStarted>::new_started_state(t.request_id()));
+    impl_verification_sender!(completion, VerificationToken<TcStateStarted>, (), ACK_COMPLETION, |_| ());
+
+    /// Sends a progress report (TM[1,5]) for execution step `step`, if the TC requested it.
+    ///
+    /// The width of the step ID is given by the type of `step`, e.g. [`EcssEnumU8`].
+    pub fn send_step_success(
+        &self,
+        token: &VerificationToken<TcStateStarted>,
+        step: impl EcssEnumeration,
+    ) -> Result<(), EcssTmtcError> {
+        if self.ack_flags & ACK_PROGRESS == 0 {
+            return Ok(());
+        }
+
+        let tm_sender = self.get_tm_sender();
+        let reporter = self.app.verification_reporter.clone();
+        let timestamp = self.app.timestamp_helper.stamp();
+
+        reporter.step_success(&tm_sender, token, timestamp, step)
+    }
+
+    /// Sends a progress failure report (TM[1,6]) for execution step `step`, which ends the command.
+    pub fn send_step_failure(
+        &self,
+        token: VerificationToken<TcStateStarted>,
+        step: &impl EcssEnumeration,
+        failure_code: &dyn EcssEnumeration,
+        failure_data: &[u8],
+    ) -> Result<(), EcssTmtcError> {
+        let tm_sender = self.get_tm_sender();
+        let reporter = self.app.verification_reporter.clone();
+        let timestamp = self.app.timestamp_helper.stamp();
+
+        reporter.step_failure(
+            &tm_sender,
+            token,
+            FailParamsWithStep::new(timestamp, step, failure_code, failure_data),
+        )
+    }
 
     pub fn create_tm<'ts, 'd>(
         &'ts self,
         subservice: u8,
         src_data: &'d [u8],
     ) -> PusTmCreator<'ts, 'd> {
-        // TODO: destination ID not used
+        // The destination ID and time reference status are set when the TM is sent
         PusTmCreator::new(
             SpHeader::new(
                 PacketId::new(PacketType::Tm, true, self.app.apid),
@@ -119,6 +223,14 @@ impl CommandReplyBase {
         self.get_tm_sender()
             .send_tm(0, satrs::pus::PusTmVariant::Direct(tm))
     }
+
+    /// Sends a TM packet from the TM pool of the app and removes it from the pool.
+    ///
+    /// If the packet can't be sent, it is left in the pool, to be sent again or deleted by the caller.
+    pub fn send_stored_tm(&self, addr: PoolAddr) -> Result<(), EcssTmtcError> {
+        self.get_tm_sender()
+            .send_tm(0, satrs::pus::PusTmVariant::InStore(addr))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -140,6 +252,16 @@ pub enum AcceptanceError {
     ArgumentError,
     ServiceDisconnected,
     SendVerificationTmFailed,
+    /// The command gate does not allow this service type and subtype
+    CommandDenied(u8, u8),
+    /// A critical command was not armed, or the arm window has passed
+    CommandNotArmed(u8, u8),
+    /// The service is already running as many commands as it is allowed to
+    ServiceBusy(u8),
+    /// A service TM report could not be sent, the command has been executed
+    SendTmFailed,
+    /// An arm command was sent for a command which does not need arming
+    CommandNotCritical(u8, u8),
 }
 
 impl Into<EcssEnumU8> for AcceptanceError {
@@ -153,6 +275,11 @@ impl Into<EcssEnumU8> for AcceptanceError {
             AcceptanceError::ArgumentError => 6,
             AcceptanceError::ServiceDisconnected => 7,
             AcceptanceError::SendVerificationTmFailed => 8,
+            AcceptanceError::CommandDenied(..) => 9,
+            AcceptanceError::CommandNotArmed(..) => 10,
+            AcceptanceError::ServiceBusy(_) => 11,
+            AcceptanceError::SendTmFailed => 12,
+            AcceptanceError::CommandNotCritical(..) => 13,
         };
 
         EcssEnumU8::new(tag)
@@ -163,6 +290,10 @@ impl Into<EcssEnumU8> for AcceptanceError {
 #[derive(Debug, PartialEq)]
 pub enum CommandExecutionStatus {
     /// The task requested by the command has been started, but has not finished executing.
+    ///
+    /// Only commands of an [`AsyncPusService`] are tracked until they finish. A [`PusService`]
+    /// returning this is responsible for reporting the completion itself, it is neither
+    /// timed out nor can it be cancelled.
     Started,
     /// The task has been started and finished successfully.
     Completed,
@@ -185,10 +316,7 @@ pub trait PusService {
 
     fn handle_tc(&mut self, tc: AcceptedTc, cmd: Self::CommandT) -> AcceptanceResult;
 
-    fn handle_tc_bytes(&mut self, bytes: &[u8], mut base: CommandReplyBase) -> AcceptanceResult {
-        // ST[01] verification util
-        let mut reporter = base.app.verification_reporter.clone();
-
+    fn handle_tc_bytes(&mut self, bytes: &[u8], base: CommandReplyBase) -> AcceptanceResult {
         let pus_tc = match PusTcReader::new(&bytes) {
             Err(e) => {
                 // Could not parse the incoming bytes as a PUS TC.
@@ -198,47 +326,122 @@ pub trait PusService {
             Ok((tc, _size)) => Ok(tc),
         }?;
 
-        let token = reporter.add_tc(&pus_tc);
-        base.app.timestamp_helper.update_from_now();
+        self.handle_pus_tc(&pus_tc, base)
+    }
 
-        // Check if the TC is destined for this APID
-        if pus_tc.sp_header().apid() != base.app.apid {
-            // It's not. Return early but don't send an acceptance failure.
-            return Err(AcceptanceError::UnknownApid(pus_tc.sp_header().apid()));
-        }
+    /// Handles a TC which has already been parsed, see [`PusService::handle_tc_bytes`].
+    fn handle_pus_tc(&mut self, pus_tc: &PusTcReader, base: CommandReplyBase) -> AcceptanceResult {
+        let (tc, svc_cmd) = accept_tc::<Self::CommandT>(pus_tc, base, Self::service())?;
+        self.handle_tc(tc, svc_cmd)
+    }
+}
 
-        // Check if the TC is destined for this service
-        if pus_tc.service() != Self::service() {
-            // It's not. Return early but don't send an acceptance failure
-            // (there may be other services on this APID) that can respond to this.
-            return Err(AcceptanceError::UnknownService(pus_tc.service()));
-        }
+/// A future executing a command, see [`AsyncPusService`]
+pub type TcFuture = BoxFuture<'static, AcceptanceResult>;
 
-        // Try to parse the TC using the service's CommandT
-        // Send an acceptance failure TM frame if we couldn't parse the TC
-        let svc_cmd = Self::CommandT::from_pus_tc(&pus_tc).map_err(|parse_error| {
-            let err = AcceptanceError::CommandParseError(parse_error);
+/// A service whose commands are executed as futures.
+///
+/// Long running commands don't block the app this way, it keeps accepting TCs
+/// while the futures run.
+pub trait AsyncPusService {
+    type CommandT: ServiceCommand;
 
-            // Send acceptance failure
-            let err_code: EcssEnumU8 = err.clone().into();
-            let send_result = base.send_acceptance_failure(token, &err_code, &[]);
-            if let Err(e) = send_result {
-                println!("Error sending acceptance failure TM: {e}");
-            };
+    fn service() -> u8;
 
-            err
-        })?;
+    /// Returns the future executing `cmd`.
+    ///
+    /// The future reports the start and completion of the command through `tc`,
+    /// e.g. with [`AcceptedTc::handle_async`].
+    fn handle_tc(&mut self, tc: AcceptedTc, cmd: Self::CommandT) -> TcFuture;
+}
 
-        base.app.timestamp_helper.update_from_now();
+/// Logs a verification report which could not be sent.
+fn verification_sent<T>(result: Result<T, EcssTmtcError>) -> Result<T, AcceptanceError> {
+    result.map_err(|e| {
+        println!("Error sending verification TM: {e}");
+        AcceptanceError::SendVerificationTmFailed
+    })
+}
 
-        // Send TC accepted telemetry, get TC accepted token
-        let accepted_token = base.send_acceptance_success(token).map_err(|err| {
-            println!("Error sending acceptance success telemetry: {err}");
-            AcceptanceError::SendVerificationTmFailed
-        })?;
+/// Checks that a TC is for `service` of this app and parses its command.
+///
+/// Sends the acceptance report, a failure if the command could not be parsed.
+/// TCs for another APID or service are rejected without a report.
+pub fn accept_tc<C: ServiceCommand>(
+    pus_tc: &PusTcReader,
+    mut base: CommandReplyBase,
+    service: u8,
+) -> Result<(AcceptedTc, C), AcceptanceError> {
+    // ST[01] verification util
+    let mut reporter = base.app.verification_reporter.clone();
+
+    let token = reporter.add_tc(pus_tc);
+    base = base.for_tc(pus_tc);
+    base.app.timestamp_helper.update_from_now();
+
+    // Check if the TC is destined for this APID
+    if pus_tc.sp_header().apid() != base.app.apid {
+        // It's not. Return early but don't send an acceptance failure.
+        return Err(AcceptanceError::UnknownApid(pus_tc.sp_header().apid()));
+    }
 
-        let tc = AcceptedTc::new(base, accepted_token);
-        self.handle_tc(tc, svc_cmd)
+    // Check if the TC is destined for this service
+    if pus_tc.service() != service {
+        // It's not. Return early but don't send an acceptance failure
+        // (there may be other services on this APID) that can respond to this.
+        return Err(AcceptanceError::UnknownService(pus_tc.service()));
+    }
+
+    // Try to parse the TC using the service's CommandT
+    // Send an acceptance failure TM frame if we couldn't parse the TC
+    let svc_cmd = C::from_pus_tc(pus_tc).map_err(|parse_error| {
+        let err = AcceptanceError::CommandParseError(parse_error);
+
+        // Send acceptance failure
+        let err_code: EcssEnumU8 = err.clone().into();
+        let send_result = base.send_acceptance_failure(token, &err_code, &[]);
+        if let Err(e) = send_result {
+            println!("Error sending acceptance failure TM: {e}");
+        };
+
+        err
+    })?;
+
+    base.app.timestamp_helper.update_from_now();
+
+    // Send TC accepted telemetry, get TC accepted token
+    let accepted_token = verification_sent(base.send_acceptance_success(token))?;
+
+    Ok((AcceptedTc::new(base, accepted_token), svc_cmd))
+}
+
+/// Why the execution of a command failed, reported in the ST[01] failure notice.
+///
+/// Services define their own failure codes, e.g. as a `#[repr(u8)]` enum, and convert their
+/// errors into a `CommandFailure` together with the data explaining the failure.
+/// The codes from [`CommandFailure::FIRST_RESERVED_CODE`] on are reserved for failures which
+/// are not reported by the service itself, e.g. aborted commands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandFailure {
+    pub code: u8,
+    pub data: Vec<u8>,
+}
+
+impl CommandFailure {
+    /// Failure code of handlers which don't give a reason
+    pub const GENERIC_CODE: u8 = 1;
+    /// First failure code which must not be used by services
+    pub const FIRST_RESERVED_CODE: u8 = 0xF0;
+
+    pub fn new(code: u8, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            code,
+            data: data.into(),
+        }
+    }
+
+    pub fn generic() -> Self {
+        Self::new(Self::GENERIC_CODE, [])
     }
 }
 
@@ -257,47 +460,435 @@ impl AcceptedTc {
         Self { base, token }
     }
 
+    /// Source ID of the TC, the ground application all reports are addressed to.
+    pub fn source_id(&self) -> u16 {
+        self.base.dest_id
+    }
+
+    /// Reports the start of execution, for commands which report their progress.
+    pub fn start(mut self) -> Result<StartedTc, EcssTmtcError> {
+        self.base.app.timestamp_helper.update_from_now();
+        let token = self.report_start()?;
+        Ok(StartedTc {
+            base: self.base,
+            token,
+        })
+    }
+
+    /// Reports the start of execution and marks the base as started.
+    fn report_start(&self) -> Result<VerificationToken<TcStateStarted>, EcssTmtcError> {
+        let token = self.base.send_start_success(self.token)?;
+        self.base.started.store(true, Ordering::SeqCst);
+        Ok(token)
+    }
+
+    /// Executes a command which either succeeds or fails for an unspecified reason.
     pub fn handle<F>(&self, f: F) -> AcceptanceResult
     where
         F: FnOnce() -> bool,
     {
-        let started_token = self.base.send_start_success(self.token).unwrap();
+        self.handle_result(|| if f() { Ok(()) } else { Err(CommandFailure::generic()) })
+    }
 
-        let result = f();
+    /// Reports the completion failure of a started command.
+    fn fail(
+        &self,
+        started_token: VerificationToken<TcStateStarted>,
+        failure: CommandFailure,
+    ) -> AcceptanceResult {
+        verification_sent(self.base.send_completion_failure(
+            started_token,
+            &EcssEnumU8::new(failure.code),
+            &failure.data,
+        ))?;
+        Ok(CommandExecutionStatus::Failed)
+    }
 
-        if result {
-            self.base.send_completion_success(started_token).unwrap();
-            Ok(CommandExecutionStatus::Completed)
-        } else {
-            self.base
-                .send_completion_failure(started_token, &EcssEnumU8::new(1), &[])
-                .unwrap();
-            Ok(CommandExecutionStatus::Failed)
+    /// Executes a command, a failure is reported with its code and data.
+    ///
+    /// Returns [`AcceptanceError::SendVerificationTmFailed`] if a report could not be sent,
+    /// the command is not executed if its start could not be reported.
+    pub fn handle_result<E, F>(&self, f: F) -> AcceptanceResult
+    where
+        E: Into<CommandFailure>,
+        F: FnOnce() -> Result<(), E>,
+    {
+        let started_token = verification_sent(self.report_start())?;
+
+        match f() {
+            Ok(()) => {
+                verification_sent(self.base.send_completion_success(started_token))?;
+                Ok(CommandExecutionStatus::Completed)
+            }
+            Err(e) => self.fail(started_token, e.into()),
+        }
+    }
+
+    /// Async version of [`AcceptedTc::handle`], the start is reported before `f` is awaited.
+    pub async fn handle_async<F>(self, f: F) -> AcceptanceResult
+    where
+        F: Future<Output = bool>,
+    {
+        self.handle_async_result(|_| async {
+            if f.await {
+                Ok(())
+            } else {
+                Err(CommandFailure::generic())
+            }
+        })
+        .await
+    }
+
+    /// Async version of [`AcceptedTc::handle_result`], the start is reported before the future
+    /// returned by `f` is awaited.
+    ///
+    /// `f` gets a [`StepReporter`] to report the progress of the execution.
+    pub async fn handle_async_result<E, F, Fut>(mut self, f: F) -> AcceptanceResult
+    where
+        E: Into<CommandFailure>,
+        F: FnOnce(StepReporter) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+    {
+        let started_token = verification_sent(self.report_start())?;
+        let steps = StepReporter {
+            base: self.base.clone(),
+            token: started_token,
+        };
+
+        let result = f(steps).await;
+        self.base.app.timestamp_helper.update_from_now();
+        match result {
+            Ok(()) => {
+                verification_sent(self.base.send_completion_success(started_token))?;
+                Ok(CommandExecutionStatus::Completed)
+            }
+            Err(e) => self.fail(started_token, e.into()),
         }
     }
 
+    /// Executes a command which produces a TM report, a failure is reported with its code and data.
+    ///
+    /// Returns [`AcceptanceError::SendTmFailed`] if the report could not be sent.
     pub fn handle_with_tm<E, F>(&mut self, f: F) -> AcceptanceResult
     where
+        E: Into<CommandFailure>,
         F: FnOnce() -> Result<SubserviceTmData, E>,
     {
-        let started_token = self.base.send_start_success(self.token).unwrap();
+        let started_token = verification_sent(self.report_start())?;
 
         match f() {
-            Err(_) => {
-                self.base
-                    .send_completion_failure(started_token, &EcssEnumU8::new(1), &[])
-                    .unwrap();
-                Ok(CommandExecutionStatus::Failed)
-            }
+            Err(e) => self.fail(started_token, e.into()),
             Ok(tm_data) => {
-                self.base.send_completion_success(started_token).unwrap();
+                verification_sent(self.base.send_completion_success(started_token))?;
 
                 self.base.app.timestamp_helper.update_from_now();
                 let tm = self.base.create_tm(tm_data.subservice, &tm_data.data);
-                self.base.send_tm(tm).expect("could not send TM response");
+                self.base.send_tm(tm).map_err(|e| {
+                    println!("Error sending TM response: {e}");
+                    AcceptanceError::SendTmFailed
+                })?;
 
                 Ok(CommandExecutionStatus::Completed)
             }
         }
     }
 }
+
+/// Reports the progress of a command executed by [`AcceptedTc::handle_async_result`].
+pub struct StepReporter {
+    base: CommandReplyBase,
+    token: VerificationToken<TcStateStarted>,
+}
+
+impl StepReporter {
+    /// Reports that execution step `step` has finished (TM[1,5]).
+    pub fn step_success(&mut self, step: impl EcssEnumeration) -> Result<(), EcssTmtcError> {
+        self.base.app.timestamp_helper.update_from_now();
+        self.base.send_step_success(&self.token, step)
+    }
+}
+
+/// A command which is being executed, see [`AcceptedTc::start`].
+///
+/// The execution can be divided into steps, which are reported with their step ID as they finish.
+pub struct StartedTc {
+    pub base: CommandReplyBase,
+    pub token: VerificationToken<TcStateStarted>,
+}
+
+impl StartedTc {
+    /// Reports that execution step `step` has finished (TM[1,5]).
+    pub fn step_success(&mut self, step: impl EcssEnumeration) -> Result<(), EcssTmtcError> {
+        self.base.app.timestamp_helper.update_from_now();
+        self.base.send_step_success(&self.token, step)
+    }
+
+    /// Reports that execution step `step` has failed (TM[1,6]), which ends the command.
+    pub fn step_failure(
+        mut self,
+        step: &impl EcssEnumeration,
+        failure_code: &dyn EcssEnumeration,
+        failure_data: &[u8],
+    ) -> Result<(), EcssTmtcError> {
+        self.base.app.timestamp_helper.update_from_now();
+        self.base
+            .send_step_failure(self.token, step, failure_code, failure_data)
+    }
+
+    pub fn completion_success(mut self) -> Result<(), EcssTmtcError> {
+        self.base.app.timestamp_helper.update_from_now();
+        self.base.send_completion_success(self.token)
+    }
+
+    pub fn completion_failure(
+        mut self,
+        failure_code: &dyn EcssEnumeration,
+        failure_data: &[u8],
+    ) -> Result<(), EcssTmtcError> {
+        self.base.app.timestamp_helper.update_from_now();
+        self.base
+            .send_completion_failure(self.token, failure_code, failure_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::util::{create_pus_tc, create_pus_tc_with_ack};
+    use satrs::spacepackets::ecss::{EcssEnumU16, WritablePusPacket};
+
+    struct NoArgs;
+
+    impl ServiceCommand for NoArgs {
+        fn from_pus_tc(_tc: &PusTcReader) -> CommandParseResult<Self> {
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn test_step_reports() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let base = PusAppBase::new(42, 0).new_reply(130, tx);
+        let bytes = create_pus_tc(42, 130, 1, &[]).to_vec().unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+
+        let (accepted, _) = accept_tc::<NoArgs>(&tc, base, 130).unwrap();
+        let mut started = accepted.start().unwrap();
+        started.step_success(EcssEnumU8::new(1)).unwrap();
+        started.step_success(EcssEnumU16::new(0x0102)).unwrap();
+        started.step_failure(&EcssEnumU8::new(3), &EcssEnumU8::new(1), &[]).unwrap();
+
+        let reports: Vec<_> = rx.try_iter().collect();
+        let subservices: Vec<_> = reports.iter().map(|tm| tm[8]).collect();
+        assert_eq!(subservices, [1, 3, 5, 5, 6]);
+
+        // The step ID follows the headers (6 + 7 bytes, 8 byte timestamp)
+        // and the TC packet ID and sequence control
+        let step_offset = 6 + 7 + 8 + 4;
+        assert_eq!(reports[2][step_offset], 1);
+        assert_eq!(reports[3][step_offset..step_offset + 2], [1, 2]);
+        assert_eq!(reports[4][step_offset], 3);
+    }
+
+    #[test]
+    fn test_failure_code_and_data() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let base = PusAppBase::new(42, 0).new_reply(130, tx);
+        let bytes = create_pus_tc(42, 130, 1, &[]).to_vec().unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+
+        let (accepted, _) = accept_tc::<NoArgs>(&tc, base, 130).unwrap();
+        let status = accepted
+            .handle_result(|| Err(CommandFailure::new(7, [0xAB, 0xCD])))
+            .unwrap();
+        assert_eq!(status, CommandExecutionStatus::Failed);
+
+        let failure = rx.try_iter().last().unwrap();
+        assert_eq!(failure[8], 8);
+        // Failure code and data follow the TC packet ID and sequence control
+        let code_offset = 6 + 7 + 8 + 4;
+        assert_eq!(failure[code_offset..code_offset + 3], [7, 0xAB, 0xCD]);
+    }
+
+    #[test]
+    fn test_async_result() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let base = PusAppBase::new(42, 0).new_reply(130, tx);
+        let bytes = create_pus_tc(42, 130, 1, &[]).to_vec().unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+
+        let (accepted, _) = accept_tc::<NoArgs>(&tc, base, 130).unwrap();
+        let status = futures::executor::block_on(accepted.handle_async_result(
+            |mut steps| async move {
+                steps.step_success(EcssEnumU8::new(1)).unwrap();
+                Err(CommandFailure::new(7, *b"failed"))
+            },
+        ))
+        .unwrap();
+        assert_eq!(status, CommandExecutionStatus::Failed);
+
+        let reports: Vec<_> = rx.try_iter().collect();
+        let subservices: Vec<_> = reports.iter().map(|tm| tm[8]).collect();
+        assert_eq!(subservices, [1, 3, 5, 8]);
+        let code_offset = 6 + 7 + 8 + 4;
+        assert_eq!(reports[3][code_offset], 7);
+        assert_eq!(reports[3][code_offset + 1..code_offset + 7], *b"failed");
+    }
+
+    fn reports_with_ack(ack_flags: u8, succeed: bool) -> Vec<u8> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let base = PusAppBase::new(42, 0).new_reply(130, tx);
+        let bytes = create_pus_tc_with_ack(42, 130, 1, ack_flags, &[])
+            .to_vec()
+            .unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+
+        let (accepted, _) = accept_tc::<NoArgs>(&tc, base, 130).unwrap();
+        let mut started = accepted.start().unwrap();
+        started.step_success(EcssEnumU8::new(1)).unwrap();
+        if succeed {
+            started.completion_success().unwrap();
+        } else {
+            started.completion_failure(&EcssEnumU8::new(1), &[]).unwrap();
+        }
+
+        rx.try_iter().map(|tm| tm[8]).collect()
+    }
+
+    #[test]
+    fn test_full_tm_channel_is_an_error() {
+        // Only room for the acceptance report
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        let app = PusAppBase::new(42, 0);
+        let base = app.new_reply(130, tx.clone());
+        let bytes = create_pus_tc(42, 130, 1, &[]).to_vec().unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+
+        let (accepted, _) = accept_tc::<NoArgs>(&tc, base, 130).unwrap();
+        let mut executed = false;
+        let result = accepted.handle(|| {
+            executed = true;
+            true
+        });
+        assert!(matches!(result, Err(AcceptanceError::SendVerificationTmFailed)));
+        assert!(!executed);
+
+        // The TM which could not be sent did not use up a message counter value
+        assert_eq!(rx.try_recv().unwrap()[9..11], 0u16.to_be_bytes());
+        let base = app.new_reply(130, tx);
+        base.send_tm(base.create_tm(2, &[])).unwrap();
+        assert_eq!(rx.try_recv().unwrap()[9..11], 1u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_send_stored_tm() {
+        use satrs::pool::{PoolProvider, StaticMemoryPool, StaticPoolConfig};
+        use satrs::spacepackets::ecss::tm::PusTmReader;
+        use std::sync::RwLock;
+
+        let pool = Arc::new(RwLock::new(StaticMemoryPool::new(StaticPoolConfig::new(
+            vec![(2, 64)],
+            false,
+        ))));
+        let mut app = PusAppBase::new(42, 0);
+        app.tm_pool = Some(pool.clone());
+        app.time_ref_status.store(0x5, Ordering::Relaxed);
+        for _ in 0..5 {
+            app.msg_counters.lock().unwrap().next(130, 2, 0);
+        }
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let base = app.new_reply(130, tx);
+        let tm = base.create_tm(2, &[1, 2, 3]).to_vec().unwrap();
+        let addr = pool.write().unwrap().add(&tm).unwrap();
+
+        base.send_stored_tm(addr).unwrap();
+        assert!(!pool.read().unwrap().has_element_at(&addr).unwrap());
+
+        // The message counter and time reference status are set and the CRC updated
+        let sent = rx.try_recv().unwrap();
+        let (reader, _) = PusTmReader::new(&sent, 8).unwrap();
+        assert_eq!(sent[9..11], 5u16.to_be_bytes());
+        assert_eq!(sent[6], 0x25);
+        assert_eq!(reader.source_data(), [1, 2, 3]);
+
+        assert!(base.send_stored_tm(addr).is_err());
+
+        // A full channel leaves the packet in the pool
+        let addr = pool.write().unwrap().add(&tm).unwrap();
+        let (full_tx, _full_rx) = crossbeam_channel::bounded(0);
+        assert!(app.new_reply(130, full_tx).send_stored_tm(addr).is_err());
+        assert!(pool.read().unwrap().has_element_at(&addr).unwrap());
+    }
+
+    #[test]
+    fn test_reports_are_addressed_to_tc_source() {
+        use satrs::spacepackets::ecss::tc::{PusTcCreator, PusTcSecondaryHeader};
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let app = PusAppBase::new(42, 0);
+        app.time_ref_status.store(0b0101, Ordering::Relaxed);
+        let base = app.new_reply(130, tx);
+        let bytes = PusTcCreator::new(
+            SpHeader::new_for_unseg_tc(42, 0, 0),
+            PusTcSecondaryHeader::new(130, 1, ACK_ALL, 0x0ABC),
+            &[],
+            true,
+        )
+        .to_vec()
+        .unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+
+        let (mut accepted, _) = accept_tc::<NoArgs>(&tc, base, 130).unwrap();
+        assert_eq!(accepted.source_id(), 0x0ABC);
+        accepted
+            .handle_with_tm(|| {
+                Ok::<_, CommandFailure>(SubserviceTmData {
+                    subservice: 2,
+                    data: vec![],
+                })
+            })
+            .unwrap();
+
+        // Verification reports and the response TM
+        let tms: Vec<_> = rx.try_iter().collect();
+        assert_eq!(tms.len(), 4);
+        for tm in tms {
+            assert_eq!(tm[6] & 0x0F, 0b0101);
+            assert_eq!(tm[11..13], 0x0ABCu16.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_msg_counter_policies() {
+        let counters_with = |policy| {
+            let (tx, rx) = crossbeam_channel::unbounded();
+            let app = PusAppBase::new(42, 0);
+            app.set_msg_counter_policy(policy);
+            let mut base = app.new_reply(130, tx);
+            for (subservice, dest_id) in [(2, 1), (2, 2), (3, 1), (2, 1)] {
+                base.dest_id = dest_id;
+                base.send_tm(base.create_tm(subservice, &[])).unwrap();
+            }
+            rx.try_iter()
+                .map(|tm| u16::from_be_bytes([tm[9], tm[10]]))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(counters_with(MsgCounterPolicy::Global), [0, 1, 2, 3]);
+        assert_eq!(counters_with(MsgCounterPolicy::PerService), [0, 1, 0, 2]);
+        assert_eq!(
+            counters_with(MsgCounterPolicy::PerServiceAndDestination),
+            [0, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_ack_flags() {
+        assert_eq!(reports_with_ack(ACK_ALL, true), [1, 3, 5, 7]);
+        assert_eq!(reports_with_ack(ACK_COMPLETION, true), [7]);
+        assert_eq!(reports_with_ack(ACK_ACCEPTANCE | ACK_PROGRESS, true), [1, 5]);
+        // Failures are reported even if no report was requested
+        assert_eq!(reports_with_ack(0, false), [8]);
+    }
+}
@@ -1,13 +1,18 @@
-use satrs::spacepackets::{ecss::tc::{PusTcCreator, PusTcSecondaryHeader}, PacketId, PacketSequenceCtrl, PacketType, SequenceFlags, SpHeader};
+use satrs::spacepackets::{ecss::tc::{PusTcCreator, PusTcSecondaryHeader, ACK_ALL}, PacketId, PacketSequenceCtrl, PacketType, SequenceFlags, SpHeader};
 
 pub fn create_pus_tc<'a>(apid: u16, service: u8, subservice: u8, data: &'a [u8]) -> PusTcCreator<'a> {
+    create_pus_tc_with_ack(apid, service, subservice, ACK_ALL, data)
+}
+
+/// Creates a TC which only requests the success reports selected by `ack_flags`.
+pub fn create_pus_tc_with_ack<'a>(apid: u16, service: u8, subservice: u8, ack_flags: u8, data: &'a [u8]) -> PusTcCreator<'a> {
     PusTcCreator::new(
         SpHeader::new(
             PacketId::new(PacketType::Tc, true, apid),
             PacketSequenceCtrl::new(SequenceFlags::Unsegmented, 0),
             0,
         ),
-        PusTcSecondaryHeader::new(service, subservice, 0xff, 0),
+        PusTcSecondaryHeader::new(service, subservice, ack_flags, 0),
         &data, 
         true,
     )
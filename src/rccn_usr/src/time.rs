@@ -4,15 +4,18 @@
 
 use satrs::spacepackets::time::{cuc::{CucTime, FractionalResolution}, TimeWriter};
 
+/// Length of the TM timestamps: 1 byte pfield, 4 bytes coarse, 3 bytes fine time
+pub const TIMESTAMP_LEN: usize = 8;
+
 #[derive(Clone)]
 pub struct TimestampHelper {
-    timestamp: [u8; 8], // 1 byte pfield, 4 bytes coarse, 3 bytes fine time
+    timestamp: [u8; TIMESTAMP_LEN],
 }
 
 impl TimestampHelper {
     pub fn new() -> Self {
         Self {
-            timestamp: [0u8; 8]
+            timestamp: [0u8; TIMESTAMP_LEN]
         }
     }
 
@@ -1,7 +1,7 @@
 use super::{
     ros2::{Ros2ReaderConfig, Ros2TransportError, Ros2TransportHandler, SharedNode},
     udp::UdpTransportHandler,
-    RxTransport, TransportHandler, TransportResult, TxTransport,
+    RxTransport, StopSignal, TransportHandler, TransportResult, TxTransport,
 };
 use crate::{
     config::VirtualChannel,
@@ -24,6 +24,34 @@ pub enum TransportManagerError {
     InvalidConfig(String),
 }
 
+/// Transports of a single VC, see [`TransportManager::start_virtual_channel`].
+///
+/// The transports are stopped when this is dropped.
+pub struct VirtualChannelTransports {
+    /// Data sent here goes out on the TX transport of the VC
+    pub tx: Option<Sender<Vec<u8>>>,
+    /// Data received on the RX transport of the VC
+    pub rx: Option<Receiver<Vec<u8>>>,
+    stop: StopSignal,
+    handles: Vec<JoinHandle<TransportResult>>,
+}
+
+impl VirtualChannelTransports {
+    /// Stops the transports and waits until their sockets and subscriptions are released.
+    pub fn stop(mut self) {
+        self.stop.stop();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for VirtualChannelTransports {
+    fn drop(&mut self) {
+        self.stop.stop();
+    }
+}
+
 pub struct TransportManager {
     udp_handler: UdpTransportHandler,
     ros2_handler: Ros2TransportHandler,
@@ -51,6 +79,33 @@ impl TransportManager {
         })
     }
 
+    /// Starts the transports of a single VC on their own, while other transports on `node` may already be running.
+    ///
+    /// Unlike VCs added with `add_virtual_channel`, these transports can be stopped again,
+    /// so VCs can be added, removed and changed at runtime. The ROS2 node is not spun here,
+    /// this is left to a `TransportManager` running on the same node.
+    pub fn start_virtual_channel(
+        node: SharedNode,
+        vc: &VirtualChannel,
+    ) -> Result<VirtualChannelTransports, TransportManagerError> {
+        let mut manager = Self::new_with_ros2_node(node)?;
+        manager.ros2_handler.disable_spinner();
+
+        let stop = StopSignal::new();
+        manager.udp_handler.set_stop_signal(stop.clone());
+        manager.ros2_handler.set_stop_signal(stop.clone());
+
+        manager.add_virtual_channel(vc)?;
+        let ((mut vc_tx_map, mut vc_rx_map), handles) = manager.run();
+
+        Ok(VirtualChannelTransports {
+            tx: vc_tx_map.remove(&vc.id),
+            rx: vc_rx_map.remove(&vc.id),
+            stop,
+            handles,
+        })
+    }
+
     pub fn add_virtual_channel(
         &mut self,
         vc: &VirtualChannel,
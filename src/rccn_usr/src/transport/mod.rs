@@ -5,11 +5,18 @@ pub mod config;
 
 use thiserror::Error;
 pub use udp::*;
-pub use manager::TransportManager;
+pub use manager::{TransportManager, VirtualChannelTransports};
 pub use config::{TxTransport, RxTransport};
 
 use crossbeam_channel::{SendError, Sender, Receiver};
-use std::io;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 #[allow(dead_code)] // Inner values not read currently
 #[derive(Error, Debug)]
@@ -35,11 +42,34 @@ pub type TransportResult = Result<(), TransportError>;
 
 pub const TRANSPORT_BUFFER_SIZE: usize = 8096;
 
+/// How often running transports check whether they should stop
+pub const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tells the transports of a handler to shut down, shared between the handler and its owner
+#[derive(Clone, Debug, Default)]
+pub struct StopSignal(Arc<AtomicBool>);
+
+impl StopSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 pub trait TransportHandler {
     type WriterConfig;
     type ReaderConfig;
     
     fn add_transport_writer(&mut self, rx: Receiver<Vec<u8>>, config: Self::WriterConfig);
     fn add_transport_reader(&mut self, tx: Sender<Vec<u8>>, config: Self::ReaderConfig);
+    /// Makes `run()` return once `stop` is signalled, instead of running forever
+    fn set_stop_signal(&mut self, stop: StopSignal);
     fn run(self) -> TransportResult;
 }
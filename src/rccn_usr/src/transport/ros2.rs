@@ -10,7 +10,10 @@ use futures::{executor::LocalPool, task::SpawnExt, Stream};
 use r2r::{rccn_usr_msgs::msg::RawBytes, QosProfile};
 use thiserror::Error;
 
-use super::{TransportHandler, TransportReader, TransportResult, TransportWriter};
+use super::{
+    StopSignal, TransportHandler, TransportReader, TransportResult, TransportWriter,
+    STOP_POLL_INTERVAL,
+};
 
 #[allow(dead_code)] // Inner value is not read
 #[derive(Error, Debug)]
@@ -34,6 +37,8 @@ pub struct Ros2TransportHandler {
     node: SharedNode,
     publishers: Vec<TransportWriter<String>>,
     readers: Vec<TransportReader<Ros2ReaderConfig>>,
+    stop: StopSignal,
+    spin: bool,
 }
 
 pub fn new_shared_ros2_node(node_name: &str, namespace: &str) -> Result<SharedNode, r2r::Error> {
@@ -52,6 +57,11 @@ impl Ros2TransportHandler {
         Self::new_internal(Some(node), None)
     }
 
+    /// Don't spin the node in `run()`, because another handler on the same node already does.
+    pub fn disable_spinner(&mut self) {
+        self.spin = false;
+    }
+
     fn new_internal(
         node: Option<SharedNode>,
         node_name: Option<&str>,
@@ -68,6 +78,8 @@ impl Ros2TransportHandler {
             node,
             publishers: Vec::new(),
             readers: Vec::new(),
+            stop: StopSignal::new(),
+            spin: true,
         })
     }
 }
@@ -88,63 +100,84 @@ impl TransportHandler for Ros2TransportHandler {
         self.readers.push(TransportReader { tx, conf });
     }
 
-    fn run(self) -> TransportResult {
-        let node_clone = self.node.clone();
-        let _readers_handle = thread::spawn(move || run_ros2_readers(node_clone, &self.readers));
+    fn set_stop_signal(&mut self, stop: StopSignal) {
+        self.stop = stop;
+    }
 
+    fn run(self) -> TransportResult {
         let node_clone = self.node.clone();
-        let _spinner_handle = thread::spawn(move || loop {
-            node_clone
-                .lock()
-                .unwrap()
-                .spin_once(Duration::from_millis(100));
-
-            // Allow other threads to grab the node mutex
-            thread::sleep(Duration::from_millis(10));
-        });
-
-        if self.publishers.len() == 0 {
-            return Ok(());
+        let stop = self.stop.clone();
+        let readers_handle =
+            thread::spawn(move || run_ros2_readers(node_clone, &self.readers, &stop));
+
+        if self.spin {
+            let node_clone = self.node.clone();
+            let stop = self.stop.clone();
+            let _spinner_handle = thread::spawn(move || {
+                while !stop.is_stopped() {
+                    node_clone
+                        .lock()
+                        .unwrap()
+                        .spin_once(Duration::from_millis(100));
+
+                    // Allow other threads to grab the node mutex
+                    thread::sleep(Duration::from_millis(10));
+                }
+            });
         }
 
-        let mut select = Select::new();
-        let mut publishers = Vec::new();
+        run_ros2_writers(&self.node, &self.publishers, &self.stop);
+
+        // Only return once all subscriptions are dropped
+        let _ = readers_handle.join();
+        Ok(())
+    }
+}
+
+fn run_ros2_writers(node: &SharedNode, writers: &[TransportWriter<String>], stop: &StopSignal) {
+    if writers.len() == 0 {
+        return;
+    }
+
+    let mut select = Select::new();
+    let mut publishers = Vec::new();
 
-        for TransportWriter { rx, conf } in self.publishers.iter() {
-            select.recv(rx);
+    for TransportWriter { rx, conf } in writers.iter() {
+        select.recv(rx);
 
-            let publisher = self
-                .node
-                .lock()
-                .unwrap()
-                .create_publisher::<RawBytes>(conf, QosProfile::default())
-                .unwrap();
+        let publisher = node
+            .lock()
+            .unwrap()
+            .create_publisher::<RawBytes>(conf, QosProfile::default())
+            .unwrap();
 
-            publishers.push(publisher);
-        }
+        publishers.push(publisher);
+    }
 
-        loop {
-            let op = select.select();
-            let index = op.index();
-
-            let TransportWriter { rx, conf: _ } = &self.publishers[index];
-            let publisher = &publishers[index];
-            match op.recv(rx) {
-                Ok(data) => {
-                    println!("Got data on channel {}, publishing to topic.", index);
-
-                    let mut msg = RawBytes::default();
-                    msg.data = data;
-                    match publisher.publish(&msg) {
-                        Ok(()) => {
-                            println!("Published successfully.")
-                        }
-                        Err(e) => {
-                            println!("Error publishing data to topic: {:?}", e);
-                        }
-                    }
+    let mut open_channels = writers.len();
+    while open_channels > 0 && !stop.is_stopped() {
+        let Ok(op) = select.select_timeout(STOP_POLL_INTERVAL) else {
+            continue;
+        };
+        let index = op.index();
+
+        let TransportWriter { rx, conf: topic } = &writers[index];
+        let publisher = &publishers[index];
+        match op.recv(rx) {
+            Ok(data) => {
+                log::trace!("Got data on channel {}, publishing to {topic}.", index);
+
+                let mut msg = RawBytes::default();
+                msg.data = data;
+                if let Err(e) = publisher.publish(&msg) {
+                    log::error!("Error publishing data to {topic}: {e:?}");
                 }
-                Err(_) => todo!(),
+            }
+            Err(_) => {
+                // All senders are gone, keep serving the other channels
+                log::debug!("Channel for topic {topic} disconnected, no longer publishing.");
+                select.remove(index);
+                open_channels -= 1;
             }
         }
     }
@@ -154,24 +187,39 @@ async fn handle_ros2_topic_subscription(
     topic: String,
     mut subscription: impl Stream<Item = RawBytes> + Unpin,
     tx: Sender<Vec<u8>>,
+    stop: StopSignal,
 ) {
-    println!("Subscribed to {topic}.");
+    log::debug!("Subscribed to {topic}.");
 
     loop {
-        match subscription.next().await {
-            Some(msg) => {
-                println!("Received message on topic {topic}.");
+        let next = async_std::future::timeout(STOP_POLL_INTERVAL, subscription.next()).await;
+        if stop.is_stopped() {
+            log::debug!("Unsubscribing from {topic}.");
+            break;
+        }
+
+        match next {
+            Err(_) => continue,
+            Ok(Some(msg)) => {
+                log::trace!("Received message on topic {topic}.");
                 if let Err(e) = tx.send(msg.data) {
-                    println!("Error sending message to transmitter, exiting. {e:?}");
+                    log::error!("Error sending message to transmitter, exiting. {e:?}");
                     break;
                 }
             }
-            None => todo!(),
+            Ok(None) => {
+                log::debug!("Subscription to {topic} ended.");
+                break;
+            }
         }
     }
 }
 
-fn run_ros2_readers(node: Arc<Mutex<r2r::Node>>, readers: &Vec<TransportReader<Ros2ReaderConfig>>) {
+fn run_ros2_readers(
+    node: Arc<Mutex<r2r::Node>>,
+    readers: &Vec<TransportReader<Ros2ReaderConfig>>,
+    stop: &StopSignal,
+) {
     if readers.len() == 0 {
         return;
     }
@@ -193,7 +241,12 @@ fn run_ros2_readers(node: Arc<Mutex<r2r::Node>>, readers: &Vec<TransportReader<R
 
                 // TODO keep track of whether subscriptions quit
                 spawner
-                    .spawn(handle_ros2_topic_subscription(topic, subscription, tx))
+                    .spawn(handle_ros2_topic_subscription(
+                        topic,
+                        subscription,
+                        tx,
+                        stop.clone(),
+                    ))
                     .unwrap();
             }
             Ros2ReaderConfig::ActionServer(_) => todo!(),
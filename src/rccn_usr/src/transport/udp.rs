@@ -6,7 +6,8 @@ use std::{
 };
 
 use super::{
-    TransportError, TransportReader, TransportResult, TransportWriter, TRANSPORT_BUFFER_SIZE,
+    StopSignal, TransportError, TransportReader, TransportResult, TransportWriter,
+    STOP_POLL_INTERVAL, TRANSPORT_BUFFER_SIZE,
 };
 
 use super::TransportHandler;
@@ -14,6 +15,7 @@ use super::TransportHandler;
 pub struct UdpTransportHandler {
     writers: Vec<TransportWriter<SocketAddr>>,
     readers: Vec<TransportReader<SocketAddr>>,
+    stop: StopSignal,
 }
 
 impl UdpTransportHandler {
@@ -21,6 +23,7 @@ impl UdpTransportHandler {
         Self {
             writers: Vec::new(),
             readers: Vec::new(),
+            stop: StopSignal::new(),
         }
     }
 }
@@ -37,50 +40,73 @@ impl TransportHandler for UdpTransportHandler {
         self.readers.push(TransportReader { tx, conf: config });
     }
 
+    fn set_stop_signal(&mut self, stop: StopSignal) {
+        self.stop = stop;
+    }
+
     fn run(self) -> TransportResult {
-        let _readers_handle = thread::spawn(move || run_udp_transport_readers(self.readers));
-        if self.writers.len() == 0 {
-            return Ok(())
-        }
+        let stop = self.stop.clone();
+        let readers_handle = thread::spawn(move || run_udp_transport_readers(self.readers, stop));
+        let result = run_udp_transport_writers(&self.writers, &self.stop);
 
-        let socket = UdpSocket::bind("0.0.0.0:0").map_err(TransportError::IO)?;
+        // Only return once all sockets are closed, so they can be bound again
+        let _ = readers_handle.join();
+        result
+    }
+}
 
-        let mut select = Select::new();
+fn run_udp_transport_writers(
+    writers: &[TransportWriter<SocketAddr>],
+    stop: &StopSignal,
+) -> TransportResult {
+    if writers.len() == 0 {
+        return Ok(())
+    }
 
-        for TransportWriter { rx, conf: _ } in self.writers.iter() {
-            select.recv(rx);
-        }
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(TransportError::IO)?;
 
-        loop {
-            let op = select.select();
-            let index = op.index();
+    let mut select = Select::new();
 
-            log::debug!("RX channel {index} became available.");
+    for TransportWriter { rx, conf: _ } in writers.iter() {
+        select.recv(rx);
+    }
 
-            let TransportWriter { rx, conf: addr } = &self.writers[index];
-            match op.recv(rx) {
-                Ok(data) => {
-                    //println!("Got data {data:?} for addr {:?}", addr);
+    let mut open_channels = writers.len();
+    while open_channels > 0 && !stop.is_stopped() {
+        let Ok(op) = select.select_timeout(STOP_POLL_INTERVAL) else {
+            continue;
+        };
+        let index = op.index();
 
-                    match socket.send_to(&data, addr) {
-                        Ok(len) => {
-                            log::debug!("Sent {len} bytes to {:?}", addr);
-                        }
-                        Err(e) => {
-                            log::error!("Error sending bytes to {:?}: {e:?}", addr);
-                        }
+        log::debug!("RX channel {index} became available.");
+
+        let TransportWriter { rx, conf: addr } = &writers[index];
+        match op.recv(rx) {
+            Ok(data) => {
+                //println!("Got data {data:?} for addr {:?}", addr);
+
+                match socket.send_to(&data, addr) {
+                    Ok(len) => {
+                        log::debug!("Sent {len} bytes to {:?}", addr);
+                    }
+                    Err(e) => {
+                        log::error!("Error sending bytes to {:?}: {e:?}", addr);
                     }
-                }
-                Err(e) => {
-                    log::error!("Got error receiving from RX channel ID {index}: {e:?}");
-                    break Ok(()); // TODO propagate error up
                 }
             }
+            Err(_) => {
+                // All senders are gone, keep serving the other channels
+                log::debug!("RX channel ID {index} disconnected.");
+                select.remove(index);
+                open_channels -= 1;
+            }
         }
     }
+
+    Ok(())
 }
 
-fn run_udp_transport_readers(readers: Vec<TransportReader<SocketAddr>>) {
+fn run_udp_transport_readers(readers: Vec<TransportReader<SocketAddr>>, stop: StopSignal) {
     if readers.len() == 0 {
         return;
     }
@@ -91,15 +117,33 @@ fn run_udp_transport_readers(readers: Vec<TransportReader<SocketAddr>>) {
     for TransportReader { tx, conf } in readers {
         let bind_addr = conf;
         let tx = tx.clone();
+        let stop = stop.clone();
 
         spawner
             .spawn(async move {
                 let mut buf = [0u8; TRANSPORT_BUFFER_SIZE];
-                let socket = async_std::net::UdpSocket::bind(bind_addr).await.unwrap();
+                let socket = match async_std::net::UdpSocket::bind(bind_addr).await {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        log::error!("Could not bind to {bind_addr:?}: {e:?}");
+                        return;
+                    }
+                };
                 log::info!("Listening on {bind_addr:?}.");
 
                 loop {
-                    match socket.recv_from(&mut buf).await {
+                    let received =
+                        async_std::future::timeout(STOP_POLL_INTERVAL, socket.recv_from(&mut buf))
+                            .await;
+                    if stop.is_stopped() {
+                        log::info!("Stopped listening on {bind_addr:?}.");
+                        break;
+                    }
+
+                    let Ok(received) = received else {
+                        continue;
+                    };
+                    match received {
                         Ok((size, _addr)) => {
                             let data_vec = Vec::from(&buf[..size]);
 
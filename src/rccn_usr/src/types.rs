@@ -1,8 +1,15 @@
 use std::{collections::HashMap, sync::{Arc, Mutex}};
 
-use satrs::{pus::{EcssTmSender, EcssTmtcError, PusTmVariant}, spacepackets::ecss::WritablePusPacket, ComponentId};
-
+use crossbeam_channel::TrySendError;
+use satrs::{
+    pool::{PoolAddr, PoolError, PoolProvider, SharedStaticMemoryPool},
+    pus::{EcssTmSender, EcssTmtcError, PusTmVariant},
+    queue::GenericSendError,
+    spacepackets::ecss::{tm::PusTmZeroCopyWriter, PusPacket, WritablePusPacket},
+    ComponentId,
+};
 
+use crate::time::TIMESTAMP_LEN;
 
 pub type Sender = crossbeam_channel::Sender<Vec<u8>>;
 pub type Receiver = crossbeam_channel::Receiver<Vec<u8>>;
@@ -11,35 +18,142 @@ pub type VcId = u8;
 pub type VirtualChannelTxMap = HashMap<VcId, Sender>;
 pub type VirtualChannelRxMap = HashMap<VcId, Receiver>;
 
+/// Which TM share a message type counter
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MsgCounterPolicy {
+    /// One counter for all TM
+    #[default]
+    Global,
+    /// One counter per message type (service type and subtype)
+    PerService,
+    /// One counter per message type and destination ID, as in PUS-C
+    PerServiceAndDestination,
+}
+
+/// Message type counters of the TM of an application
+#[derive(Debug, Default)]
+pub struct MsgCounters {
+    policy: MsgCounterPolicy,
+    /// Next counter values by service type, subtype and destination ID, as far as the
+    /// policy distinguishes them
+    counters: HashMap<(u8, u8, u16), u16>,
+}
+
+impl MsgCounters {
+    pub fn new(policy: MsgCounterPolicy) -> Self {
+        Self {
+            policy,
+            counters: HashMap::new(),
+        }
+    }
+
+    pub fn policy(&self) -> MsgCounterPolicy {
+        self.policy
+    }
+
+    fn key(&self, service: u8, subservice: u8, dest_id: u16) -> (u8, u8, u16) {
+        match self.policy {
+            MsgCounterPolicy::Global => (0, 0, 0),
+            MsgCounterPolicy::PerService => (service, subservice, 0),
+            MsgCounterPolicy::PerServiceAndDestination => (service, subservice, dest_id),
+        }
+    }
+
+    /// Returns the counter of the next TM of a message type to a destination, without using it.
+    pub fn peek(&self, service: u8, subservice: u8, dest_id: u16) -> u16 {
+        let key = self.key(service, subservice, dest_id);
+        self.counters.get(&key).copied().unwrap_or_default()
+    }
+
+    /// Returns the counter of the next TM of a message type to a destination.
+    pub fn next(&mut self, service: u8, subservice: u8, dest_id: u16) -> u16 {
+        let key = self.key(service, subservice, dest_id);
+        let counter = self.counters.entry(key).or_default();
+        let value = *counter;
+        *counter = counter.wrapping_add(1);
+        value
+    }
+}
+
+pub type SharedMsgCounters = Arc<Mutex<MsgCounters>>;
+
 pub struct RccnEcssTmSender {
     pub channel: Sender,
-    pub msg_counter: Arc<Mutex<u16>>
+    pub msg_counters: SharedMsgCounters,
+    /// Pool holding the TM sent as [`PusTmVariant::InStore`]
+    pub tm_pool: Option<SharedStaticMemoryPool>,
+    /// Destination ID of the TM, the source ID of the TC they reply to
+    pub dest_id: u16,
+    /// Spacecraft time reference status (4 bits) of the TM
+    pub time_ref_status: u8,
+}
+
+impl RccnEcssTmSender {
+    fn pool(&self, addr: PoolAddr) -> Result<&SharedStaticMemoryPool, EcssTmtcError> {
+        self.tm_pool.as_ref().ok_or(EcssTmtcError::CantSendAddr(addr))
+    }
+
+    /// Copies a TM packet out of the pool, it stays there until it has been sent
+    fn read_from_pool(&self, addr: PoolAddr) -> Result<Vec<u8>, EcssTmtcError> {
+        let pool = self
+            .pool(addr)?
+            .read()
+            .map_err(|_| EcssTmtcError::Store(PoolError::LockError))?;
+        Ok(pool.read_as_vec(&addr)?)
+    }
+
+    /// Removes a sent TM packet from the pool
+    fn delete_from_pool(&self, addr: PoolAddr) -> Result<(), EcssTmtcError> {
+        let mut pool = self
+            .pool(addr)?
+            .write()
+            .map_err(|_| EcssTmtcError::Store(PoolError::LockError))?;
+        Ok(pool.delete(addr)?)
+    }
 }
 
 impl EcssTmSender for RccnEcssTmSender {
+    /// Sends the TM without blocking, a full or disconnected channel is reported as error.
     fn send_tm(&self, _sender_id: ComponentId, tm: PusTmVariant) -> Result<(), EcssTmtcError> {
-        let mut tm_creator = match tm {
-            PusTmVariant::InStore(_) => todo!(),
-            PusTmVariant::Direct(creator) => creator
-        }; 
-
-        // CCSDS seq count is updated by the comm application
-
-        // Update PUS service message counter
-        { 
-            // New block to limit the lifetime of the mutex guard
-            let mut counter = self.msg_counter.lock().unwrap();
-            tm_creator.set_msg_counter(*counter);
-            *counter += 1;
-        }
+        // CCSDS seq count is updated by the comm application, the PUS service
+        // message counter and destination ID are set here. The counters stay locked until
+        // the TM is sent, so that a TM which can't be sent does not use up a counter value.
+        let mut msg_counters = self.msg_counters.lock().unwrap();
+        let (bytes, stored_addr, service, subservice) = match tm {
+            PusTmVariant::InStore(addr) => {
+                let mut bytes = self.read_from_pool(addr)?;
+                // The status shares its byte with the PUS version, there is no writer method for it
+                if let Some(byte) = bytes.get_mut(6) {
+                    *byte = (*byte & 0xF0) | (self.time_ref_status & 0x0F);
+                }
+                let mut writer = PusTmZeroCopyWriter::new(&mut bytes, TIMESTAMP_LEN)
+                    .ok_or(EcssTmtcError::CantSendAddr(addr))?;
+                let (service, subservice) = (writer.service(), writer.subservice());
+                writer.set_msg_count(msg_counters.peek(service, subservice, self.dest_id));
+                writer.set_destination_id(self.dest_id);
+                writer.finish();
+                (bytes, Some(addr), service, subservice)
+            }
+            PusTmVariant::Direct(mut creator) => {
+                let (service, subservice) = (creator.service(), creator.subservice());
+                creator.set_msg_counter(msg_counters.peek(service, subservice, self.dest_id));
+                creator.sec_header.dest_id = self.dest_id;
+                creator.sec_header.sc_time_ref_status = self.time_ref_status & 0x0F;
+                (creator.to_vec()?, None, service, subservice)
+            }
+        };
 
-        let bytes = tm_creator.to_vec()?;
+        self.channel.try_send(bytes).map_err(|e| match e {
+            TrySendError::Full(_) => GenericSendError::QueueFull(None),
+            TrySendError::Disconnected(_) => GenericSendError::RxDisconnected,
+        })?;
+        msg_counters.next(service, subservice, self.dest_id);
+        drop(msg_counters);
 
-        match self.channel.send(bytes) {
-            Ok(()) => Ok(()),
-            Err(_) => {
-                Err(EcssTmtcError::CantSendDirectTm)
-            }
+        // A stored TM is only removed once it has been sent, so that it can be retried
+        if let Some(addr) = stored_addr {
+            self.delete_from_pool(addr)?;
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
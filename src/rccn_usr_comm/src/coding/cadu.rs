@@ -0,0 +1,170 @@
+//! Channel Access Data Units (CADUs) as specified in CCSDS 131.0-B.
+//!
+//! A CADU consists of the attached sync marker followed by the transfer frame and,
+//! if Reed-Solomon coding is enabled, the interleaved check symbols. Everything after
+//! the ASM is optionally pseudo-randomized.
+
+use super::{
+    randomizer::randomize_tm,
+    reed_solomon::{self, RS_DATA_LEN, RS_PARITY_LEN},
+    CodingError,
+};
+use crate::config::CaduConfig;
+
+/// Attached sync marker preceding every CADU
+pub const ASM: [u8; 4] = [0x1A, 0xCF, 0xFC, 0x1D];
+
+/// Interleave depths allowed by CCSDS 131.0-B
+pub const VALID_INTERLEAVE_DEPTHS: [u8; 6] = [1, 2, 3, 4, 5, 8];
+
+/// Encodes a transfer frame into a CADU.
+pub fn encode(config: &CaduConfig, frame: &[u8]) -> Result<Vec<u8>, CodingError> {
+    let mut cadu = Vec::with_capacity(ASM.len() + frame.len());
+    cadu.extend_from_slice(&ASM);
+    cadu.extend_from_slice(frame);
+
+    if let Some(rs) = &config.reed_solomon {
+        let depth = rs.interleave_depth as usize;
+        check_frame_length(frame.len(), depth)?;
+
+        let mut check_symbols = vec![0u8; RS_PARITY_LEN * depth];
+        for i in 0..depth {
+            let data: Vec<u8> = frame.iter().skip(i).step_by(depth).copied().collect();
+            let parity = reed_solomon::encode(&data)?;
+
+            for (j, symbol) in parity.iter().enumerate() {
+                check_symbols[j * depth + i] = *symbol;
+            }
+        }
+        cadu.extend_from_slice(&check_symbols);
+    }
+
+    if config.randomize {
+        randomize_tm(&mut cadu[ASM.len()..]);
+    }
+
+    Ok(cadu)
+}
+
+/// Decodes a CADU back into a transfer frame, correcting errors if Reed-Solomon coding is enabled.
+#[allow(dead_code)] // Only used for testing the downlink, the comm application does not receive CADUs
+pub fn decode(config: &CaduConfig, cadu: &[u8]) -> Result<Vec<u8>, CodingError> {
+    if cadu.len() < ASM.len() || cadu[..ASM.len()] != ASM {
+        return Err(CodingError::MissingSyncMarker);
+    }
+
+    let mut data = cadu[ASM.len()..].to_vec();
+    if config.randomize {
+        randomize_tm(&mut data);
+    }
+
+    if let Some(rs) = &config.reed_solomon {
+        let depth = rs.interleave_depth as usize;
+        let parity_len = RS_PARITY_LEN * depth;
+        if data.len() < parity_len {
+            return Err(CodingError::InvalidLength(data.len()));
+        }
+        let frame_len = data.len() - parity_len;
+        check_frame_length(frame_len, depth)?;
+
+        for i in 0..depth {
+            let positions: Vec<usize> = (i..data.len()).step_by(depth).collect();
+            let mut codeword: Vec<u8> = positions.iter().map(|&pos| data[pos]).collect();
+
+            reed_solomon::decode(&mut codeword)?;
+
+            for (&pos, symbol) in positions.iter().zip(codeword) {
+                data[pos] = symbol;
+            }
+        }
+
+        data.truncate(frame_len);
+    }
+
+    Ok(data)
+}
+
+fn check_frame_length(frame_len: usize, depth: usize) -> Result<(), CodingError> {
+    if frame_len == 0 || !frame_len.is_multiple_of(depth) || frame_len > RS_DATA_LEN * depth {
+        return Err(CodingError::InvalidLength(frame_len));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReedSolomonConfig;
+
+    fn config(randomize: bool, interleave_depth: Option<u8>) -> CaduConfig {
+        CaduConfig {
+            randomize,
+            reed_solomon: interleave_depth.map(|interleave_depth| ReedSolomonConfig { interleave_depth }),
+        }
+    }
+
+    fn test_frame(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    #[test]
+    fn test_asm_only() {
+        let frame = test_frame(100);
+        let cadu = encode(&config(false, None), &frame).unwrap();
+
+        assert_eq!(cadu[..4], ASM);
+        assert_eq!(cadu[4..], frame);
+        assert_eq!(decode(&config(false, None), &cadu).unwrap(), frame);
+    }
+
+    #[test]
+    fn test_roundtrip_all_interleave_depths() {
+        for depth in VALID_INTERLEAVE_DEPTHS {
+            let config = config(true, Some(depth));
+            let frame = test_frame(RS_DATA_LEN * depth as usize);
+
+            let cadu = encode(&config, &frame).unwrap();
+            assert_eq!(cadu.len(), ASM.len() + 255 * depth as usize);
+            assert_eq!(decode(&config, &cadu).unwrap(), frame);
+        }
+    }
+
+    #[test]
+    fn test_corrects_burst_errors() {
+        // With interleave depth 4, a burst of 64 bytes only hits 16 symbols per codeword
+        let config = config(true, Some(4));
+        let frame = test_frame(RS_DATA_LEN * 4);
+
+        let mut cadu = encode(&config, &frame).unwrap();
+        for byte in cadu[100..164].iter_mut() {
+            *byte ^= 0xFF;
+        }
+
+        assert_eq!(decode(&config, &cadu).unwrap(), frame);
+    }
+
+    #[test]
+    fn test_shortened_frames() {
+        let config = config(false, Some(2));
+        let frame = test_frame(200);
+
+        let cadu = encode(&config, &frame).unwrap();
+        assert_eq!(cadu.len(), ASM.len() + 200 + 2 * RS_PARITY_LEN);
+        assert_eq!(decode(&config, &cadu).unwrap(), frame);
+
+        // Frame length must be a multiple of the interleave depth
+        assert!(matches!(
+            encode(&config, &test_frame(201)),
+            Err(CodingError::InvalidLength(201))
+        ));
+    }
+
+    #[test]
+    fn test_missing_asm() {
+        let config = config(false, None);
+        assert!(matches!(
+            decode(&config, &[0u8; 16]),
+            Err(CodingError::MissingSyncMarker)
+        ));
+    }
+}
@@ -0,0 +1,326 @@
+//! Communications Link Transmission Units (CLTUs) as specified in CCSDS 231.0-B.
+//!
+//! A CLTU consists of a start sequence, a number of BCH(63,56) codeblocks and a tail
+//! sequence. Each codeblock carries 7 bytes of (optionally randomized) frame data,
+//! followed by 7 complemented parity bits and one filler bit.
+
+use crossbeam_channel::{Receiver, Sender};
+
+use super::randomizer::randomize_tc;
+use crate::frame_reassembler::TC_PRIMARY_HEADER_LEN;
+
+pub const START_SEQUENCE: [u8; 2] = [0xEB, 0x90];
+pub const TAIL_SEQUENCE: [u8; CODEBLOCK_LEN] = [0xC5, 0xC5, 0xC5, 0xC5, 0xC5, 0xC5, 0xC5, 0x79];
+
+const CODEBLOCK_LEN: usize = 8;
+const INFO_LEN: usize = 7;
+const INFO_BITS: usize = INFO_LEN * 8;
+const FILL_BYTE: u8 = 0x55;
+
+/// Generator polynomial g(x) = x^7 + x^6 + x^2 + 1, without the x^7 term
+const BCH_GENERATOR: u8 = 0b100_0101;
+
+/// Upper bound for the data in one CLTU: the maximum TC frame length plus fill
+const MAX_CLTU_DATA_LEN: usize = 1024 + INFO_LEN;
+
+/// Computes the 7 BCH parity bits (not complemented) of a codeblock's information bytes.
+fn bch_parity(info: &[u8]) -> u8 {
+    let mut register = 0u8;
+    for byte in info {
+        for bit in (0..8).rev() {
+            let feedback = ((register >> 6) ^ (byte >> bit)) & 1;
+            register = (register << 1) & 0x7F;
+            if feedback != 0 {
+                register ^= BCH_GENERATOR;
+            }
+        }
+    }
+    register
+}
+
+/// Returns the syndrome of a single bit error at `position`, where positions
+/// 0 to 55 are the information bits (MSB first) and 56 to 62 the parity bits.
+fn single_error_syndrome(position: usize) -> u8 {
+    if position < INFO_BITS {
+        let mut info = [0u8; INFO_LEN];
+        info[position / 8] = 0x80 >> (position % 8);
+        bch_parity(&info)
+    } else {
+        0x40 >> (position - INFO_BITS)
+    }
+}
+
+/// Decodes a single codeblock, correcting up to one bit error.
+///
+/// Returns `None` if the codeblock can not be corrected, which also marks the end of
+/// the CLTU since the tail sequence is constructed to be uncorrectable.
+fn decode_codeblock(codeblock: &[u8]) -> Option<[u8; INFO_LEN]> {
+    let mut info = [0u8; INFO_LEN];
+    info.copy_from_slice(&codeblock[..INFO_LEN]);
+
+    let received_parity = !(codeblock[INFO_LEN] >> 1) & 0x7F;
+    let syndrome = bch_parity(&info) ^ received_parity;
+    if syndrome == 0 {
+        return Some(info);
+    }
+
+    let position = (0..INFO_BITS + 7).find(|&pos| single_error_syndrome(pos) == syndrome)?;
+    if position < INFO_BITS {
+        info[position / 8] ^= 0x80 >> (position % 8);
+    }
+    Some(info)
+}
+
+/// Encodes a transfer frame into a CLTU.
+#[allow(dead_code)] // Only used for testing the uplink, the comm application does not send CLTUs
+pub fn encode(frame: &[u8], randomize: bool) -> Vec<u8> {
+    let mut data = frame.to_vec();
+    if randomize {
+        randomize_tc(&mut data);
+    }
+    let padded_len = data.len().div_ceil(INFO_LEN) * INFO_LEN;
+    data.resize(padded_len, FILL_BYTE);
+
+    let mut cltu = START_SEQUENCE.to_vec();
+    for info in data.chunks(INFO_LEN) {
+        cltu.extend_from_slice(info);
+        cltu.push(!bch_parity(info) << 1);
+    }
+    cltu.extend_from_slice(&TAIL_SEQUENCE);
+    cltu
+}
+
+enum DecoderState {
+    /// Looking for the start sequence
+    Searching,
+    /// Collecting the data of the codeblocks
+    Decoding(Vec<u8>),
+}
+
+/// Cuts the decoded data of a CLTU to the TC frame it carries, removing the fill bytes
+/// of the last codeblock. Returns `None` if the data does not hold a complete frame.
+fn frame_from_cltu_data(mut data: Vec<u8>) -> Option<Vec<u8>> {
+    let header = data.get(..TC_PRIMARY_HEADER_LEN)?;
+    let frame_len = (u16::from_be_bytes([header[2], header[3]]) & 0x03FF) as usize + 1;
+    if frame_len > data.len() {
+        return None;
+    }
+    data.truncate(frame_len);
+    Some(data)
+}
+
+/// Extracts transfer frames from a byte stream of CLTUs.
+///
+/// Each CLTU is expected to carry a single TC frame. The fill bytes after the frame are
+/// removed, and CLTUs ending before their frame is complete are discarded.
+pub struct CltuDecoder {
+    randomized: bool,
+    buffer: Vec<u8>,
+    state: DecoderState,
+}
+
+impl CltuDecoder {
+    pub fn new(randomized: bool) -> Self {
+        Self {
+            randomized,
+            buffer: Vec::new(),
+            state: DecoderState::Searching,
+        }
+    }
+
+    /// Feeds received bytes into the decoder and returns the data of all CLTUs completed by them.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut frames = Vec::new();
+
+        loop {
+            match &mut self.state {
+                DecoderState::Searching => {
+                    let start = self
+                        .buffer
+                        .windows(START_SEQUENCE.len())
+                        .position(|w| w == START_SEQUENCE);
+
+                    match start {
+                        Some(pos) => {
+                            self.buffer.drain(..pos + START_SEQUENCE.len());
+                            self.state = DecoderState::Decoding(Vec::new());
+                        }
+                        None => {
+                            // Keep the last byte, it could be the beginning of a start sequence
+                            let keep_from = self.buffer.len().saturating_sub(START_SEQUENCE.len() - 1);
+                            self.buffer.drain(..keep_from);
+                            return frames;
+                        }
+                    }
+                }
+                DecoderState::Decoding(data) => {
+                    if self.buffer.len() < CODEBLOCK_LEN {
+                        return frames;
+                    }
+
+                    let decoded = decode_codeblock(&self.buffer[..CODEBLOCK_LEN]);
+                    self.buffer.drain(..CODEBLOCK_LEN);
+
+                    match decoded {
+                        Some(info) if data.len() < MAX_CLTU_DATA_LEN => {
+                            data.extend_from_slice(&info);
+                        }
+                        Some(_) => {
+                            log::warn!("CLTU exceeds maximum length, discarding it.");
+                            self.state = DecoderState::Searching;
+                        }
+                        None => {
+                            // Tail sequence or uncorrectable codeblock: the CLTU ends here
+                            let mut data = std::mem::take(data);
+                            self.state = DecoderState::Searching;
+
+                            if data.is_empty() {
+                                continue;
+                            }
+                            if self.randomized {
+                                randomize_tc(&mut data);
+                            }
+                            let data_len = data.len();
+                            match frame_from_cltu_data(data) {
+                                Some(frame) => frames.push(frame),
+                                None => log::warn!(
+                                    "CLTU with {} bytes of data does not contain a complete frame, discarding it.",
+                                    data_len
+                                ),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decodes CLTUs received on `bytes_rx` and forwards the contained frame data to `frames_tx`.
+    pub fn run(mut self, bytes_rx: Receiver<Vec<u8>>, frames_tx: Sender<Vec<u8>>) {
+        while let Ok(bytes) = bytes_rx.recv() {
+            for frame in self.push(&bytes) {
+                if frames_tx.send(frame).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_reassembler::FrameReassembler;
+
+    /// TC frame of `len` bytes with a matching frame length field
+    fn test_frame(len: usize) -> Vec<u8> {
+        let mut frame: Vec<u8> = (0..len).map(|i| (i * 13 + 1) as u8).collect();
+        let length_field = (len - 1) as u16;
+        frame[0] = 0x00;
+        frame[2] = (frame[2] & 0xFC) | (length_field >> 8) as u8;
+        frame[3] = length_field as u8;
+        frame
+    }
+
+    #[test]
+    fn test_single_error_syndromes_are_unique() {
+        let mut syndromes: Vec<u8> = (0..INFO_BITS + 7).map(single_error_syndrome).collect();
+        syndromes.sort();
+        syndromes.dedup();
+        assert_eq!(syndromes.len(), INFO_BITS + 7);
+        assert!(!syndromes.contains(&0));
+    }
+
+    #[test]
+    fn test_tail_sequence_is_uncorrectable() {
+        assert!(decode_codeblock(&TAIL_SEQUENCE).is_none());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let frame = test_frame(21);
+        let cltu = encode(&frame, false);
+        assert_eq!(cltu.len(), 2 + 3 * CODEBLOCK_LEN + 8);
+
+        let mut decoder = CltuDecoder::new(false);
+        assert_eq!(decoder.push(&cltu), vec![frame]);
+    }
+
+    #[test]
+    fn test_roundtrip_randomized() {
+        let frame = test_frame(30);
+        let cltu = encode(&frame, true);
+
+        let mut decoder = CltuDecoder::new(true);
+        assert_eq!(decoder.push(&cltu), vec![frame]);
+    }
+
+    #[test]
+    fn test_corrects_single_bit_errors() {
+        let frame = test_frame(14);
+        let cltu = encode(&frame, false);
+
+        // Flip every bit of the first codeblock (except the filler bit) one at a time
+        for bit in 0..63 {
+            let mut corrupted = cltu.clone();
+            corrupted[2 + bit / 8] ^= 0x80 >> (bit % 8);
+
+            let mut decoder = CltuDecoder::new(false);
+            assert_eq!(decoder.push(&corrupted), vec![frame.clone()], "bit {bit}");
+        }
+    }
+
+    #[test]
+    fn test_double_bit_error_ends_cltu() {
+        let frame = test_frame(21);
+        let mut cltu = encode(&frame, false);
+        // Two errors in the second codeblock
+        cltu[2 + CODEBLOCK_LEN] ^= 0x81;
+
+        // The CLTU ends after the first codeblock, before the frame is complete
+        let mut decoder = CltuDecoder::new(false);
+        assert!(decoder.push(&cltu).is_empty());
+
+        // The decoder is ready for the next CLTU
+        assert_eq!(decoder.push(&encode(&frame, false)), vec![frame]);
+    }
+
+    #[test]
+    fn test_fill_bytes_are_removed_between_frames() {
+        let frames = [test_frame(10), test_frame(19)];
+        let mut stream = encode(&frames[0], true);
+        stream.extend(encode(&frames[1], true));
+
+        let mut decoder = CltuDecoder::new(true);
+        let decoded = decoder.push(&stream);
+        assert_eq!(decoded, frames);
+
+        // The frames are passed on as a byte stream, fill bytes would end up in the next frame
+        let mut reassembler = FrameReassembler::new(false);
+        for data in decoded {
+            reassembler.push(&data);
+        }
+        assert_eq!(reassembler.next_frame(), Some(frames[0].clone()));
+        assert_eq!(reassembler.next_frame(), Some(frames[1].clone()));
+        assert_eq!(reassembler.discarded_bytes(), 0);
+    }
+
+    #[test]
+    fn test_stream_with_garbage_and_split_reads() {
+        let frames = [test_frame(10), test_frame(40)];
+
+        let mut stream = vec![0x00, 0x12, 0xEB];
+        stream.extend(encode(&frames[0], true));
+        stream.extend([0x55, 0x55, 0xAA]);
+        stream.extend(encode(&frames[1], true));
+
+        let mut decoder = CltuDecoder::new(true);
+        let mut decoded = Vec::new();
+        for chunk in stream.chunks(5) {
+            decoded.extend(decoder.push(chunk));
+        }
+
+        assert_eq!(decoded, frames);
+    }
+}
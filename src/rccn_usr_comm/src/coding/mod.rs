@@ -0,0 +1,18 @@
+//! Channel coding for the physical link, below the transfer frame layer.
+
+pub mod cadu;
+pub mod cltu;
+pub mod randomizer;
+pub mod reed_solomon;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CodingError {
+    #[error("Invalid data length {0}")]
+    InvalidLength(usize),
+    #[error("Attached sync marker not found")]
+    MissingSyncMarker,
+    #[error("Too many errors to correct")]
+    Uncorrectable,
+}
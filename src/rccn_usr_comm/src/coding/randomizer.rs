@@ -0,0 +1,61 @@
+//! CCSDS pseudo-randomizers for the downlink (CCSDS 131.0-B) and uplink (CCSDS 231.0-B).
+//!
+//! Both randomizers XOR the data with the output of an 8-bit LFSR which is reset to
+//! all ones at the start of every CADU or CLTU. Applying a randomizer twice restores
+//! the original data.
+
+/// Feedback taps of the TM randomizer, h(x) = x^8 + x^7 + x^5 + x^3 + 1
+const TM_TAPS: u8 = 0b1001_0101;
+/// Feedback taps of the TC randomizer, h(x) = x^8 + x^6 + x^4 + x^3 + x^2 + x + 1
+const TC_TAPS: u8 = 0b1111_1010;
+
+/// Applies the TM pseudo-randomizer to `data` in place.
+pub fn randomize_tm(data: &mut [u8]) {
+    apply_lfsr(TM_TAPS, data);
+}
+
+/// Applies the TC pseudo-randomizer to `data` in place.
+pub fn randomize_tc(data: &mut [u8]) {
+    apply_lfsr(TC_TAPS, data);
+}
+
+fn apply_lfsr(taps: u8, data: &mut [u8]) {
+    let mut state: u8 = 0xFF;
+
+    for byte in data.iter_mut() {
+        let mut sequence = 0u8;
+        for _ in 0..8 {
+            sequence = (sequence << 1) | (state >> 7);
+            let feedback = ((state & taps).count_ones() & 1) as u8;
+            state = (state << 1) | feedback;
+        }
+        *byte ^= sequence;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tm_sequence() {
+        let mut data = [0u8; 10];
+        randomize_tm(&mut data);
+        assert_eq!(
+            data,
+            [0xFF, 0x48, 0x0E, 0xC0, 0x9A, 0x0D, 0x70, 0xBC, 0x8E, 0x2C]
+        );
+
+        // The sequence repeats after 255 bytes
+        let mut data = [0u8; 257];
+        randomize_tm(&mut data);
+        assert_eq!(data[255..], data[..2]);
+    }
+
+    #[test]
+    fn test_tc_sequence() {
+        let mut data = [0u8; 5];
+        randomize_tc(&mut data);
+        assert_eq!(data, [0xFF, 0x39, 0x9E, 0x5A, 0x68]);
+    }
+}
@@ -0,0 +1,359 @@
+//! CCSDS Reed-Solomon (255,223) code as specified in CCSDS 131.0-B.
+//!
+//! Symbols on the link are represented in the Berlekamp dual basis. Internally, all
+//! arithmetic is done in the conventional basis of GF(2^8) with field polynomial
+//! x^8 + x^7 + x^2 + x + 1. Shortened codewords (less than 223 data symbols) are
+//! supported by treating the missing leading symbols as zero (virtual fill).
+
+use std::sync::OnceLock;
+
+use super::CodingError;
+
+/// Number of data symbols in a full codeword
+pub const RS_DATA_LEN: usize = 223;
+/// Number of check symbols in a codeword
+pub const RS_PARITY_LEN: usize = 32;
+
+const NN: usize = 255;
+const GF_POLY: u16 = 0x187;
+/// First consecutive root of the generator polynomial, in powers of `PRIM`
+const FCR: usize = 112;
+/// The generator roots are powers of alpha^PRIM
+const PRIM: usize = 11;
+/// Marker for log(0) in the `index_of` table
+const A0: u8 = NN as u8;
+
+/// Conversion matrix from the conventional to the dual basis
+const TAL: [u8; 8] = [0x8d, 0xef, 0xec, 0x86, 0xfa, 0x99, 0xaf, 0x7b];
+
+struct Tables {
+    alpha_to: [u8; 256],
+    index_of: [u8; 256],
+    /// Generator polynomial coefficients in index form, genpoly[i] is the coefficient of x^i
+    genpoly: [u8; RS_PARITY_LEN + 1],
+    to_dual: [u8; 256],
+    from_dual: [u8; 256],
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(Tables::new)
+}
+
+fn modnn(x: usize) -> usize {
+    x % NN
+}
+
+impl Tables {
+    fn new() -> Self {
+        let mut alpha_to = [0u8; 256];
+        let mut index_of = [0u8; 256];
+
+        index_of[0] = A0;
+        alpha_to[NN] = 0;
+        let mut sr: u16 = 1;
+        for (i, alpha) in alpha_to.iter_mut().take(NN).enumerate() {
+            index_of[sr as usize] = i as u8;
+            *alpha = sr as u8;
+            sr <<= 1;
+            if sr & 0x100 != 0 {
+                sr ^= GF_POLY;
+            }
+        }
+
+        // Generator polynomial with roots alpha^(PRIM * (FCR + i)), i = 0..RS_PARITY_LEN
+        let mut genpoly = [0u8; RS_PARITY_LEN + 1];
+        genpoly[0] = 1;
+        let mut root = FCR * PRIM;
+        for i in 0..RS_PARITY_LEN {
+            genpoly[i + 1] = 1;
+            for j in (1..=i).rev() {
+                genpoly[j] = if genpoly[j] != 0 {
+                    genpoly[j - 1] ^ alpha_to[modnn(index_of[genpoly[j] as usize] as usize + root)]
+                } else {
+                    genpoly[j - 1]
+                };
+            }
+            genpoly[0] = alpha_to[modnn(index_of[genpoly[0] as usize] as usize + root)];
+            root += PRIM;
+        }
+        for coeff in genpoly.iter_mut() {
+            *coeff = index_of[*coeff as usize];
+        }
+
+        let mut to_dual = [0u8; 256];
+        let mut from_dual = [0u8; 256];
+        for (i, to_dual) in to_dual.iter_mut().enumerate() {
+            let mut dual = 0u8;
+            for j in 0..8 {
+                for k in 0..8 {
+                    if i & (1 << k) != 0 {
+                        dual ^= TAL[7 - k] & (1 << j);
+                    }
+                }
+            }
+            *to_dual = dual;
+            from_dual[dual as usize] = i as u8;
+        }
+
+        Self {
+            alpha_to,
+            index_of,
+            genpoly,
+            to_dual,
+            from_dual,
+        }
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.alpha_to[modnn(self.index_of[a as usize] as usize + self.index_of[b as usize] as usize)]
+    }
+
+    fn div(&self, a: u8, b: u8) -> u8 {
+        if a == 0 {
+            return 0;
+        }
+        self.alpha_to
+            [modnn(self.index_of[a as usize] as usize + NN - self.index_of[b as usize] as usize)]
+    }
+
+    fn pow_alpha(&self, exponent: usize) -> u8 {
+        self.alpha_to[modnn(exponent)]
+    }
+
+    /// Evaluates the polynomial `poly` (poly[i] is the coefficient of x^i) at `x`.
+    fn eval(&self, poly: &[u8], x: u8) -> u8 {
+        poly.iter()
+            .rev()
+            .fold(0, |acc, &coeff| self.mul(acc, x) ^ coeff)
+    }
+}
+
+/// Computes the check symbols for `data` (at most 223 dual basis symbols).
+pub fn encode(data: &[u8]) -> Result<[u8; RS_PARITY_LEN], CodingError> {
+    if data.len() > RS_DATA_LEN {
+        return Err(CodingError::InvalidLength(data.len()));
+    }
+
+    let t = tables();
+    let mut bb = [0u8; RS_PARITY_LEN];
+
+    for &symbol in data {
+        let feedback = t.index_of[(t.from_dual[symbol as usize] ^ bb[0]) as usize];
+        if feedback != A0 {
+            for (j, symbol) in bb.iter_mut().enumerate().skip(1) {
+                *symbol ^= t.alpha_to
+                    [modnn(feedback as usize + t.genpoly[RS_PARITY_LEN - j] as usize)];
+            }
+        }
+        bb.copy_within(1.., 0);
+        bb[RS_PARITY_LEN - 1] = if feedback != A0 {
+            t.alpha_to[modnn(feedback as usize + t.genpoly[0] as usize)]
+        } else {
+            0
+        };
+    }
+
+    for symbol in bb.iter_mut() {
+        *symbol = t.to_dual[*symbol as usize];
+    }
+    Ok(bb)
+}
+
+/// Corrects errors in `codeword` (data symbols followed by 32 check symbols) in place.
+///
+/// Returns the number of corrected symbols, or an error if the codeword has more errors
+/// than the code can correct. The codeword is left untouched in the error case.
+pub fn decode(codeword: &mut [u8]) -> Result<usize, CodingError> {
+    let n = codeword.len();
+    if n <= RS_PARITY_LEN || n > NN {
+        return Err(CodingError::InvalidLength(n));
+    }
+
+    let t = tables();
+    let received: Vec<u8> = codeword.iter().map(|&s| t.from_dual[s as usize]).collect();
+
+    // Syndromes S_k = r(alpha^(PRIM * (FCR + k))), codeword[0] is the highest order coefficient
+    let mut syndromes = [0u8; RS_PARITY_LEN];
+    for (k, syndrome) in syndromes.iter_mut().enumerate() {
+        let root = t.pow_alpha(PRIM * (FCR + k));
+        *syndrome = received.iter().fold(0, |acc, &r| t.mul(acc, root) ^ r);
+    }
+    if syndromes.iter().all(|&s| s == 0) {
+        return Ok(0);
+    }
+
+    // Berlekamp-Massey: find the error locator polynomial lambda
+    let mut lambda = vec![0u8; RS_PARITY_LEN + 1];
+    let mut prev = vec![0u8; RS_PARITY_LEN + 1];
+    lambda[0] = 1;
+    prev[0] = 1;
+    let mut errors = 0;
+    let mut shift = 1;
+    let mut prev_discrepancy = 1u8;
+
+    for r in 0..RS_PARITY_LEN {
+        let mut discrepancy = syndromes[r];
+        for i in 1..=errors {
+            discrepancy ^= t.mul(lambda[i], syndromes[r - i]);
+        }
+
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let scale = t.div(discrepancy, prev_discrepancy);
+        let previous_lambda = lambda.clone();
+        for i in shift..=RS_PARITY_LEN {
+            lambda[i] ^= t.mul(scale, prev[i - shift]);
+        }
+
+        if 2 * errors <= r {
+            errors = r + 1 - errors;
+            prev = previous_lambda;
+            prev_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+    }
+
+    if errors > RS_PARITY_LEN / 2 || lambda[errors + 1..].iter().any(|&c| c != 0) {
+        return Err(CodingError::Uncorrectable);
+    }
+    let lambda = &lambda[..=errors];
+
+    // Chien search: find the error positions among the transmitted symbols
+    let mut positions = Vec::new();
+    for i in 0..n {
+        let power = n - 1 - i;
+        let x_inv = t.pow_alpha(NN - modnn(PRIM * power));
+        if t.eval(lambda, x_inv) == 0 {
+            positions.push(i);
+        }
+    }
+    if positions.len() != errors {
+        return Err(CodingError::Uncorrectable);
+    }
+
+    // Error evaluator omega(x) = S(x) * lambda(x) mod x^32
+    let mut omega = [0u8; RS_PARITY_LEN];
+    for (i, &s) in syndromes.iter().enumerate() {
+        for (j, &l) in lambda.iter().enumerate() {
+            if i + j < RS_PARITY_LEN {
+                omega[i + j] ^= t.mul(s, l);
+            }
+        }
+    }
+
+    // Formal derivative of lambda, only the odd powers remain in GF(2^m)
+    let lambda_prime: Vec<u8> = (1..lambda.len())
+        .map(|i| if i % 2 == 1 { lambda[i] } else { 0 })
+        .collect();
+
+    // Forney algorithm: e = X^(1 - FCR) * omega(X^-1) / lambda'(X^-1)
+    let mut corrected = received;
+    for &i in &positions {
+        let power = modnn(PRIM * (n - 1 - i));
+        let x_inv = t.pow_alpha(NN - power);
+
+        let denominator = t.eval(&lambda_prime, x_inv);
+        if denominator == 0 {
+            return Err(CodingError::Uncorrectable);
+        }
+        let numerator = t.mul(
+            t.eval(&omega, x_inv),
+            t.pow_alpha(power * (NN + 1 - FCR)),
+        );
+        corrected[i] ^= t.div(numerator, denominator);
+    }
+
+    for (symbol, corrected) in codeword.iter_mut().zip(corrected) {
+        *symbol = t.to_dual[corrected as usize];
+    }
+    Ok(positions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codeword(data: &[u8]) -> Vec<u8> {
+        let mut codeword = data.to_vec();
+        codeword.extend_from_slice(&encode(data).unwrap());
+        codeword
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 + 11) as u8).collect()
+    }
+
+    #[test]
+    fn test_valid_codeword_decodes_without_corrections() {
+        let mut cw = codeword(&test_data(RS_DATA_LEN));
+        let original = cw.clone();
+        assert_eq!(decode(&mut cw).unwrap(), 0);
+        assert_eq!(cw, original);
+    }
+
+    #[test]
+    fn test_zero_data_has_zero_parity() {
+        assert_eq!(encode(&[0u8; RS_DATA_LEN]).unwrap(), [0u8; RS_PARITY_LEN]);
+    }
+
+    #[test]
+    fn test_known_answer() {
+        // Check symbols computed by plain polynomial division with the CCSDS generator
+        // polynomial and the dual basis conversion of CCSDS 131.0-B
+        let data: Vec<u8> = (0..RS_DATA_LEN).map(|i| i as u8).collect();
+        let expected = [
+            0x4f, 0xfb, 0x92, 0xdd, 0x55, 0x7e, 0xc6, 0x7f, 0x27, 0xfb, 0x89, 0x82, 0xcf, 0x58,
+            0xf8, 0xfd, 0x02, 0x8a, 0xd1, 0x17, 0xfc, 0xef, 0x6b, 0x27, 0x93, 0xd0, 0x41, 0x88,
+            0x26, 0x57, 0x86, 0x51,
+        ];
+        assert_eq!(encode(&data).unwrap(), expected);
+
+        // Shortened codeword, virtual fill with zeros
+        let expected = [
+            0x0d, 0x61, 0xc1, 0x18, 0x74, 0x3e, 0x66, 0x35, 0x89, 0xa3, 0x64, 0x58, 0x49, 0xdb,
+            0xd1, 0x80, 0xad, 0x4f, 0x2e, 0x46, 0x5f, 0x80, 0x26, 0xcc, 0x62, 0xb1, 0x3d, 0x43,
+            0xd1, 0x88, 0x40, 0xa0,
+        ];
+        assert_eq!(encode(&[0xFF; 10]).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_corrects_up_to_16_errors() {
+        for len in [RS_DATA_LEN, 100] {
+            let original = codeword(&test_data(len));
+
+            for errors in 1..=16 {
+                let mut cw = original.clone();
+                for e in 0..errors {
+                    let pos = (e * 13 + 5) % cw.len();
+                    cw[pos] ^= (e as u8).wrapping_mul(29) | 1;
+                }
+
+                assert_eq!(decode(&mut cw).unwrap(), errors);
+                assert_eq!(cw, original);
+            }
+        }
+    }
+
+    #[test]
+    fn test_too_many_errors_detected() {
+        let original = codeword(&test_data(RS_DATA_LEN));
+        let mut cw = original.clone();
+        for pos in 0..17 {
+            cw[pos * 3] ^= 0xA5;
+        }
+        let corrupted = cw.clone();
+
+        assert!(matches!(decode(&mut cw), Err(CodingError::Uncorrectable)));
+        assert_eq!(cw, corrupted);
+    }
+}
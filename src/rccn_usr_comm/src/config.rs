@@ -1,21 +1,53 @@
 use rccn_usr::{
     config::VirtualChannel,
     transport::{RxTransport, TxTransport},
+    types::VcId,
 };
 use serde::{Deserialize, Serialize};
+
+use crate::coding::{cadu::VALID_INTERLEAVE_DEPTHS, reed_solomon::RS_DATA_LEN};
+use crate::farm::FarmConfig;
+use crate::link_stats::LinkStatusConfig;
+use crate::packet_store::PacketStoresConfig;
+use crate::routing::{PacketFilter, RouteConfig};
+use crate::sdls::{SaDirection, SdlsConfig};
+use crate::sequence_counter::PacketCrc;
 use std::{io, path::{Path, PathBuf}};
 use thiserror::Error;
 
+/// VC ID reserved for "Only Idle Data" (OID) frames
+pub const IDLE_VC_ID: VcId = 63;
+
+/// Largest data zone of a downlink frame, the size of the frame data buffer
+pub const MAX_FRAME_DATA_LEN: usize = 4096;
+/// USLP primary header without the VC frame count
+const USLP_PRIMARY_HEADER_LEN: usize = 7;
+/// Transfer frame data field header with first header pointer, used for packets
+pub const TFDF_HEADER_LEN: usize = 3;
+/// Transfer frame data field header without first header pointer, used for idle data
+pub const IDLE_TFDF_HEADER_LEN: usize = 1;
+const OCF_LEN: usize = 4;
+const FECF_LEN: usize = 2;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
     #[error("YAML parsing error: {0}")]
     Yaml(#[from] serde_yaml::Error),
-    #[error("Config validation error: {0}")]
-    Validation(String),
+    #[error("Invalid config value `{field}`: {message}")]
+    Validation { field: String, message: String },
     #[error("Configuration file not found")]
-    ConfigNotFound
+    ConfigNotFound,
+    #[error("Configuration file {0} does not exist")]
+    ConfigFileMissing(PathBuf),
+}
+
+fn invalid(field: impl Into<String>, message: impl Into<String>) -> ConfigError {
+    ConfigError::Validation {
+        field: field.into(),
+        message: message.into(),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Copy)]
@@ -29,12 +61,100 @@ pub enum FrameKind {
 pub struct FrameConfig {
     pub frame_kind: FrameKind,
     pub transport: RxTransport,
+    /// Frames are received wrapped in CLTUs instead of as bare frames
+    pub cltu: Option<CltuConfig>,
+    /// Incoming frames end with a CRC-16 frame error control field
+    #[serde(default)]
+    pub fecf: bool,
+    /// Enables COP-1 frame acceptance checks (FARM-1) for every VC
+    pub farm: Option<FarmConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CltuConfig {
+    /// Frames inside the CLTUs are pseudo-randomized
+    #[serde(default = "default_randomize")]
+    pub randomize: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct FrameOutConfig {
     pub frame_kind: FrameKind,
     pub transport: TxTransport,
+    /// Transfer frame version number written into the frame header
+    #[serde(default = "default_tfvn")]
+    pub tfvn: u8,
+    /// Value of the frame length field in the frame header
+    #[serde(default = "default_frame_length")]
+    pub frame_length: u16,
+    /// Append a frame error control field (CRC-16) to every frame
+    #[serde(default)]
+    pub fecf: bool,
+    /// Whether frames carry an operational control field
+    #[serde(default)]
+    pub ocf: bool,
+    /// Length in bytes of the per-VC frame sequence counter (0 to 7)
+    #[serde(default = "default_vc_frame_count_length")]
+    pub vc_frame_count_length: u8,
+    /// Enables constant frame rate mode, filling gaps with idle frames
+    pub idle_frames: Option<IdleFramesConfig>,
+    /// Enables channel coding, frames are sent as CADUs
+    pub coding: Option<CaduConfig>,
+}
+
+impl FrameOutConfig {
+    /// Length of the USLP primary header, including the VC frame count
+    pub fn primary_header_len(&self) -> usize {
+        USLP_PRIMARY_HEADER_LEN + self.vc_frame_count_length as usize
+    }
+
+    /// Length of the data zone of a frame with a data field header of `tfdf_header_len` bytes,
+    /// `None` if the frame length doesn't even fit the headers.
+    pub fn data_zone_len(&self, tfdf_header_len: usize) -> Option<usize> {
+        let overhead = self.primary_header_len()
+            + tfdf_header_len
+            + if self.ocf { OCF_LEN } else { 0 }
+            + if self.fecf { FECF_LEN } else { 0 };
+        // The frame length field is the number of bytes in the frame minus one
+        (self.frame_length as usize + 1).checked_sub(overhead)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct IdleFramesConfig {
+    /// Time between two consecutive frames in milliseconds
+    pub interval_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CaduConfig {
+    /// Apply the CCSDS pseudo-randomizer to everything after the ASM
+    #[serde(default = "default_randomize")]
+    pub randomize: bool,
+    /// Append Reed-Solomon (255,223) check symbols to every frame
+    pub reed_solomon: Option<ReedSolomonConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReedSolomonConfig {
+    /// Number of interleaved codewords per frame (1, 2, 3, 4, 5 or 8)
+    pub interleave_depth: u8,
+}
+
+fn default_randomize() -> bool {
+    true
+}
+
+fn default_tfvn() -> u8 {
+    12
+}
+
+fn default_frame_length() -> u16 {
+    522
+}
+
+fn default_vc_frame_count_length() -> u8 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -44,65 +164,455 @@ pub struct Frames {
     pub out: FrameOutConfig,
 }
 
+/// Framing options that only apply to a single virtual channel
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct VcFramingConfig {
+    /// Wrap raw data received on this VC in a space packet with this APID before framing
+    pub space_packet_apid: Option<u16>,
+    /// Which packets of the VC end with a CRC, which is updated after setting the sequence count
+    #[serde(default)]
+    pub packet_crc: PacketCrc,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VirtualChannelConfig {
+    #[serde(flatten)]
+    pub vc: VirtualChannel,
+    #[serde(default)]
+    pub framing: VcFramingConfig,
+}
+
+/// A master channel: all frames of one spacecraft, with its own links and virtual channels
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Config {
+pub struct MasterChannelConfig {
     pub frames: Frames,
-    pub virtual_channels: Vec<VirtualChannel>,
+    pub virtual_channels: Vec<VirtualChannelConfig>,
+    /// Enables the SDLS security layer
+    pub sdls: Option<SdlsConfig>,
+    /// Enables periodic link status housekeeping packets
+    pub link_status: Option<LinkStatusConfig>,
+    /// Rules selecting the downlink VC of packets, checked in order
+    #[serde(default)]
+    pub downlink_routes: Vec<RouteConfig>,
+    /// Enables onboard packet stores with playback
+    pub packet_stores: Option<PacketStoresConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub master_channels: Vec<MasterChannelConfig>,
 }
 
 impl Config {
-    pub fn find_config_file() -> Result<PathBuf, ConfigError> {
-        let paths = [
-            "etc/config.yaml",
-            "install/rccn_usr_comm/share/rccn_usr_comm/etc/config.yaml"
-        ];
+    /// Default locations of the config file, relative to the working directory
+    const DEFAULT_PATHS: [&'static str; 2] = [
+        "etc/config.yaml",
+        "install/rccn_usr_comm/share/rccn_usr_comm/etc/config.yaml",
+    ];
+
+    /// Returns `path` if given, otherwise the first default config file location which exists.
+    pub fn find_config_file(path: Option<&Path>) -> Result<PathBuf, ConfigError> {
+        if let Some(path) = path {
+            if !path.exists() {
+                return Err(ConfigError::ConfigFileMissing(path.to_path_buf()));
+            }
+            return Ok(path.to_path_buf());
+        }
 
-        for path in paths {
+        for path in Self::DEFAULT_PATHS {
             let p = PathBuf::from(path);
             if p.exists() {
                 return Ok(p)
             }
         }
 
-        return Err(ConfigError::ConfigNotFound)
+        Err(ConfigError::ConfigNotFound)
     }
 
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
         let contents = std::fs::read_to_string(path)?;
-        let config: Self = serde_yaml::from_str(&contents)?;
+        let config = Self::from_yaml(&contents)?;
         config.validate()?;
         Ok(config)
     }
 
+    /// Parses a config, which may also be a single master channel with its fields at the
+    /// top level, the layout before multiple master channels were supported.
+    fn from_yaml(contents: &str) -> Result<Self, ConfigError> {
+        let value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+        if value.get("master_channels").is_some() {
+            return Ok(serde_yaml::from_value(value)?);
+        }
+        Ok(Self {
+            master_channels: vec![serde_yaml::from_value(value)?],
+        })
+    }
+
+    /// Serializes the config including all default values, as it is used by the application.
+    ///
+    /// SDLS keys are replaced by a placeholder.
+    pub fn to_yaml(&self) -> Result<String, ConfigError> {
+        let mut config = self.clone();
+        for mc in &mut config.master_channels {
+            for sa in mc.sdls.iter_mut().flat_map(|s| s.security_associations.iter_mut()) {
+                sa.key = "<redacted>".into();
+            }
+        }
+        Ok(serde_yaml::to_string(&config)?)
+    }
+
     fn validate(&self) -> Result<(), ConfigError> {
+        if self.master_channels.is_empty() {
+            return Err(invalid(
+                "master_channels",
+                "At least one master channel must be configured",
+            ));
+        }
+
+        let mut seen_scids = std::collections::HashSet::new();
+        for (i, mc) in self.master_channels.iter().enumerate() {
+            let path = format!("master_channels[{}]", i);
+            let scid = mc.frames.spacecraft_id;
+            if !seen_scids.insert(scid) {
+                return Err(invalid(
+                    format!("{path}.frames.spacecraft_id"),
+                    format!("Duplicate master channel spacecraft ID {:#x}", scid),
+                ));
+            }
+
+            mc.validate(&path)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_packet_filter(filter: &PacketFilter, path: &str) -> Result<(), ConfigError> {
+    if filter.apid.is_some_and(|apid| apid > 0x7FF) {
+        return Err(invalid(format!("{path}.apid"), "APID does not fit in 11 bits"));
+    }
+    if filter.subservice.is_some() && filter.service.is_none() {
+        return Err(invalid(
+            format!("{path}.subservice"),
+            "A subservice can only be matched together with a service",
+        ));
+    }
+    Ok(())
+}
+
+impl MasterChannelConfig {
+    pub fn virtual_channel(&self, id: VcId) -> Option<&VirtualChannelConfig> {
+        self.virtual_channels.iter().find(|vc| vc.vc.id == id)
+    }
+
+    /// Checks the master channel, `path` is its location in the config file used in error messages.
+    fn validate(&self, path: &str) -> Result<(), ConfigError> {
+        if self.frames.spacecraft_id > 0x3FF {
+            return Err(invalid(
+                format!("{path}.frames.spacecraft_id"),
+                format!(
+                    "Spacecraft ID {:#x} does not fit in the 10 bit TC frame field",
+                    self.frames.spacecraft_id
+                ),
+            ));
+        }
+
         // Validate frame types
         if self.frames.r#in.frame_kind != FrameKind::Tc {
-            return Err(ConfigError::Validation(
-                "Input frame kind must be TC".into(),
+            return Err(invalid(
+                format!("{path}.frames.in.frame_kind"),
+                "Input frame kind must be TC",
             ));
         }
         if self.frames.out.frame_kind != FrameKind::Uslp {
-            return Err(ConfigError::Validation(
-                "Output frame kind must be USLP".into(),
+            return Err(invalid(
+                format!("{path}.frames.out.frame_kind"),
+                "Output frame kind must be USLP",
             ));
         }
 
+        // The frame links are only implemented for UDP
+        if matches!(self.frames.r#in.transport, RxTransport::Ros2(_)) {
+            return Err(invalid(
+                format!("{path}.frames.in.transport"),
+                "Only UDP is supported as frame link transport",
+            ));
+        }
+        if matches!(self.frames.out.transport, TxTransport::Ros2(_)) {
+            return Err(invalid(
+                format!("{path}.frames.out.transport"),
+                "Only UDP is supported as frame link transport",
+            ));
+        }
+
+        if let Some(farm) = &self.frames.r#in.farm {
+            if farm.window_width < 2 || farm.window_width % 2 != 0 {
+                return Err(invalid(
+                    format!("{path}.frames.in.farm.window_width"),
+                    format!(
+                        "FARM window width must be an even number between 2 and 254, got {}",
+                        farm.window_width
+                    ),
+                ));
+            }
+        }
+
+        // Validate downlink framing parameters
+        if self.frames.out.tfvn > 0xF {
+            return Err(invalid(
+                format!("{path}.frames.out.tfvn"),
+                format!(
+                    "Transfer frame version number {} does not fit in 4 bits",
+                    self.frames.out.tfvn
+                ),
+            ));
+        }
+        if self.frames.out.vc_frame_count_length > 7 {
+            return Err(invalid(
+                format!("{path}.frames.out.vc_frame_count_length"),
+                format!(
+                    "VC frame count length must be between 0 and 7 bytes, got {}",
+                    self.frames.out.vc_frame_count_length
+                ),
+            ));
+        }
+
+        let data_zone = (
+            self.frames.out.data_zone_len(TFDF_HEADER_LEN),
+            self.frames.out.data_zone_len(IDLE_TFDF_HEADER_LEN),
+        );
+        match data_zone {
+            (Some(len), Some(idle_len)) if len > 0 && idle_len <= MAX_FRAME_DATA_LEN => {}
+            _ => {
+                return Err(invalid(
+                    format!("{path}.frames.out.frame_length"),
+                    format!(
+                        "Frame length {} leaves no room for data after the headers, OCF and FECF, \
+                         or exceeds the {} byte frame data buffer",
+                        self.frames.out.frame_length, MAX_FRAME_DATA_LEN
+                    ),
+                ));
+            }
+        }
+
+        if let Some(idle) = &self.frames.out.idle_frames {
+            if idle.interval_ms == 0 {
+                return Err(invalid(
+                    format!("{path}.frames.out.idle_frames.interval_ms"),
+                    "Idle frame interval must be greater than zero",
+                ));
+            }
+        }
+
+        if let Some(rs) = self.frames.out.coding.as_ref().and_then(|c| c.reed_solomon.as_ref()) {
+            if !VALID_INTERLEAVE_DEPTHS.contains(&rs.interleave_depth) {
+                return Err(invalid(
+                    format!("{path}.frames.out.coding.reed_solomon.interleave_depth"),
+                    format!(
+                        "Reed-Solomon interleave depth must be one of {:?}, got {}",
+                        VALID_INTERLEAVE_DEPTHS, rs.interleave_depth
+                    ),
+                ));
+            }
+
+            // The whole frame, including the FECF, is split into the interleaved codewords
+            let frame_len = self.frames.out.frame_length as usize + 1;
+            let depth = rs.interleave_depth as usize;
+            if !frame_len.is_multiple_of(depth) || frame_len > RS_DATA_LEN * depth {
+                return Err(invalid(
+                    format!("{path}.frames.out.frame_length"),
+                    format!(
+                        "Frame of {} bytes does not fit Reed-Solomon coding with interleave depth {}, \
+                         it must be a multiple of the depth and at most {} bytes",
+                        frame_len,
+                        depth,
+                        RS_DATA_LEN * depth
+                    ),
+                ));
+            }
+        }
+
         // Validate virtual channels: check IDs are unique and ROS2 output transports
         let mut seen_ids = std::collections::HashSet::new();
-        for vc in &self.virtual_channels {
+        for (i, VirtualChannelConfig { vc, framing }) in self.virtual_channels.iter().enumerate() {
+            let vc_path = format!("{path}.virtual_channels[{i}]");
+
             if !seen_ids.insert(vc.id) {
-                return Err(ConfigError::Validation(format!(
-                    "Duplicate virtual channel ID: {}",
-                    vc.id
-                )));
+                return Err(invalid(
+                    format!("{vc_path}.id"),
+                    format!("Duplicate virtual channel ID {}", vc.id),
+                ));
+            }
+
+            if vc.id == IDLE_VC_ID && self.frames.out.idle_frames.is_some() {
+                return Err(invalid(
+                    format!("{vc_path}.id"),
+                    format!("VC {} is reserved for idle frames", IDLE_VC_ID),
+                ));
+            }
+
+            if let Some(apid) = framing.space_packet_apid {
+                if apid > 0x7FF {
+                    return Err(invalid(
+                        format!("{vc_path}.framing.space_packet_apid"),
+                        format!("Space packet APID {:#x} does not fit in 11 bits", apid),
+                    ));
+                }
+            }
+
+            if let PacketCrc::Apids(apids) = &framing.packet_crc {
+                if let Some(apid) = apids.iter().find(|apid| **apid > 0x7FF) {
+                    return Err(invalid(
+                        format!("{vc_path}.framing.packet_crc"),
+                        format!("APID {:#x} does not fit in 11 bits", apid),
+                    ));
+                }
             }
 
             if let Some(RxTransport::Ros2(t)) = &vc.rx_transport {
                 if t.topic_sub.is_none() && t.action_srv.is_none() {
-                    return Err(ConfigError::Validation(format!(
-                        "Need `topic_sub` or `action_srv` for output transport of VC {}",
-                        vc.name
-                    )));
+                    return Err(invalid(
+                        format!("{vc_path}.rx_transport"),
+                        format!(
+                            "Need `topic_sub` or `action_srv` for output transport of VC {}",
+                            vc.name
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if let Some(link_status) = &self.link_status {
+            if link_status.apid > 0x7FF {
+                return Err(invalid(
+                    format!("{path}.link_status.apid"),
+                    format!("Link status APID {:#x} does not fit in 11 bits", link_status.apid),
+                ));
+            }
+            if link_status.interval_ms == 0 {
+                return Err(invalid(
+                    format!("{path}.link_status.interval_ms"),
+                    "Link status interval must be greater than zero",
+                ));
+            }
+            if self.virtual_channel(link_status.vc_id).is_none() {
+                return Err(invalid(
+                    format!("{path}.link_status.vc_id"),
+                    format!("VC {} is not a configured virtual channel", link_status.vc_id),
+                ));
+            }
+        }
+
+        for (i, route) in self.downlink_routes.iter().enumerate() {
+            let route_path = format!("{path}.downlink_routes[{i}]");
+
+            validate_packet_filter(&route.filter, &route_path)?;
+            if self.virtual_channel(route.vc_id).is_none() {
+                return Err(invalid(
+                    format!("{route_path}.vc_id"),
+                    format!("VC {} is not a configured virtual channel", route.vc_id),
+                ));
+            }
+        }
+
+        if let Some(packet_stores) = &self.packet_stores {
+            self.validate_packet_stores(packet_stores, &format!("{path}.packet_stores"))?;
+        }
+
+        if let Some(sdls) = &self.sdls {
+            self.validate_sdls(sdls, &format!("{path}.sdls"))?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_packet_stores(
+        &self,
+        config: &PacketStoresConfig,
+        path: &str,
+    ) -> Result<(), ConfigError> {
+        if config.service_apid > 0x7FF {
+            return Err(invalid(
+                format!("{path}.service_apid"),
+                format!("Service APID {:#x} does not fit in 11 bits", config.service_apid),
+            ));
+        }
+        for (field, vc_id) in [("tm_vc_id", config.tm_vc_id), ("dump_vc_id", config.dump_vc_id)] {
+            if self.virtual_channel(vc_id).is_none() {
+                return Err(invalid(
+                    format!("{path}.{field}"),
+                    format!("VC {} is not a configured virtual channel", vc_id),
+                ));
+            }
+        }
+
+        let mut seen_ids = std::collections::HashSet::new();
+        for (i, store) in config.stores.iter().enumerate() {
+            let store_path = format!("{path}.stores[{i}]");
+
+            if !seen_ids.insert(store.id) {
+                return Err(invalid(
+                    format!("{store_path}.id"),
+                    format!("Duplicate packet store ID {}", store.id),
+                ));
+            }
+            if store.segment_bytes == 0 || store.segment_bytes > store.max_bytes {
+                return Err(invalid(
+                    format!("{store_path}.segment_bytes"),
+                    format!(
+                        "Segment size must be between 1 and max_bytes ({}), got {}",
+                        store.max_bytes, store.segment_bytes
+                    ),
+                ));
+            }
+            for (j, filter) in store.packets.iter().enumerate() {
+                validate_packet_filter(filter, &format!("{store_path}.packets[{j}]"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_sdls(&self, sdls: &SdlsConfig, path: &str) -> Result<(), ConfigError> {
+        let mut seen_spis = std::collections::HashSet::new();
+        let mut downlink_vcs = std::collections::HashSet::new();
+
+        for (i, sa) in sdls.security_associations.iter().enumerate() {
+            let sa_path = format!("{path}.security_associations[{i}]");
+
+            if !seen_spis.insert(sa.spi) {
+                return Err(invalid(
+                    format!("{sa_path}.spi"),
+                    format!("Duplicate security association SPI {}", sa.spi),
+                ));
+            }
+
+            sa.key_bytes()
+                .map_err(|e| invalid(format!("{sa_path}.key"), e.to_string()))?;
+
+            if !(1..=8).contains(&sa.arsn_length) {
+                return Err(invalid(
+                    format!("{sa_path}.arsn_length"),
+                    format!(
+                        "ARSN length must be between 1 and 8 bytes, got {}",
+                        sa.arsn_length
+                    ),
+                ));
+            }
+
+            for vc_id in &sa.virtual_channels {
+                if self.virtual_channel(*vc_id).is_none() {
+                    return Err(invalid(
+                        format!("{sa_path}.virtual_channels"),
+                        format!("VC {} is not a configured virtual channel", vc_id),
+                    ));
+                }
+
+                if sa.direction == SaDirection::Downlink && !downlink_vcs.insert(*vc_id) {
+                    return Err(invalid(
+                        format!("{sa_path}.virtual_channels"),
+                        format!("More than one downlink security association for VC {}", vc_id),
+                    ));
                 }
             }
         }
@@ -110,3 +620,106 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_config() -> Config {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("etc/config.yaml");
+        Config::from_file(path).unwrap()
+    }
+
+    fn invalid_field(config: &Config) -> String {
+        match config.validate() {
+            Err(ConfigError::Validation { field, .. }) => field,
+            other => panic!("Expected validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validation_errors_name_the_field() {
+        let mut config = example_config();
+        config.master_channels[0].frames.out.tfvn = 16;
+        assert_eq!(invalid_field(&config), "master_channels[0].frames.out.tfvn");
+
+        let mut config = example_config();
+        config.master_channels[0].virtual_channels[1].framing.space_packet_apid = Some(0x800);
+        assert_eq!(
+            invalid_field(&config),
+            "master_channels[0].virtual_channels[1].framing.space_packet_apid"
+        );
+
+        let mut config = example_config();
+        config.master_channels[0].frames.out.transport = TxTransport::Ros2("/frames/out".into());
+        assert_eq!(invalid_field(&config), "master_channels[0].frames.out.transport");
+
+        let mut config = example_config();
+        config.master_channels[0].frames.out.frame_length = 10;
+        assert_eq!(invalid_field(&config), "master_channels[0].frames.out.frame_length");
+
+        // The default frame of 523 bytes does not fit into a single RS codeword
+        let mut config = example_config();
+        config.master_channels[0].frames.out.coding = Some(CaduConfig {
+            randomize: true,
+            reed_solomon: Some(ReedSolomonConfig { interleave_depth: 1 }),
+        });
+        assert_eq!(invalid_field(&config), "master_channels[0].frames.out.frame_length");
+        config.master_channels[0].frames.out.frame_length = 222;
+        assert!(config.validate().is_ok());
+        config.master_channels[0].frames.out.coding = Some(CaduConfig {
+            randomize: true,
+            reed_solomon: Some(ReedSolomonConfig { interleave_depth: 2 }),
+        });
+        assert_eq!(invalid_field(&config), "master_channels[0].frames.out.frame_length");
+
+        let mut config = example_config();
+        let mc = config.master_channels[0].clone();
+        config.master_channels.push(mc);
+        assert_eq!(invalid_field(&config), "master_channels[1].frames.spacecraft_id");
+    }
+
+    #[test]
+    fn test_frame_data_zone() {
+        let mut out = example_config().master_channels[0].frames.out.clone();
+        assert_eq!(out.data_zone_len(TFDF_HEADER_LEN), Some(512));
+        assert_eq!(out.data_zone_len(IDLE_TFDF_HEADER_LEN), Some(514));
+
+        out.fecf = true;
+        out.ocf = true;
+        assert_eq!(out.data_zone_len(TFDF_HEADER_LEN), Some(506));
+
+        out.frame_length = 8;
+        assert_eq!(out.data_zone_len(TFDF_HEADER_LEN), None);
+    }
+
+    #[test]
+    fn test_effective_config_round_trip() {
+        let config = example_config();
+        let yaml = config.to_yaml().unwrap();
+        let parsed: Config = serde_yaml::from_str(&yaml).unwrap();
+        parsed.validate().unwrap();
+        assert_eq!(parsed.master_channels[0].frames, config.master_channels[0].frames);
+    }
+
+    #[test]
+    fn test_single_master_channel_layout() {
+        let config = example_config();
+        let yaml = serde_yaml::to_string(&config.master_channels[0]).unwrap();
+        assert!(!yaml.contains("master_channels"));
+
+        let parsed = Config::from_yaml(&yaml).unwrap();
+        parsed.validate().unwrap();
+        assert_eq!(parsed.master_channels.len(), 1);
+        assert_eq!(parsed.master_channels[0].frames, config.master_channels[0].frames);
+    }
+
+    #[test]
+    fn test_explicit_config_path_must_exist() {
+        let missing = Path::new("/nonexistent/rccn_usr_comm.yaml");
+        assert!(matches!(
+            Config::find_config_file(Some(missing)),
+            Err(ConfigError::ConfigFileMissing(p)) if p == missing
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+//! Frame acceptance and reporting mechanism (FARM-1) of the COP-1 protocol, CCSDS 232.1-B.
+//!
+//! One FARM instance runs per virtual channel. Type-BD frames (bypass) are always
+//! accepted. Type-AD frames are only accepted in sequence, as given by the frame
+//! sequence number N(S) and the receiver frame sequence number V(R). Type-BC frames
+//! carry the Unlock and Set V(R) control commands, which are consumed by the FARM.
+//! The wait state is never entered, since the VC channels do not apply back pressure.
+
+use serde::{Deserialize, Serialize};
+
+const BYPASS_FLAG: u8 = 0x20;
+const CONTROL_COMMAND_FLAG: u8 = 0x10;
+
+const UNLOCK_COMMAND: [u8; 1] = [0x00];
+const SET_VR_COMMAND: [u8; 2] = [0x82, 0x00];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FarmConfig {
+    /// FARM sliding window width W, an even number between 2 and 254
+    #[serde(default = "default_window_width")]
+    pub window_width: u8,
+}
+
+fn default_window_width() -> u8 {
+    10
+}
+
+#[allow(dead_code)] // Wait is never entered, but reported as part of the FARM state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FarmState {
+    Open = 1,
+    Wait = 2,
+    Lockout = 3,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FarmAction {
+    /// Pass the frame data on to the virtual channel
+    Accept,
+    /// The frame was a control command and has been executed by the FARM
+    Control,
+    /// Discard the frame, its sequence number is out of order
+    Reject,
+}
+
+pub struct Farm {
+    window_width: u8,
+    state: FarmState,
+    receiver_frame_seq: u8,
+    retransmit: bool,
+    farm_b_counter: u8,
+}
+
+impl Farm {
+    pub fn new(config: &FarmConfig) -> Self {
+        Self {
+            window_width: config.window_width,
+            state: FarmState::Open,
+            receiver_frame_seq: 0,
+            retransmit: false,
+            farm_b_counter: 0,
+        }
+    }
+
+    pub fn state(&self) -> FarmState {
+        self.state
+    }
+
+    /// Receiver frame sequence number V(R)
+    pub fn receiver_frame_seq(&self) -> u8 {
+        self.receiver_frame_seq
+    }
+
+    pub fn retransmit(&self) -> bool {
+        self.retransmit
+    }
+
+    /// Counts accepted type-B frames, modulo 4 as reported in the CLCW
+    pub fn farm_b_counter(&self) -> u8 {
+        self.farm_b_counter
+    }
+
+    /// Runs a received TC frame through the FARM.
+    ///
+    /// `frame` is the complete frame starting with the primary header, `data_field` the frame data field.
+    pub fn process(&mut self, frame: &[u8], data_field: &[u8]) -> FarmAction {
+        let bypass = frame[0] & BYPASS_FLAG != 0;
+        let control_command = frame[0] & CONTROL_COMMAND_FLAG != 0;
+        let frame_seq = frame[4];
+
+        match (bypass, control_command) {
+            (true, false) => {
+                self.increment_farm_b_counter();
+                FarmAction::Accept
+            }
+            (true, true) => self.process_control_command(data_field),
+            // Type-AC frames do not exist
+            (false, true) => FarmAction::Reject,
+            (false, false) => self.process_sequence_controlled(frame_seq),
+        }
+    }
+
+    fn process_sequence_controlled(&mut self, frame_seq: u8) -> FarmAction {
+        if self.state == FarmState::Lockout {
+            return FarmAction::Reject;
+        }
+
+        let ahead = frame_seq.wrapping_sub(self.receiver_frame_seq);
+        let behind = self.receiver_frame_seq.wrapping_sub(frame_seq);
+        let half_window = self.window_width / 2;
+
+        if ahead == 0 {
+            self.receiver_frame_seq = self.receiver_frame_seq.wrapping_add(1);
+            self.retransmit = false;
+            FarmAction::Accept
+        } else if ahead < half_window {
+            // A frame was lost, request a retransmission
+            self.retransmit = true;
+            FarmAction::Reject
+        } else if behind <= half_window {
+            // Already accepted frame was sent again
+            FarmAction::Reject
+        } else {
+            self.state = FarmState::Lockout;
+            FarmAction::Reject
+        }
+    }
+
+    fn process_control_command(&mut self, data_field: &[u8]) -> FarmAction {
+        if data_field == UNLOCK_COMMAND {
+            self.state = FarmState::Open;
+            self.retransmit = false;
+        } else if data_field.len() == 3 && data_field[..2] == SET_VR_COMMAND {
+            if self.state != FarmState::Lockout {
+                self.receiver_frame_seq = data_field[2];
+                self.retransmit = false;
+            }
+        } else {
+            return FarmAction::Reject;
+        }
+
+        self.increment_farm_b_counter();
+        FarmAction::Control
+    }
+
+    fn increment_farm_b_counter(&mut self) {
+        self.farm_b_counter = (self.farm_b_counter + 1) % 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn farm() -> Farm {
+        Farm::new(&FarmConfig { window_width: 10 })
+    }
+
+    fn header(flags: u8, frame_seq: u8) -> [u8; 5] {
+        [flags, 0xAB, 0x04, 0x10, frame_seq]
+    }
+
+    fn ad_frame(farm: &mut Farm, frame_seq: u8) -> FarmAction {
+        farm.process(&header(0, frame_seq), &[1, 2, 3])
+    }
+
+    #[test]
+    fn test_accepts_ad_frames_in_sequence() {
+        let mut farm = farm();
+        for seq in 0..=255u8 {
+            assert_eq!(ad_frame(&mut farm, seq), FarmAction::Accept);
+        }
+        // V(R) wraps around
+        assert_eq!(farm.receiver_frame_seq(), 0);
+        assert_eq!(ad_frame(&mut farm, 0), FarmAction::Accept);
+    }
+
+    #[test]
+    fn test_gap_requests_retransmission() {
+        let mut farm = farm();
+        assert_eq!(ad_frame(&mut farm, 0), FarmAction::Accept);
+        assert_eq!(ad_frame(&mut farm, 2), FarmAction::Reject);
+        assert!(farm.retransmit());
+        assert_eq!(farm.state(), FarmState::Open);
+
+        assert_eq!(ad_frame(&mut farm, 1), FarmAction::Accept);
+        assert!(!farm.retransmit());
+
+        // Repeated frame in the negative window
+        assert_eq!(ad_frame(&mut farm, 1), FarmAction::Reject);
+        assert_eq!(farm.state(), FarmState::Open);
+    }
+
+    #[test]
+    fn test_lockout_and_unlock() {
+        let mut farm = farm();
+        assert_eq!(ad_frame(&mut farm, 100), FarmAction::Reject);
+        assert_eq!(farm.state(), FarmState::Lockout);
+        assert_eq!(ad_frame(&mut farm, 0), FarmAction::Reject);
+
+        // Set V(R) is ignored in lockout
+        let bc = header(BYPASS_FLAG | CONTROL_COMMAND_FLAG, 0);
+        assert_eq!(farm.process(&bc, &[0x82, 0x00, 100]), FarmAction::Control);
+        assert_eq!(farm.receiver_frame_seq(), 0);
+
+        assert_eq!(farm.process(&bc, &UNLOCK_COMMAND), FarmAction::Control);
+        assert_eq!(farm.state(), FarmState::Open);
+        assert_eq!(farm.process(&bc, &[0x82, 0x00, 100]), FarmAction::Control);
+        assert_eq!(ad_frame(&mut farm, 100), FarmAction::Accept);
+    }
+
+    #[test]
+    fn test_bypass_frames() {
+        let mut farm = farm();
+        ad_frame(&mut farm, 50);
+        assert_eq!(farm.state(), FarmState::Lockout);
+
+        // BD frames are accepted even in lockout
+        for i in 1..=5 {
+            assert_eq!(farm.process(&header(BYPASS_FLAG, 7), &[1]), FarmAction::Accept);
+            assert_eq!(farm.farm_b_counter(), i % 4);
+        }
+
+        // Unknown control commands and type-AC frames are rejected
+        let bc = header(BYPASS_FLAG | CONTROL_COMMAND_FLAG, 0);
+        assert_eq!(farm.process(&bc, &[0x42]), FarmAction::Reject);
+        assert_eq!(farm.process(&header(CONTROL_COMMAND_FLAG, 0), &[]), FarmAction::Reject);
+    }
+}
@@ -1,13 +1,27 @@
 use ccsds_protocols::traits::CCSDSFrames;
-use crossbeam_channel::{Receiver, Select, SendError, Sender};
-use spacepackets::{PacketId, PacketSequenceCtrl, SpHeader};
+use crossbeam_channel::{bounded, tick, Receiver, Select, SendError, Sender, TrySendError};
+use spacepackets::CRC_CCITT_FALSE;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use ccsds_protocols::tc_transfer_frame::TcTransferFrame;
 
-use crate::config::{Config, FrameKind};
-use rccn_usr::types::{VcId, VirtualChannelRxMap, VirtualChannelTxMap};
+use crate::coding::cadu;
+use crate::config::{
+    MasterChannelConfig, VirtualChannelConfig, IDLE_TFDF_HEADER_LEN, IDLE_VC_ID, MAX_FRAME_DATA_LEN,
+    TFDF_HEADER_LEN,
+};
+use crate::farm::{Farm, FarmAction};
+use crate::frame_reassembler::{FrameReassembler, TC_PRIMARY_HEADER_LEN};
+use crate::link_stats::{FarmStatus, LinkStats};
+use crate::packet_store::PacketStorage;
+use crate::routing::{RouteConfig, Router};
+use crate::sdls::{SdlsError, SecurityLayer};
+use crate::sequence_counter::{PacketCrc, SequenceCounters};
+use crate::virtual_channels::VirtualChannels;
+use rccn_usr::time::TimestampHelper;
+use rccn_usr::types::VcId;
 
 use ccsds_protocols::uslp_transfer_paket::USLPTransferPaket;
 
@@ -19,209 +33,648 @@ pub enum FrameProcessingError {
     RXChannelClosed,
     UnknownSpacecraft(u16),
     UnknownVirtualChannel(VcId),
+    VirtualChannelFull(VcId),
 }
 
 pub type FrameProcessingResult = Result<(), FrameProcessingError>;
 
-const FRAME_PROCESSING_BUFFER_SIZE: usize = 8096;
+/// Where the downlink data of a channel comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DownlinkSource {
+    /// An application, through the transports of a virtual channel
+    VirtualChannel,
+    /// Space packets generated by the comm application itself
+    Internal,
+    /// Packets retrieved from a packet store, sent as they were recorded
+    Playback,
+}
+
+/// TFDZ construction rules value for idle data
+const IDLE_TFDZ_CONSTRUCTION_RULES: u8 = 0b111;
+/// USLP protocol identifier for idle data
+const IDLE_PROTOCOL_ID: u8 = 0b11111;
+const IDLE_DATA_PATTERN: u8 = 0x55;
 
+/// Processes the uplink and downlink frames of a single master channel
 #[derive(Clone)]
 pub struct FrameProcessor {
-    config: Arc<Config>,
+    config: Arc<MasterChannelConfig>,
     shared_state: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    vc_frame_counts: Arc<Mutex<HashMap<VcId, u64>>>,
+    seq_counters: Arc<Mutex<SequenceCounters>>,
+    security: Option<Arc<Mutex<SecurityLayer>>>,
+    farms: Arc<Mutex<HashMap<VcId, Farm>>>,
+    stats: Arc<Mutex<LinkStats>>,
+    virtual_channels: Arc<RwLock<VirtualChannels>>,
+    router: Arc<RwLock<Router>>,
+    storage: Option<Arc<PacketStorage>>,
+    /// Tells the downlink thread to pick up changed virtual channels
+    vcs_changed_tx: Sender<()>,
+    vcs_changed_rx: Receiver<()>,
 }
 
 impl FrameProcessor {
-    pub fn new(config: Arc<Config>) -> Self {
-        Self {
+    /// Creates the frame processor for a master channel.
+    ///
+    /// The VCs in `config` are ignored in favour of `virtual_channels`, which can change at runtime.
+    /// If `storage` is given, downlink packets are recorded and ST[15] commands are handled.
+    /// Fails if the saved ARSNs of the security layer can not be read.
+    pub fn new(
+        config: Arc<MasterChannelConfig>,
+        virtual_channels: VirtualChannels,
+        storage: Option<PacketStorage>,
+    ) -> Result<Self, SdlsError> {
+        let security = match &config.sdls {
+            Some(sdls) => Some(Arc::new(Mutex::new(SecurityLayer::new(sdls)?))),
+            None => None,
+        };
+
+        let (vcs_changed_tx, vcs_changed_rx) = bounded(1);
+        let router = Router::new(&config.downlink_routes);
+
+        let processor = Self {
             config,
             shared_state: Arc::new(Mutex::new(HashMap::new())),
+            vc_frame_counts: Arc::new(Mutex::new(HashMap::new())),
+            seq_counters: Arc::new(Mutex::new(SequenceCounters::new())),
+            security,
+            farms: Arc::new(Mutex::new(HashMap::new())),
+            stats: Arc::new(Mutex::new(LinkStats::default())),
+            virtual_channels: Arc::new(RwLock::new(virtual_channels)),
+            router: Arc::new(RwLock::new(router)),
+            storage: storage.map(Arc::new),
+            vcs_changed_tx,
+            vcs_changed_rx,
+        };
+        processor.update_farms();
+        Ok(processor)
+    }
+
+    /// Applies a changed VC configuration while frames are being processed.
+    ///
+    /// FARMs, frame counters and sequence counters of the remaining VCs are kept.
+    pub fn update_virtual_channels(&self, configs: &[VirtualChannelConfig]) {
+        let changes = self.virtual_channels.write().unwrap().update(configs);
+        if changes.is_empty() {
+            return;
         }
+        log::info!(
+            "Updated virtual channels of master channel {:#x}: {:?}",
+            self.config.frames.spacecraft_id,
+            changes
+        );
+
+        self.update_farms();
+        let _ = self.vcs_changed_tx.try_send(());
     }
 
-    pub fn process_incoming_frames(
-        &self,
-        bytes_in_rx: Receiver<Vec<u8>>,
-        vc_in_map: &VirtualChannelTxMap,
-    ) -> FrameProcessingResult {
+    /// Replaces the downlink routes while frames are being processed.
+    pub fn update_routes(&self, routes: &[RouteConfig]) {
+        *self.router.write().unwrap() = Router::new(routes);
+    }
+
+    /// Keeps one FARM for every VC which receives uplink data.
+    fn update_farms(&self) {
+        let Some(farm_config) = &self.config.frames.r#in.farm else {
+            return;
+        };
+
+        let uplink_vcs = self.virtual_channels.read().unwrap().uplink_vc_ids();
+        let mut farms = self.farms.lock().unwrap();
+        farms.retain(|vc_id, _| uplink_vcs.contains(vc_id));
+        for vc_id in uplink_vcs {
+            farms.entry(vc_id).or_insert_with(|| Farm::new(farm_config));
+        }
+    }
+
+    pub fn process_incoming_frames(&self, bytes_in_rx: Receiver<Vec<u8>>) -> FrameProcessingResult {
         let _state = Arc::clone(&self.shared_state);
 
-        let mut buf = [0u8; FRAME_PROCESSING_BUFFER_SIZE];
-        let mut buf_pos: usize = 0;
+        // Only TC frames are supported on the uplink, this is checked when loading the config
+        let mut reassembler = FrameReassembler::new(self.config.frames.r#in.fecf);
+        let mut discarded_bytes = 0;
 
         loop {
             let data = bytes_in_rx
                 .recv()
                 .map_err(|_| FrameProcessingError::RXChannelClosed)?;
 
-            // Check if the data fits.
-            let rcvd_size = data.len();
-            if rcvd_size > buf_pos + buf.len() {
-                println!(
-                    "Received data too big, size {} does not fit in buffer (current position: {})",
-                    data.len(),
-                    buf_pos
-                );
-                println!("Discarding data and clearing buffer.");
-                buf.fill(0);
-                buf_pos = 0;
-                continue;
-            }
-
-            // Copy the data to our buffer
-            buf[0..buf_pos + rcvd_size].copy_from_slice(&data);
-            buf_pos += rcvd_size;
+            reassembler.push(&data);
 
-            // TODO: not possible to use `Option<dyn CCSDSFrames, usize>` because CCSDSFrames has PartialEq
-            let frame_result = match self.config.frames.r#in.frame_kind {
-                FrameKind::Tc => {
-                    // TODO this shouldn't be mut
-                    TcTransferFrame::from_bytes(&mut buf)
-                }
-                FrameKind::Uslp => todo!(),
-            };
+            while let Some(mut frame_bytes) = reassembler.next_frame() {
+                self.stats.lock().unwrap().frames_received += 1;
 
-            if let Ok((frame, size)) = frame_result {
-                //println!("We got a valid frame: {:?}", frame);
+                // The security layer and the FARM work on the raw frame, the parsed frame borrows the buffer
+                let raw_frame = frame_bytes.clone();
 
-                match self.distribute_vc_data(&frame, vc_in_map) {
-                    Ok(()) => {
-                        println!("Frame data sent to transport sucessfully.");
+                // TODO this shouldn't be mut
+                let frame = match TcTransferFrame::from_bytes(&mut frame_bytes) {
+                    Ok((frame, _size)) => frame,
+                    Err(_) => {
+                        log::warn!("Could not parse TC frame, discarding it.");
+                        self.stats.lock().unwrap().rejected.invalid += 1;
+                        continue;
                     }
+                };
+
+                match self.check_frame_address(&frame) {
+                    Ok(()) => {}
                     Err(FrameProcessingError::UnknownSpacecraft(id)) => {
-                        println!("Received frame for unknown spacecraft ID {}", id);
+                        log::warn!("Received frame for unknown spacecraft ID {}", id);
+                        self.stats.lock().unwrap().rejected.wrong_spacecraft_id += 1;
+                        continue;
                     }
-                    Err(FrameProcessingError::UnknownVirtualChannel(id)) => {
-                        println!("Received frame for unknown virtual channel ID {}", id);
+                    Err(e) => {
+                        log::warn!("Received frame for unknown virtual channel: {:?}", e);
+                        self.stats.lock().unwrap().rejected.unknown_virtual_channel += 1;
+                        continue;
+                    }
+                }
+                let vc_id = frame.get_vc_id();
+
+                // With SDLS enabled, frames failing authentication are dropped here
+                let data = match &self.security {
+                    Some(security) => match self.unprotect_frame(security, vc_id, &raw_frame) {
+                        Ok(data) => data,
+                        Err(e) => {
+                            log::warn!("Rejected frame on VC {}: {}", vc_id, e);
+                            self.stats.lock().unwrap().rejected.security += 1;
+                            continue;
+                        }
+                    },
+                    None => Vec::from(frame.get_data_field()),
+                };
+
+                if let Some(farm) = self.farms.lock().unwrap().get_mut(&vc_id) {
+                    match farm.process(&raw_frame, &data) {
+                        FarmAction::Accept => {}
+                        FarmAction::Control => {
+                            log::info!("Executed FARM control command on VC {}", vc_id);
+                            continue;
+                        }
+                        FarmAction::Reject => {
+                            log::warn!(
+                                "FARM rejected frame on VC {} ({:?}, V(R) = {})",
+                                vc_id,
+                                farm.state(),
+                                farm.receiver_frame_seq()
+                            );
+                            self.stats.lock().unwrap().rejected.sequence += 1;
+                            continue;
+                        }
+                    }
+                }
+
+                // Packet storage commands are handled by the comm application itself
+                let data = match &self.storage {
+                    Some(storage) => storage.handle_uplink(data),
+                    None => data,
+                };
+                if data.is_empty() {
+                    continue;
+                }
+
+                match self.distribute_vc_data(vc_id, data) {
+                    Ok(()) => {
+                        log::debug!("Frame data sent to transport sucessfully.");
                     }
                     Err(e) => {
-                        println!("Unexpected error sending VC data: {:?}", e);
+                        log::error!("Could not pass frame data to VC {}: {:?}", vc_id, e);
+                        self.stats.lock().unwrap().vc_data_dropped += 1;
                     }
                 };
+            }
 
-                // Clear buffer up to the end of the frame we received
-                buf[0..size].fill(0);
-                buf_pos = 0;
-            } else if let Err(_e) = frame_result {
-                // TODO check potential errors in from_bytes,
-                // some may indicate we have a broken frame
-                //  and should clear the buffer
+            {
+                let mut stats = self.stats.lock().unwrap();
+                stats.rejected.crc = reassembler.crc_errors() as u32;
+                stats.discarded_bytes = reassembler.discarded_bytes() as u32;
+            }
+
+            if reassembler.discarded_bytes() != discarded_bytes {
+                log::warn!(
+                    "Discarded {} bytes while searching for a frame start",
+                    reassembler.discarded_bytes() - discarded_bytes
+                );
+                discarded_bytes = reassembler.discarded_bytes();
             }
         }
     }
 
-    fn distribute_vc_data(
+    fn unprotect_frame(
         &self,
-        frame: &TcTransferFrame<'_>,
-        vc_in_map: &VirtualChannelTxMap,
-    ) -> FrameProcessingResult {
+        security: &Mutex<SecurityLayer>,
+        vc_id: VcId,
+        raw_frame: &[u8],
+    ) -> Result<Vec<u8>, SdlsError> {
+        let fecf_len = if self.config.frames.r#in.fecf { 2 } else { 0 };
+        let (primary_header, rest) = raw_frame.split_at(TC_PRIMARY_HEADER_LEN);
+        let data_field = &rest[..rest.len() - fecf_len];
+
+        security
+            .lock()
+            .unwrap()
+            .unprotect_tc(vc_id, primary_header, data_field)
+    }
+
+    fn check_frame_address(&self, frame: &TcTransferFrame<'_>) -> FrameProcessingResult {
         if frame.get_spacecraft_id() != self.config.frames.spacecraft_id {
             return Err(FrameProcessingError::UnknownSpacecraft(
                 frame.get_spacecraft_id(),
             ));
         }
 
-        match vc_in_map.get(&frame.get_vc_id()) {
-            None => Err(FrameProcessingError::UnknownVirtualChannel(
+        if self
+            .virtual_channels
+            .read()
+            .unwrap()
+            .sender(frame.get_vc_id())
+            .is_none()
+        {
+            return Err(FrameProcessingError::UnknownVirtualChannel(
                 frame.get_vc_id(),
-            )),
-            Some(sender) => {
-                let data_vec = Vec::from(frame.get_data_field());
+            ));
+        }
 
+        Ok(())
+    }
+
+    fn distribute_vc_data(&self, vc_id: VcId, data: Vec<u8>) -> FrameProcessingResult {
+        let sender = self.virtual_channels.read().unwrap().sender(vc_id).cloned();
+        match sender {
+            None => Err(FrameProcessingError::UnknownVirtualChannel(vc_id)),
+            Some(sender) => {
                 // TODO: process splitting incoming data stream according to
                 // the `splitter` config variable for this virtual channel.
 
-                sender
-                    .send(data_vec)
-                    .map_err(FrameProcessingError::SendError)
+                // Don't block the whole uplink if a single VC is not read fast enough
+                sender.try_send(data).map_err(|e| match e {
+                    TrySendError::Full(_) => FrameProcessingError::VirtualChannelFull(vc_id),
+                    TrySendError::Disconnected(data) => {
+                        FrameProcessingError::SendError(SendError(data))
+                    }
+                })
             }
         }
     }
 
-    pub fn process_frames_out(&self, bytes_tx: Sender<Vec<u8>>, vc_out_map: &VirtualChannelRxMap) {
-        let mut select = Select::new();
-        let mut channels = Vec::new();
-        for (id, receiver) in vc_out_map {
-            select.recv(receiver);
-            channels.push((id, receiver));
-        }
+    /// Frames and sends the downlink data of all virtual channels.
+    ///
+    /// `sources` carries data which does not come from the transports of a virtual channel,
+    /// together with the VC it is sent on.
+    pub fn process_frames_out(
+        &self,
+        bytes_tx: Sender<Vec<u8>>,
+        sources: &[(VcId, Receiver<Vec<u8>>, DownlinkSource)],
+    ) {
+        // In constant frame rate mode, one frame is sent every tick.
+        // If no VC has data ready at that point, an idle frame is sent instead.
+        let ticker = self
+            .config
+            .frames
+            .out
+            .idle_frames
+            .as_ref()
+            .map(|idle| tick(Duration::from_millis(idle.interval_ms)));
 
         loop {
-            // Block until a channel has data ready to be received.
-            let op = select.select();
-            let index = op.index();
+            // The channels are collected again whenever the virtual channels change
+            let mut channels: Vec<_> = self
+                .virtual_channels
+                .read()
+                .unwrap()
+                .receivers()
+                .into_iter()
+                .map(|(id, receiver)| (id, receiver, DownlinkSource::VirtualChannel))
+                .collect();
+            channels.extend(sources.iter().cloned());
+
+            let mut select = Select::new();
+            for (_, receiver, _) in &channels {
+                select.recv(receiver);
+            }
+            let vcs_changed = select.recv(&self.vcs_changed_rx);
+
+            loop {
+                let op = match &ticker {
+                    None => {
+                        // Block until a channel has data ready to be received.
+                        select.select()
+                    }
+                    Some(ticker) => {
+                        let _ = ticker.recv();
+
+                        match select.try_select() {
+                            Ok(op) => op,
+                            Err(_) => {
+                                self.send_idle_frame(bytes_tx.clone());
+                                continue;
+                            }
+                        }
+                    }
+                };
+                let index = op.index();
+
+                if index == vcs_changed {
+                    let _ = op.recv(&self.vcs_changed_rx);
+                    break;
+                }
 
-            let (vc_id, channel) = channels[index];
-            match op.recv(channel) {
-                Ok(data) => {
-                    //println!("Received data on channel for VC ID {vc_id}: {data:?}");
+                let (vc_id, channel, source) = &channels[index];
+                match (op.recv(channel), source) {
+                    (Ok(data), DownlinkSource::Internal) => {
+                        self.send_internal_packet(bytes_tx.clone(), *vc_id, data);
+                    }
+                    (Ok(data), DownlinkSource::Playback) => {
+                        self.send_frame(bytes_tx.clone(), *vc_id, 0, 0, &data);
+                    }
+                    (Ok(data), DownlinkSource::VirtualChannel) => {
+                        //log::debug!("Received data on channel for VC ID {vc_id}: {data:?}");
 
-                    // TODO: Put it into a frame and send it to bytes_tx
-                    self.frame_and_send_virtual_channel_data(bytes_tx.clone(), *vc_id, &data);
+                        // TODO: Put it into a frame and send it to bytes_tx
+                        self.frame_and_send_virtual_channel_data(bytes_tx.clone(), *vc_id, &data);
+                    }
+                    (Err(_), _) => {
+                        log::warn!("Downlink channel of VC {} closed", vc_id);
+                        select.remove(index);
+                    }
                 }
-                Err(_) => todo!(),
             }
         }
     }
 
+    /// Periodically sends the link status housekeeping packet to `packets_tx`.
+    ///
+    /// Returns immediately if no link status packets are configured.
+    pub fn run_link_status(&self, packets_tx: Sender<Vec<u8>>) {
+        let Some(link_status) = &self.config.link_status else {
+            return;
+        };
+
+        let ticker = tick(Duration::from_millis(link_status.interval_ms));
+        let mut timestamp = TimestampHelper::new();
+        let mut msg_counter: u16 = 0;
+
+        loop {
+            let _ = ticker.recv();
+
+            let mut stats = self.stats.lock().unwrap().clone();
+            stats.farm = self
+                .farms
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(vc_id, farm)| (*vc_id, FarmStatus::from(farm)))
+                .collect();
+
+            timestamp.update_from_now();
+            let packet = stats.to_housekeeping_tm(link_status, msg_counter, &timestamp);
+            msg_counter = msg_counter.wrapping_add(1);
+
+            if packets_tx.send(packet).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Sends an "Only Idle Data" frame on the reserved idle VC, its data zone filled with idle data.
+    pub fn send_idle_frame(&self, bytes_tx: Sender<Vec<u8>>) {
+        let idle_data_len = self
+            .config
+            .frames
+            .out
+            .data_zone_len(IDLE_TFDF_HEADER_LEN)
+            .unwrap_or(0);
+        let idle_data = vec![IDLE_DATA_PATTERN; idle_data_len];
+        self.send_frame(
+            bytes_tx,
+            IDLE_VC_ID,
+            IDLE_TFDZ_CONSTRUCTION_RULES,
+            IDLE_PROTOCOL_ID,
+            &idle_data,
+        );
+    }
+
+    /// Returns the next VC frame count for `vc_id`, wrapping around at the configured counter length.
+    fn next_vc_frame_count(&self, vc_id: VcId) -> u64 {
+        let count_bits = 8 * self.config.frames.out.vc_frame_count_length as u32;
+        let mut counts = self.vc_frame_counts.lock().unwrap();
+        let count = counts.entry(vc_id).or_insert(0);
+
+        let current = *count;
+        *count = match 1u64.checked_shl(count_bits) {
+            Some(modulus) => (current + 1) % modulus,
+            None => current.wrapping_add(1),
+        };
+        current
+    }
+
     pub fn frame_and_send_virtual_channel_data(
         &self,
         bytes_tx: Sender<Vec<u8>>,
         vc_id: VcId,
         data: &[u8],
     ) {
-        let mut data_with_hdr = Vec::new();
-
-        let data = if vc_id == 1 {
+        let (wrap_apid, packet_crc) = self
+            .virtual_channels
+            .read()
+            .unwrap()
+            .config(vc_id)
+            .map(|vc| (vc.framing.space_packet_apid, vc.framing.packet_crc.clone()))
+            .unwrap_or_default();
+
+        if let Some(apid) = wrap_apid {
             if data.len() < 2 {
                 return;
             }
-            let header = SpHeader::new(
-                PacketId::new(spacepackets::PacketType::Tm, false, 0x44),
-                PacketSequenceCtrl::new(spacepackets::SequenceFlags::Unsegmented, 0),
-                data.len() as u16,
-            );
+            log::debug!("Wrapping data in SpacePacket");
+            let frame_data = self.seq_counters.lock().unwrap().wrap(apid, data);
+            self.record(&frame_data);
+            self.send_frame(bytes_tx, vc_id, 0, 0, &frame_data);
+            return;
+        }
 
-            println!("Wrapping data in SpacePacket");
-            data_with_hdr.extend_from_slice(&header.to_vec());
-            data_with_hdr.extend_from_slice(&data);
+        // Packets produced by the applications may be routed to another VC
+        let routed = self.router.read().unwrap().route(vc_id, data);
+        for (target_vc_id, mut frame_data) in routed {
+            // Set the CCSDS sequence count of the packets produced by the applications
+            self.seq_counters
+                .lock()
+                .unwrap()
+                .stamp_packets(&mut frame_data, &packet_crc);
+
+            self.record(&frame_data);
+            self.send_frame(bytes_tx.clone(), target_vc_id, 0, 0, &frame_data);
+        }
+    }
+
+    /// Sends a space packet generated by the comm application on `vc_id`.
+    fn send_internal_packet(&self, bytes_tx: Sender<Vec<u8>>, vc_id: VcId, mut packet: Vec<u8>) {
+        // The comm application only generates PUS packets, which all have a CRC
+        self.seq_counters
+            .lock()
+            .unwrap()
+            .stamp_packets(&mut packet, &PacketCrc::All(true));
+        self.record(&packet);
+        self.send_frame(bytes_tx, vc_id, 0, 0, &packet);
+    }
 
-            &data_with_hdr
+    /// Records downlink packets in the packet stores, as they are sent.
+    fn record(&self, packets: &[u8]) {
+        if let Some(storage) = &self.storage {
+            storage.stores().record(packets);
+        }
+    }
+
+    /// Frames and sends `data`, with SDLS protection if the VC has a downlink security association.
+    fn send_frame(
+        &self,
+        bytes_tx: Sender<Vec<u8>>,
+        vc_id: VcId,
+        tfdz_construction_rules: u8,
+        protocol_id: u8,
+        data: &[u8],
+    ) {
+        let out = &self.config.frames.out;
+        let idle = tfdz_construction_rules == IDLE_TFDZ_CONSTRUCTION_RULES;
+        let tfdf_header_len = if idle {
+            IDLE_TFDF_HEADER_LEN
+        } else {
+            TFDF_HEADER_LEN
+        };
+        let security = self.security.as_ref().filter(|_| !idle);
+        let protection_overhead =
+            security.map_or(0, |security| security.lock().unwrap().protection_overhead(vc_id));
+        let frame_data_len = data.len() + protection_overhead;
+
+        // The frame length is checked against the headers when loading the config
+        let data_zone_len = out.data_zone_len(tfdf_header_len).unwrap_or(0);
+        if frame_data_len > data_zone_len {
+            log::error!(
+                "Dropping {} bytes of data for VC {}, frames only hold {} bytes",
+                frame_data_len,
+                vc_id,
+                data_zone_len
+            );
+            return;
+        }
+        let vc_frame_count = self.next_vc_frame_count(vc_id);
+
+        // Protected data is written into the serialized frame, since the frame headers are
+        // authenticated along with it. Until then, the frame holds a placeholder of its length.
+        let placeholder;
+        let frame_data = if protection_overhead > 0 {
+            placeholder = vec![0u8; frame_data_len];
+            &placeholder[..]
         } else {
             data
         };
 
-        let mut frame: USLPTransferPaket<0, 512> = USLPTransferPaket::construct_final_frame(
-            12,
-            0xab,
+        let frame = USLPTransferPaket::<0, MAX_FRAME_DATA_LEN>::construct_final_frame(
+            out.tfvn,
+            self.config.frames.spacecraft_id,
             true,
             vc_id.into(),
             0,
             false,
-            522, //data.len() as u16,
+            out.frame_length,
             false,
             false,
             0,
-            false,
-            1,
-            1,
+            out.ocf,
+            out.vc_frame_count_length,
+            vc_frame_count as _,
             [],
+            tfdz_construction_rules,
+            protocol_id,
             0,
+            frame_data,
             0,
             0,
-            data,
-            0,
-            0,
-        )
-        .expect("USLP creation failed");
-
+        );
+        let Ok(mut frame) = frame else {
+            log::error!("Could not create frame for VC {}", vc_id);
+            return;
+        };
 
         let mut buf = [0u8; 65536];
         match frame.to_bytes(&mut buf) {
-            Ok(size) => {
-                bytes_tx.send(Vec::from(&buf[0..size])).unwrap();
+            Ok(mut size) => {
+                if let Some(security) = security.filter(|_| protection_overhead > 0) {
+                    let data_start = out.primary_header_len() + tfdf_header_len;
+                    let protected =
+                        match security.lock().unwrap().protect(vc_id, &buf[..data_start], data) {
+                            Ok(protected) => protected,
+                            Err(e) => {
+                                log::error!("Could not apply SDLS protection for VC {}: {}", vc_id, e);
+                                return;
+                            }
+                        };
+                    buf[data_start..data_start + protected.len()].copy_from_slice(&protected);
+                }
+
+                if out.fecf {
+                    let crc = CRC_CCITT_FALSE.checksum(&buf[0..size]);
+                    buf[size..size + 2].copy_from_slice(&crc.to_be_bytes());
+                    size += 2;
+                }
+
+                let bytes = match &out.coding {
+                    None => Vec::from(&buf[0..size]),
+                    Some(coding) => match cadu::encode(coding, &buf[0..size]) {
+                        Ok(cadu) => cadu,
+                        Err(e) => {
+                            log::error!("Could not encode frame of {size} bytes as CADU: {e}");
+                            return;
+                        }
+                    },
+                };
+                if bytes_tx.send(bytes).is_err() {
+                    log::error!("Downlink transport closed, dropping frame of VC {}", vc_id);
+                    return;
+                }
+                self.stats.lock().unwrap().count_frame_sent(vc_id);
             }
-            Err(_) => todo!(),
+            Err(_) => log::error!("Could not serialize frame of VC {}", vc_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Instant};
+
+    use crate::config::{Config, IdleFramesConfig};
+    use rccn_usr::transport::ros2::new_shared_ros2_node;
+
+    use super::*;
+
+    #[test]
+    fn test_idle_frames_fill_the_configured_frame_length() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("etc/config.yaml");
+        let mut config = Config::from_file(path).unwrap().master_channels.remove(0);
+        config.frames.out.frame_length = 99;
+        config.frames.out.fecf = true;
+        config.frames.out.idle_frames = Some(IdleFramesConfig { interval_ms: 20 });
+
+        let node = new_shared_ros2_node("test_idle_frames", "/").unwrap();
+        let virtual_channels = VirtualChannels::start(node, &[]).unwrap();
+        let processor = FrameProcessor::new(Arc::new(config), virtual_channels, None).unwrap();
+
+        let (bytes_tx, bytes_rx) = bounded(16);
+        std::thread::spawn(move || processor.process_frames_out(bytes_tx, &[]));
+
+        let start = Instant::now();
+        for _ in 0..5 {
+            let frame = bytes_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(frame.len(), 100);
+            assert_eq!(u16::from_be_bytes([frame[4], frame[5]]), 99);
+
+            let vc_id = ((frame[2] & 0x07) << 3) | (frame[3] >> 5);
+            assert_eq!(vc_id, IDLE_VC_ID);
+            assert_eq!(CRC_CCITT_FALSE.checksum(&frame), 0);
         }
+        // One frame per tick, the first one after the first interval
+        assert!(start.elapsed() >= Duration::from_millis(5 * 20));
     }
 }
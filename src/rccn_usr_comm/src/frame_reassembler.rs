@@ -0,0 +1,307 @@
+//! Reassembly of TC transfer frames from a byte stream.
+//!
+//! The input transports deliver data in arbitrary chunks: a read can contain part of a
+//! frame, several frames, or garbage between frames. The reassembler buffers the stream
+//! and uses the frame length field of the TC primary header to cut it into frames.
+//! When the data at the start of the buffer can not be the beginning of a valid frame,
+//! it skips one byte and tries again until it finds a plausible frame header.
+//!
+//! Without a frame error control field, only the header fields can be checked, so
+//! resynchronization after random data is best effort. With the FECF enabled, every
+//! candidate frame is verified with its CRC before it is accepted. A false frame start
+//! can then delay the following frames until enough data for the claimed length has
+//! arrived, but no valid frame is lost.
+
+use spacepackets::CRC_CCITT_FALSE;
+
+pub const TC_PRIMARY_HEADER_LEN: usize = 5;
+pub const TC_MAX_FRAME_LEN: usize = 1024;
+const FECF_LEN: usize = 2;
+
+pub struct FrameReassembler {
+    buffer: Vec<u8>,
+    /// Start of the unprocessed data in `buffer`, the bytes before it are removed on the next push
+    start: usize,
+    fecf: bool,
+    discarded_bytes: u64,
+    crc_errors: u64,
+}
+
+impl FrameReassembler {
+    /// Creates a new reassembler. If `fecf` is set, frames are expected to end with a
+    /// CRC-16 frame error control field, which is used to detect false frame starts.
+    pub fn new(fecf: bool) -> Self {
+        Self {
+            buffer: Vec::with_capacity(2 * TC_MAX_FRAME_LEN),
+            start: 0,
+            fecf,
+            discarded_bytes: 0,
+            crc_errors: 0,
+        }
+    }
+
+    /// Appends received data to the stream.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The buffered data which has not been processed yet
+    fn pending(&self) -> &[u8] {
+        &self.buffer[self.start..]
+    }
+
+    /// Number of bytes skipped so far because they did not belong to a valid frame.
+    pub fn discarded_bytes(&self) -> u64 {
+        self.discarded_bytes
+    }
+
+    /// Number of frame candidates which had a plausible header, but failed the FECF check.
+    pub fn crc_errors(&self) -> u64 {
+        self.crc_errors
+    }
+
+    /// Returns the next complete frame in the stream, or `None` if more data is needed.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let frame_len = match self.candidate_frame_len()? {
+                Some(len) => len,
+                None => {
+                    self.skip_byte();
+                    continue;
+                }
+            };
+
+            let Some(frame) = self.pending().get(..frame_len) else {
+                // Wait for the rest of the frame
+                return None;
+            };
+
+            if self.fecf && CRC_CCITT_FALSE.checksum(frame) != 0 {
+                self.crc_errors += 1;
+                self.skip_byte();
+                continue;
+            }
+
+            let frame = frame.to_vec();
+            self.start += frame_len;
+            return Some(frame);
+        }
+    }
+
+    /// Checks whether the buffer starts with a plausible TC frame header.
+    ///
+    /// Returns `None` if not enough data is buffered to decide, `Some(None)` if the
+    /// header is invalid, and `Some(Some(len))` with the frame length otherwise.
+    fn candidate_frame_len(&self) -> Option<Option<usize>> {
+        let header = self.pending().get(..TC_PRIMARY_HEADER_LEN)?;
+
+        let version = header[0] >> 6;
+        let spare = (header[0] >> 2) & 0x03;
+        if version != 0 || spare != 0 {
+            return Some(None);
+        }
+
+        let frame_len = (u16::from_be_bytes([header[2], header[3]]) & 0x03FF) as usize + 1;
+        let min_len = TC_PRIMARY_HEADER_LEN + 1 + if self.fecf { FECF_LEN } else { 0 };
+        if frame_len < min_len {
+            return Some(None);
+        }
+
+        Some(Some(frame_len))
+    }
+
+    fn skip_byte(&mut self) {
+        self.start += 1;
+        self.discarded_bytes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal linear congruential generator, so the tests are deterministic
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+
+        fn below(&mut self, max: usize) -> usize {
+            self.next() as usize % max
+        }
+    }
+
+    fn tc_frame(vc_id: u8, seq: u8, data: &[u8], fecf: bool) -> Vec<u8> {
+        let spacecraft_id: u16 = 0xAB;
+        let len = TC_PRIMARY_HEADER_LEN + data.len() + if fecf { FECF_LEN } else { 0 };
+        let length_field = (len - 1) as u16;
+
+        let mut frame = vec![
+            (spacecraft_id >> 8) as u8 & 0x03,
+            spacecraft_id as u8,
+            (vc_id << 2) | (length_field >> 8) as u8,
+            length_field as u8,
+            seq,
+        ];
+        frame.extend_from_slice(data);
+        if fecf {
+            let crc = CRC_CCITT_FALSE.checksum(&frame);
+            frame.extend_from_slice(&crc.to_be_bytes());
+        }
+        frame
+    }
+
+    fn random_frames(rng: &mut Lcg, count: usize, fecf: bool) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| {
+                let len = 1 + rng.below(200);
+                let data: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+                tc_frame(rng.below(8) as u8, i as u8, &data, fecf)
+            })
+            .collect()
+    }
+
+    /// Feeds `stream` into a reassembler in random chunk sizes and collects all frames.
+    fn reassemble(rng: &mut Lcg, stream: &[u8], fecf: bool) -> Vec<Vec<u8>> {
+        let mut reassembler = FrameReassembler::new(fecf);
+        let mut frames = Vec::new();
+
+        let mut pos = 0;
+        while pos < stream.len() {
+            let chunk_len = (1 + rng.below(300)).min(stream.len() - pos);
+            reassembler.push(&stream[pos..pos + chunk_len]);
+            pos += chunk_len;
+
+            while let Some(frame) = reassembler.next_frame() {
+                frames.push(frame);
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn test_multiple_frames_in_one_read() {
+        let frames = [tc_frame(0, 0, &[1, 2, 3], false), tc_frame(1, 1, &[4], false)];
+
+        let mut reassembler = FrameReassembler::new(false);
+        reassembler.push(&frames.concat());
+
+        assert_eq!(reassembler.next_frame().as_ref(), Some(&frames[0]));
+        assert_eq!(reassembler.next_frame().as_ref(), Some(&frames[1]));
+        assert_eq!(reassembler.next_frame(), None);
+    }
+
+    #[test]
+    fn test_partial_frame_is_kept() {
+        let frame = tc_frame(0, 0, &[1, 2, 3, 4, 5], true);
+
+        let mut reassembler = FrameReassembler::new(true);
+        reassembler.push(&frame[..3]);
+        assert_eq!(reassembler.next_frame(), None);
+        reassembler.push(&frame[3..7]);
+        assert_eq!(reassembler.next_frame(), None);
+        reassembler.push(&frame[7..]);
+        assert_eq!(reassembler.next_frame(), Some(frame));
+        assert_eq!(reassembler.discarded_bytes(), 0);
+    }
+
+    #[test]
+    fn test_crc_errors_are_counted() {
+        // All bytes after the first one are invalid frame header starts
+        let mut corrupted = tc_frame(1, 0xFF, &[0xFF; 7], true);
+        let len = corrupted.len();
+        corrupted[len - 2..].copy_from_slice(&[0xFF, 0xFF]);
+        let valid = tc_frame(1, 0, &[1, 2, 3], true);
+
+        let mut reassembler = FrameReassembler::new(true);
+        reassembler.push(&corrupted);
+        reassembler.push(&valid);
+
+        assert_eq!(reassembler.next_frame(), Some(valid));
+        assert_eq!(reassembler.crc_errors(), 1);
+        assert_eq!(reassembler.discarded_bytes(), len as u64);
+    }
+
+    #[test]
+    fn test_randomly_fragmented_stream() {
+        for seed in 0..50 {
+            let mut rng = Lcg(seed);
+            let fecf = seed % 2 == 0;
+            let frames = random_frames(&mut rng, 20, fecf);
+
+            let reassembled = reassemble(&mut rng, &frames.concat(), fecf);
+            assert_eq!(reassembled, frames, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn test_resync_after_invalid_headers() {
+        // 0xFF can never start a TC frame (wrong version number)
+        let mut rng = Lcg(7);
+        let frames = random_frames(&mut rng, 10, false);
+
+        let mut stream = Vec::new();
+        for frame in &frames {
+            stream.extend(std::iter::repeat_n(0xFF, rng.below(20)));
+            stream.extend_from_slice(frame);
+        }
+
+        assert_eq!(reassemble(&mut rng, &stream, false), frames);
+    }
+
+    #[test]
+    fn test_long_garbage_is_skipped_in_one_read() {
+        // Skipping does not move the buffered data, which would take minutes for this much garbage
+        let garbage = vec![0xFF; 4 * 1024 * 1024];
+        let frame = tc_frame(2, 5, &[1, 2, 3], false);
+
+        let mut reassembler = FrameReassembler::new(false);
+        reassembler.push(&garbage);
+        reassembler.push(&frame);
+
+        assert_eq!(reassembler.next_frame(), Some(frame));
+        assert_eq!(reassembler.discarded_bytes(), garbage.len() as u64);
+        assert_eq!(reassembler.next_frame(), None);
+    }
+
+    #[test]
+    fn test_resync_after_random_garbage_with_fecf() {
+        for seed in 0..20 {
+            let mut rng = Lcg(seed);
+            let frames = random_frames(&mut rng, 10, true);
+
+            let mut stream = Vec::new();
+            let mut garbage_len = 0;
+            for frame in &frames {
+                let len = rng.below(50);
+                garbage_len += len;
+                stream.extend((0..len).map(|_| rng.next() as u8));
+                stream.extend_from_slice(frame);
+            }
+            // Idle fill after the last frame flushes out false frame starts in the garbage
+            stream.extend([0xFF; TC_MAX_FRAME_LEN]);
+
+            let mut reassembler = FrameReassembler::new(true);
+            reassembler.push(&stream);
+            let mut reassembled = Vec::new();
+            while let Some(frame) = reassembler.next_frame() {
+                reassembled.push(frame);
+            }
+
+            assert_eq!(reassembled, frames, "seed {seed}");
+            // Everything but the last few fill bytes, which are too short for a header
+            assert_eq!(
+                reassembler.discarded_bytes(),
+                (garbage_len + TC_MAX_FRAME_LEN - (TC_PRIMARY_HEADER_LEN - 1)) as u64
+            );
+        }
+    }
+}
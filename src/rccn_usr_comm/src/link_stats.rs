@@ -0,0 +1,253 @@
+//! Link statistics, reported to the ground as PUS housekeeping telemetry (TM[3,25]).
+//!
+//! The source data of the housekeeping packet is laid out as follows, all
+//! values big endian:
+//!
+//! ```text
+//! | structure ID (u16) | frames received (u32) |
+//! | rejected: wrong SCID, unknown VC, CRC, sequence, security, invalid (6 x u32) |
+//! | discarded bytes (u32) | VC data dropped (u32) |
+//! | N (u8) | N x (VC ID (u8), frames sent (u32)) |
+//! | M (u8) | M x (VC ID (u8), FARM state (u8), V(R) (u8), flags (u8), FARM-B counter (u8)) |
+//! ```
+//!
+//! The FARM flags contain the lockout flag in bit 0, the wait flag in bit 1 and the
+//! retransmit flag in bit 2.
+
+use std::collections::BTreeMap;
+
+use rccn_usr::{time::TimestampHelper, types::VcId};
+use serde::{Deserialize, Serialize};
+use spacepackets::{
+    ecss::{
+        tm::{PusTmCreator, PusTmSecondaryHeader},
+        WritablePusPacket,
+    },
+    PacketId, PacketSequenceCtrl, PacketType, SequenceFlags, SpHeader,
+};
+
+use crate::farm::{Farm, FarmState};
+
+const HOUSEKEEPING_SERVICE: u8 = 3;
+const HOUSEKEEPING_REPORT_SUBSERVICE: u8 = 25;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LinkStatusConfig {
+    /// APID of the link status packets
+    pub apid: u16,
+    /// VC the link status packets are sent on
+    pub vc_id: VcId,
+    /// Time between two link status packets in milliseconds
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// Housekeeping structure ID at the start of the packet
+    #[serde(default = "default_structure_id")]
+    pub structure_id: u16,
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+fn default_structure_id() -> u16 {
+    1
+}
+
+/// Uplink frames which were not passed on to a virtual channel, by reason
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RejectedFrames {
+    pub wrong_spacecraft_id: u32,
+    pub unknown_virtual_channel: u32,
+    /// Frame candidates with a plausible header which failed the FECF check
+    pub crc: u32,
+    /// Type-AD frames out of sequence, or any frame rejected by the FARM
+    pub sequence: u32,
+    /// Frames failing SDLS processing
+    pub security: u32,
+    /// Frames which could not be parsed
+    pub invalid: u32,
+}
+
+/// Snapshot of the FARM of one virtual channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct FarmStatus {
+    pub state: FarmState,
+    pub receiver_frame_seq: u8,
+    pub lockout: bool,
+    pub wait: bool,
+    pub retransmit: bool,
+    pub farm_b_counter: u8,
+}
+
+impl From<&Farm> for FarmStatus {
+    fn from(farm: &Farm) -> Self {
+        Self {
+            state: farm.state(),
+            receiver_frame_seq: farm.receiver_frame_seq(),
+            lockout: farm.state() == FarmState::Lockout,
+            wait: farm.state() == FarmState::Wait,
+            retransmit: farm.retransmit(),
+            farm_b_counter: farm.farm_b_counter(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LinkStats {
+    pub frames_received: u32,
+    pub rejected: RejectedFrames,
+    /// Bytes skipped on the uplink while searching for a frame start
+    pub discarded_bytes: u32,
+    /// Uplink frames dropped because the VC channel was full or closed
+    pub vc_data_dropped: u32,
+    pub frames_sent: BTreeMap<VcId, u32>,
+    pub farm: BTreeMap<VcId, FarmStatus>,
+}
+
+impl LinkStats {
+    pub fn count_frame_sent(&mut self, vc_id: VcId) {
+        let count = self.frames_sent.entry(vc_id).or_insert(0);
+        *count = count.wrapping_add(1);
+    }
+
+    /// Serializes the statistics into the housekeeping report source data.
+    pub fn to_bytes(&self, structure_id: u16) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&structure_id.to_be_bytes());
+
+        let rejected = &self.rejected;
+        for value in [
+            self.frames_received,
+            rejected.wrong_spacecraft_id,
+            rejected.unknown_virtual_channel,
+            rejected.crc,
+            rejected.sequence,
+            rejected.security,
+            rejected.invalid,
+            self.discarded_bytes,
+            self.vc_data_dropped,
+        ] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+
+        data.push(self.frames_sent.len() as u8);
+        for (vc_id, count) in &self.frames_sent {
+            data.push(*vc_id);
+            data.extend_from_slice(&count.to_be_bytes());
+        }
+
+        data.push(self.farm.len() as u8);
+        for (vc_id, farm) in &self.farm {
+            let flags = farm.lockout as u8 | (farm.wait as u8) << 1 | (farm.retransmit as u8) << 2;
+            data.extend_from_slice(&[
+                *vc_id,
+                farm.state as u8,
+                farm.receiver_frame_seq,
+                flags,
+                farm.farm_b_counter,
+            ]);
+        }
+
+        data
+    }
+
+    /// Creates the PUS TM[3,25] housekeeping report.
+    ///
+    /// The CCSDS sequence count is set when the packet is framed.
+    pub fn to_housekeeping_tm(
+        &self,
+        config: &LinkStatusConfig,
+        msg_counter: u16,
+        timestamp: &TimestampHelper,
+    ) -> Vec<u8> {
+        let data = self.to_bytes(config.structure_id);
+        let tm = PusTmCreator::new(
+            SpHeader::new(
+                PacketId::new(PacketType::Tm, true, config.apid),
+                PacketSequenceCtrl::new(SequenceFlags::Unsegmented, 0),
+                0,
+            ),
+            PusTmSecondaryHeader::new(
+                HOUSEKEEPING_SERVICE,
+                HOUSEKEEPING_REPORT_SUBSERVICE,
+                msg_counter,
+                0,
+                timestamp.stamp(),
+            ),
+            &data,
+            true,
+        );
+        tm.to_vec().expect("Link status packet does not fit")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::farm::FarmConfig;
+    use spacepackets::{
+        ecss::{
+            tm::{GenericPusTmSecondaryHeader, PusTmReader},
+            PusPacket,
+        },
+        CcsdsPacket,
+    };
+
+    fn stats() -> LinkStats {
+        let mut stats = LinkStats {
+            frames_received: 10,
+            rejected: RejectedFrames {
+                wrong_spacecraft_id: 1,
+                unknown_virtual_channel: 2,
+                crc: 3,
+                sequence: 4,
+                security: 5,
+                invalid: 6,
+            },
+            discarded_bytes: 300,
+            vc_data_dropped: 7,
+            ..Default::default()
+        };
+        stats.count_frame_sent(1);
+        stats.count_frame_sent(0);
+        stats.count_frame_sent(1);
+        stats
+            .farm
+            .insert(0, FarmStatus::from(&Farm::new(&FarmConfig { window_width: 10 })));
+        stats
+    }
+
+    #[test]
+    fn test_source_data_layout() {
+        let data = stats().to_bytes(0x1234);
+
+        let mut expected = vec![0x12, 0x34];
+        for value in [10u32, 1, 2, 3, 4, 5, 6, 300, 7] {
+            expected.extend_from_slice(&value.to_be_bytes());
+        }
+        expected.extend_from_slice(&[2, 0, 0, 0, 0, 1, 1, 0, 0, 0, 2]);
+        expected.extend_from_slice(&[1, 0, FarmState::Open as u8, 0, 0, 0]);
+
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_housekeeping_tm() {
+        let config = LinkStatusConfig {
+            apid: 0x50,
+            vc_id: 0,
+            interval_ms: 1000,
+            structure_id: 7,
+        };
+        let stats = stats();
+
+        let bytes = stats.to_housekeeping_tm(&config, 42, &TimestampHelper::new());
+        let (tm, _) = PusTmReader::new(&bytes, 8).unwrap();
+
+        assert_eq!(tm.apid(), 0x50);
+        assert_eq!(PusPacket::service(&tm), HOUSEKEEPING_SERVICE);
+        assert_eq!(PusPacket::subservice(&tm), HOUSEKEEPING_REPORT_SUBSERVICE);
+        assert_eq!(GenericPusTmSecondaryHeader::msg_counter(&tm), 42);
+        assert_eq!(tm.source_data(), stats.to_bytes(7));
+    }
+}
@@ -1,24 +1,210 @@
+use clap::Parser;
 use crossbeam_channel::bounded;
-use std::{sync::Arc, thread};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
 
-use config::Config;
-use frame_processor::FrameProcessor;
+use coding::cltu::CltuDecoder;
+use config::{Config, MasterChannelConfig};
+use frame_processor::{DownlinkSource, FrameProcessingResult, FrameProcessor};
+use packet_store::PacketStorage;
+use virtual_channels::VirtualChannels;
 use rccn_usr::transport::{
+    ros2::{new_shared_ros2_node, SharedNode},
     RxTransport::{self},
     TransportManager, TxTransport,
 };
 
+mod coding;
 mod config;
+mod farm;
 mod frame_processor;
+mod frame_reassembler;
+mod link_stats;
+mod packet_store;
+mod routing;
+mod sdls;
+mod sequence_counter;
+mod virtual_channels;
+
+/// How often the config file is checked for changes
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Frame-level communication between the spacecraft modem and the applications
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Config file, searched for in the default locations if not given
+    #[arg(short, long, env = "RCCN_USR_COMM_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Log level or env_logger filter, e.g. `debug` or `info,rccn_usr=warn`
+    #[arg(short, long, env = "RCCN_USR_COMM_LOG", default_value = "info")]
+    log_level: String,
+
+    /// Name of the ROS2 node, must be unique when running several instances
+    #[arg(long, env = "RCCN_USR_COMM_NODE_NAME", default_value = "rccn_usr_comm")]
+    node_name: String,
+
+    /// Only validate the config file and exit
+    #[arg(long)]
+    check: bool,
+
+    /// Print the effective config, including default values, and exit
+    #[arg(long)]
+    print_config: bool,
+
+    /// Don't reload the config when the file changes, reloading on SIGHUP still works
+    #[arg(long, env = "RCCN_USR_COMM_NO_WATCH")]
+    no_watch: bool,
+}
+
+/// A master channel with its frame processing threads running
+struct MasterChannel {
+    config: Arc<MasterChannelConfig>,
+    processor: FrameProcessor,
+    frame_process_handle: JoinHandle<FrameProcessingResult>,
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = Config::find_config_file()?;
-    println!("Using config file: {}", config_path.display());
-    let config = Arc::new(Config::from_file(config_path)?);
-    println!("Loaded configuration: {:#?}", config);
+    // Arguments after `--ros-args`, added by `ros2 run` and launch files, are left to ROS2
+    let args = Args::parse_from(std::env::args().take_while(|arg| arg != "--ros-args"));
+    env_logger::Builder::new()
+        .parse_filters(&args.log_level)
+        .init();
+
+    let config_path = Config::find_config_file(args.config.as_deref())?;
+    log::info!("Using config file: {}", config_path.display());
+    let config = match Config::from_file(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            // Print the message instead of the debug representation of the error
+            eprintln!("{}: {}", config_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    if args.print_config {
+        print!("{}", config.to_yaml()?);
+        return Ok(());
+    }
+    if args.check {
+        println!("Configuration is valid");
+        return Ok(());
+    }
+    log::debug!("Loaded configuration:\n{}", config.to_yaml()?);
+
+    // All master channels share one ROS2 node
+    let node = new_shared_ros2_node(&args.node_name, &"/")?;
+
+    let mut master_channels = Vec::new();
+    for mc in config.master_channels {
+        master_channels.push(start_master_channel(Arc::new(mc), node.clone())?);
+    }
+
+    // Reload the virtual channels and routes on SIGHUP or when the config file changes
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, reload_requested.clone())?;
+    let mut modified = modification_time(&config_path);
 
-    // Create transport manager and configure virtual channels
-    let mut transport_manager = TransportManager::new("rccn_usr_comm".into())?;
+    while !master_channels
+        .iter()
+        .all(|mc| mc.frame_process_handle.is_finished())
+    {
+        thread::sleep(CONFIG_POLL_INTERVAL);
+
+        let current = modification_time(&config_path);
+        let file_changed = !args.no_watch && current != modified;
+        if reload_requested.swap(false, Ordering::Relaxed) || file_changed {
+            modified = current;
+            log::info!("Reloading config file {}", config_path.display());
+            reload_config(&config_path, &mut master_channels);
+        }
+    }
+
+    for mc in master_channels {
+        if let Err(e) = mc.frame_process_handle.join() {
+            log::error!(
+                "Frame processing thread of master channel {:#x} panicked: {:?}",
+                mc.config.frames.spacecraft_id, e
+            );
+        }
+    }
+    Ok(())
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Applies the virtual channels and downlink routes in the config file at `path` to the running master channels.
+///
+/// Other changes, like the frame links or security associations, only take effect after a restart.
+/// The config of each master channel is updated with the applied changes.
+fn reload_config(path: &Path, master_channels: &mut [MasterChannel]) {
+    let config = match Config::from_file(path) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Keeping the current config, {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    for new in &config.master_channels {
+        let scid = new.frames.spacecraft_id;
+        let Some(mc) = master_channels
+            .iter_mut()
+            .find(|mc| mc.config.frames.spacecraft_id == scid)
+        else {
+            log::warn!("Master channel {:#x} was added, it is started after a restart", scid);
+            continue;
+        };
+
+        if mc.config.frames != new.frames
+            || mc.config.sdls != new.sdls
+            || mc.config.link_status != new.link_status
+            || mc.config.packet_stores != new.packet_stores
+        {
+            log::warn!(
+                "Master channel {:#x}: only virtual channel and route changes are applied without a restart",
+                scid
+            );
+        }
+
+        mc.processor.update_virtual_channels(&new.virtual_channels);
+        mc.processor.update_routes(&new.downlink_routes);
+
+        let mut applied = MasterChannelConfig::clone(&mc.config);
+        applied.virtual_channels = new.virtual_channels.clone();
+        applied.downlink_routes = new.downlink_routes.clone();
+        mc.config = Arc::new(applied);
+    }
+
+    for mc in master_channels {
+        let scid = mc.config.frames.spacecraft_id;
+        if !config
+            .master_channels
+            .iter()
+            .any(|new| new.frames.spacecraft_id == scid)
+        {
+            log::warn!("Master channel {:#x} was removed, it is stopped after a restart", scid);
+        }
+    }
+}
+
+/// Sets up the links and virtual channels of a master channel and starts its frame processing threads.
+fn start_master_channel(
+    config: Arc<MasterChannelConfig>,
+    node: SharedNode,
+) -> Result<MasterChannel, Box<dyn std::error::Error>> {
+    // Create transport manager for the frame links
+    let mut transport_manager = TransportManager::new_with_ros2_node(node.clone())?;
 
     // Create channel for communication between the bytes-in
     // frame link and the frame processing task.
@@ -30,7 +216,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let addr = udp_rx_transport.listen.clone().parse()?;
             transport_manager.add_udp_reader(bytes_in_tx, addr);
         }
-        RxTransport::Ros2(_ros2_rx_transport) => todo!(),
+        // Rejected when loading the config
+        RxTransport::Ros2(_) => return Err("ROS2 is not supported as frame link transport".into()),
+    };
+
+    // If the uplink delivers CLTUs, decode them in a separate thread
+    // and pass on the contained frames to the frame processing task.
+    let frames_in_rx = match &config.frames.r#in.cltu {
+        None => bytes_in_rx,
+        Some(cltu_config) => {
+            let (frames_in_tx, frames_in_rx) = bounded(32);
+            let decoder = CltuDecoder::new(cltu_config.randomize);
+            thread::spawn(move || decoder.run(bytes_in_rx, frames_in_tx));
+            frames_in_rx
+        }
     };
 
     // Create channel for communication between the frames-out
@@ -43,33 +242,50 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let addr = udp_tx_transport.send.clone().parse()?;
             transport_manager.add_udp_writer(bytes_out_rx, addr);
         }
-        TxTransport::Ros2(_) => {
-            todo!();
-        }
+        TxTransport::Ros2(_) => return Err("ROS2 is not supported as frame link transport".into()),
     };
 
-    // Configure all virtual channels
-    for vc in config.virtual_channels.iter() {
-        transport_manager.add_virtual_channel(vc)?;
-    }
+    let (_, _transport_handles) = transport_manager.run();
+
+    // Every virtual channel runs its own transports, so they can be changed at runtime
+    let virtual_channels = VirtualChannels::start(node, &config.virtual_channels)?;
+
+    // Downlink data which doesn't come from the applications
+    let mut downlink_sources = Vec::new();
 
-    // Get the virtual channel maps for frame processing
-    let ((vc_tx_map, vc_rx_map), _transport_handles) = transport_manager.run();
+    let storage = match &config.packet_stores {
+        None => None,
+        Some(stores_config) => {
+            let (tm_tx, tm_rx) = bounded(16);
+            let (playback_tx, playback_rx) = bounded(16);
+            downlink_sources.push((stores_config.tm_vc_id, tm_rx, DownlinkSource::Internal));
+            downlink_sources.push((stores_config.dump_vc_id, playback_rx, DownlinkSource::Playback));
+            Some(PacketStorage::open(stores_config, tm_tx, playback_tx)?)
+        }
+    };
 
     // Create frame processor and spawn processing threads
-    let processor = FrameProcessor::new(config);
+    let processor = FrameProcessor::new(config.clone(), virtual_channels, storage)?;
     let p_in = processor.clone();
     let p_out = processor.clone();
 
+    if let Some(link_status) = &config.link_status {
+        let (packets_tx, packets_rx) = bounded(4);
+        downlink_sources.push((link_status.vc_id, packets_rx, DownlinkSource::Internal));
+
+        let p_stats = processor.clone();
+        thread::spawn(move || p_stats.run_link_status(packets_tx));
+    }
+
     let frame_process_handle =
-        thread::spawn(move || p_in.process_incoming_frames(bytes_in_rx, &vc_tx_map));
+        thread::spawn(move || p_in.process_incoming_frames(frames_in_rx));
 
     let _frames_out_handle =
-        thread::spawn(move || p_out.process_frames_out(bytes_out_tx, &vc_rx_map));
+        thread::spawn(move || p_out.process_frames_out(bytes_out_tx, &downlink_sources));
 
-    // Wait for threads to complete
-    if let Err(e) = frame_process_handle.join() {
-        println!("Frame processing thread panicked: {:?}", e);
-    }
-    Ok(())
+    Ok(MasterChannel {
+        config,
+        processor,
+        frame_process_handle,
+    })
 }
@@ -0,0 +1,141 @@
+//! Commands of the storage and retrieval service (ST[15]).
+//!
+//! The application data of the commands is laid out as follows, all values big endian:
+//!
+//! ```text
+//! TC[15,1]   enable storage:              | store ID (u16) |
+//! TC[15,2]   disable storage:             | store ID (u16) |
+//! TC[15,9]   retrieve time range:         | store ID (u16) | start (u32) | end (u32) |
+//! TC[15,12]  report summary:              (no data)
+//! TC[15,17]  abort retrieval:             (no data)
+//! TC[15,128] retrieve sequence range:     | store ID (u16) | first (u64) | last (u64) |
+//! ```
+//!
+//! Times are seconds since the Unix epoch, both ends of a range are included.
+//! The sequence range retrieval is mission specific, it selects packets by their
+//! sequence number in the store.
+
+use rccn_usr::service::{CommandParseError, CommandParseResult, ServiceCommand};
+use satrs::spacepackets::ecss::{tc::PusTcReader, PusPacket};
+
+use super::store::RetrievalRange;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    EnableStorage(u16),
+    DisableStorage(u16),
+    StartRetrieval(u16, RetrievalRange),
+    ReportSummary,
+    AbortRetrieval,
+}
+
+/// Reads big endian values from the application data of a TC
+struct AppData<'a>(&'a [u8]);
+
+impl AppData<'_> {
+    fn take<const N: usize>(&mut self) -> CommandParseResult<[u8; N]> {
+        if self.0.len() < N {
+            return Err(CommandParseError::Other);
+        }
+        let (value, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(value.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> CommandParseResult<u16> {
+        self.take().map(u16::from_be_bytes)
+    }
+
+    fn u32(&mut self) -> CommandParseResult<u32> {
+        self.take().map(u32::from_be_bytes)
+    }
+
+    fn u64(&mut self) -> CommandParseResult<u64> {
+        self.take().map(u64::from_be_bytes)
+    }
+}
+
+impl ServiceCommand for Command {
+    fn from_pus_tc(tc: &PusTcReader) -> CommandParseResult<Self> {
+        let mut data = AppData(tc.app_data());
+
+        match tc.subservice() {
+            1 => Ok(Self::EnableStorage(data.u16()?)),
+            2 => Ok(Self::DisableStorage(data.u16()?)),
+            9 => {
+                let id = data.u16()?;
+                let start = data.u32()? as u64;
+                let end = data.u32()? as u64;
+                Ok(Self::StartRetrieval(
+                    id,
+                    RetrievalRange::Time {
+                        start_ms: start * 1000,
+                        end_ms: end * 1000 + 999,
+                    },
+                ))
+            }
+            12 => Ok(Self::ReportSummary),
+            17 => Ok(Self::AbortRetrieval),
+            128 => {
+                let id = data.u16()?;
+                let first = data.u64()?;
+                let last = data.u64()?;
+                Ok(Self::StartRetrieval(id, RetrievalRange::Sequence { first, last }))
+            }
+            _ => Err(CommandParseError::UnknownSubservice(tc.subservice())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use satrs::spacepackets::{
+        ecss::{
+            tc::{PusTcCreator, PusTcSecondaryHeader},
+            WritablePusPacket,
+        },
+        SpHeader,
+    };
+
+    fn parse(subservice: u8, app_data: &[u8]) -> CommandParseResult<Command> {
+        let tc = PusTcCreator::new(
+            SpHeader::new_for_unseg_tc(0x42, 0, 0),
+            PusTcSecondaryHeader::new_simple(15, subservice),
+            app_data,
+            true,
+        );
+        let bytes = tc.to_vec().unwrap();
+        let (reader, _) = PusTcReader::new(&bytes).unwrap();
+        Command::from_pus_tc(&reader)
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse(1, &[0, 3]).unwrap(), Command::EnableStorage(3));
+        assert_eq!(
+            parse(9, &[0, 1, 0, 0, 0, 10, 0, 0, 0, 20]).unwrap(),
+            Command::StartRetrieval(
+                1,
+                RetrievalRange::Time {
+                    start_ms: 10_000,
+                    end_ms: 20_999
+                }
+            )
+        );
+
+        let mut data = vec![0, 2];
+        data.extend_from_slice(&5u64.to_be_bytes());
+        data.extend_from_slice(&7u64.to_be_bytes());
+        assert_eq!(
+            parse(128, &data).unwrap(),
+            Command::StartRetrieval(2, RetrievalRange::Sequence { first: 5, last: 7 })
+        );
+
+        assert!(matches!(parse(9, &[0, 1, 0]), Err(CommandParseError::Other)));
+        assert!(matches!(
+            parse(3, &[]),
+            Err(CommandParseError::UnknownSubservice(3))
+        ));
+    }
+}
@@ -0,0 +1,454 @@
+//! Onboard packet stores, so that telemetry produced outside of a ground pass is not lost.
+//!
+//! Every store continuously records the downlink packets matching its filters into a bounded,
+//! circular set of files on disk. A time or sequence range of a store can be played back onto the
+//! dump VC, either through the [`PacketStores`] API or with PUS ST[15] commands, which are handled
+//! by the built-in [`service::StorageAndRetrievalService`].
+
+pub mod command;
+pub mod service;
+pub mod store;
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use rccn_usr::{
+    service::{PusAppBase, PusService},
+    types::VcId,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::routing::PacketFilter;
+use crate::sequence_counter::split_packets;
+use service::StorageAndRetrievalService;
+use store::{PacketStore, RetrievalRange, StoreError};
+
+/// Component ID of the storage and retrieval service in verification reports
+const STORAGE_SERVICE_COMPONENT_ID: u64 = 15;
+
+/// Number of downlink packet batches waiting to be recorded before further ones are dropped
+pub const RECORD_QUEUE_LEN: usize = 256;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PacketStoresConfig {
+    /// Directory holding one subdirectory per store
+    pub directory: PathBuf,
+    /// APID of the ST[15] commands handled by the comm application, and of their replies
+    pub service_apid: u16,
+    /// VC the ST[15] verification and report packets are sent on
+    pub tm_vc_id: VcId,
+    /// VC retrieved packets are played back on
+    pub dump_vc_id: VcId,
+    pub stores: Vec<PacketStoreConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PacketStoreConfig {
+    /// Store ID used in ST[15] commands
+    pub id: u16,
+    /// Size limit of the store, the oldest packets are deleted when it is exceeded
+    pub max_bytes: u64,
+    /// Size of the files the store is made of, the unit in which old packets are deleted
+    #[serde(default = "default_segment_bytes")]
+    pub segment_bytes: u64,
+    /// A packet is recorded if it matches any of the filters
+    pub packets: Vec<PacketFilter>,
+    /// Whether the store records packets after startup
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_segment_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Error, Debug)]
+pub enum PacketStoreError {
+    #[error("Packet store {0} is not configured")]
+    UnknownStore(u16),
+    #[error("A retrieval is already running")]
+    RetrievalRunning,
+    #[error("Packet store {0}: {1}")]
+    Store(u16, StoreError),
+}
+
+/// State of a packet store, as reported in the ST[15] summary report
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreSummary {
+    pub id: u16,
+    pub enabled: bool,
+    pub used_bytes: u64,
+    pub max_bytes: u64,
+    /// Sequence number of the oldest packet in the store
+    pub first_seq: u64,
+    /// Sequence number the next recorded packet will get
+    pub next_seq: u64,
+    /// Time of the newest packet, in ms since the Unix epoch
+    pub newest_time_ms: Option<u64>,
+}
+
+struct RecordingStore {
+    config: PacketStoreConfig,
+    store: PacketStore,
+    enabled: bool,
+}
+
+struct Retrieval {
+    abort: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+type Stores = Arc<Mutex<BTreeMap<u16, RecordingStore>>>;
+
+/// The packet stores of a master channel
+///
+/// Packets are written to disk by a recording thread, so that a slow disk does not hold up the
+/// downlink. The thread stops when the stores are dropped.
+pub struct PacketStores {
+    stores: Stores,
+    record_tx: Sender<(u64, Vec<u8>)>,
+    dropped_packets: AtomicU64,
+    playback_tx: Sender<Vec<u8>>,
+    retrieval: Mutex<Option<Retrieval>>,
+}
+
+impl PacketStores {
+    /// Opens all configured stores, retrieved packets are sent to `playback_tx`.
+    pub fn open(
+        config: &PacketStoresConfig,
+        playback_tx: Sender<Vec<u8>>,
+    ) -> Result<Self, PacketStoreError> {
+        let mut stores = BTreeMap::new();
+        for store_config in &config.stores {
+            let directory = config.directory.join(store_config.id.to_string());
+            let store = PacketStore::open(
+                &directory,
+                store_config.max_bytes,
+                store_config.segment_bytes,
+            )
+            .map_err(|e| PacketStoreError::Store(store_config.id, e))?;
+
+            stores.insert(
+                store_config.id,
+                RecordingStore {
+                    config: store_config.clone(),
+                    store,
+                    enabled: store_config.enabled,
+                },
+            );
+        }
+
+        let stores = Arc::new(Mutex::new(stores));
+        let (record_tx, record_rx) = crossbeam_channel::bounded(RECORD_QUEUE_LEN);
+        let recorded_stores = stores.clone();
+        thread::spawn(move || record_packets(recorded_stores, record_rx));
+
+        Ok(Self {
+            stores,
+            record_tx,
+            dropped_packets: AtomicU64::new(0),
+            playback_tx,
+            retrieval: Mutex::new(None),
+        })
+    }
+
+    /// Queues the packets in `data` for recording in every enabled store with a matching filter.
+    ///
+    /// Data which is not a sequence of complete space packets is not recorded. If the recording
+    /// thread falls behind and the queue is full, the packets are dropped and counted.
+    pub fn record(&self, data: &[u8]) {
+        let Some(packet_lengths) = split_packets(data) else {
+            return;
+        };
+        match self.record_tx.try_send((now_ms(), data.to_vec())) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self
+                    .dropped_packets
+                    .fetch_add(packet_lengths.len() as u64, Ordering::Relaxed)
+                    + packet_lengths.len() as u64;
+                log::warn!(
+                    "Packet recording queue full, dropped {} packets ({} in total)",
+                    packet_lengths.len(),
+                    dropped
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                log::error!("Packet recording thread stopped, packets are not recorded");
+            }
+        }
+    }
+
+    /// Number of packets dropped because the recording queue was full
+    pub fn dropped_packets(&self) -> u64 {
+        self.dropped_packets.load(Ordering::Relaxed)
+    }
+
+    /// Starts or stops recording in a store.
+    pub fn set_enabled(&self, id: u16, enabled: bool) -> Result<(), PacketStoreError> {
+        let mut stores = self.stores.lock().unwrap();
+        let store = stores
+            .get_mut(&id)
+            .ok_or(PacketStoreError::UnknownStore(id))?;
+        store.enabled = enabled;
+        log::info!(
+            "Packet store {} {}",
+            id,
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
+
+    /// Starts playing back the packets in `range` of a store on the dump VC.
+    ///
+    /// Only one retrieval runs at a time. Packets recorded while the retrieval runs
+    /// are played back as well if they are in the range.
+    pub fn start_retrieval(&self, id: u16, range: RetrievalRange) -> Result<(), PacketStoreError> {
+        let mut retrieval = self.retrieval.lock().unwrap();
+        if retrieval.as_ref().is_some_and(|r| !r.handle.is_finished()) {
+            return Err(PacketStoreError::RetrievalRunning);
+        }
+
+        let mut reader = self
+            .stores
+            .lock()
+            .unwrap()
+            .get(&id)
+            .ok_or(PacketStoreError::UnknownStore(id))?
+            .store
+            .reader(range);
+
+        let abort = Arc::new(AtomicBool::new(false));
+        let abort_requested = abort.clone();
+        let playback_tx = self.playback_tx.clone();
+        log::info!("Starting retrieval of {:?} from packet store {}", range, id);
+
+        let handle = thread::spawn(move || {
+            let mut count = 0;
+            while !abort_requested.load(Ordering::Relaxed) {
+                match reader.next_record() {
+                    Ok(Some(record)) => {
+                        // Blocks while the downlink is busy, so the playback is paced by the link
+                        if playback_tx.send(record.packet).is_err() {
+                            return;
+                        }
+                        count += 1;
+                    }
+                    Ok(None) => {
+                        log::info!("Retrieval from packet store {} done, {} packets", id, count);
+                        return;
+                    }
+                    Err(e) => {
+                        log::error!("Retrieval from packet store {} failed: {}", id, e);
+                        return;
+                    }
+                }
+            }
+            log::info!("Retrieval from packet store {} aborted after {} packets", id, count);
+        });
+
+        *retrieval = Some(Retrieval { abort, handle });
+        Ok(())
+    }
+
+    /// Stops the running retrieval, returns `false` if there was none.
+    pub fn abort_retrieval(&self) -> bool {
+        match self.retrieval.lock().unwrap().take() {
+            Some(r) if !r.handle.is_finished() => {
+                r.abort.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn summary(&self) -> Vec<StoreSummary> {
+        self.stores
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, s)| StoreSummary {
+                id: *id,
+                enabled: s.enabled,
+                used_bytes: s.store.used_bytes(),
+                max_bytes: s.config.max_bytes,
+                first_seq: s.store.first_seq(),
+                next_seq: s.store.next_seq(),
+                newest_time_ms: s.store.newest_time_ms(),
+            })
+            .collect()
+    }
+}
+
+/// Appends the queued packets to the stores until the [`PacketStores`] are dropped.
+fn record_packets(stores: Stores, record_rx: Receiver<(u64, Vec<u8>)>) {
+    for (time_ms, data) in record_rx {
+        let Some(packet_lengths) = split_packets(&data) else {
+            continue;
+        };
+
+        let mut stores = stores.lock().unwrap();
+        let mut offset = 0;
+        for len in packet_lengths {
+            let packet = &data[offset..offset + len];
+            offset += len;
+
+            for (id, s) in stores.iter_mut() {
+                if !s.enabled || !s.config.packets.iter().any(|f| f.matches(packet)) {
+                    continue;
+                }
+                if let Err(e) = s.store.append(time_ms, packet) {
+                    log::error!("Could not record packet in store {}: {}", id, e);
+                }
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// The packet stores of a master channel together with their ST[15] service
+pub struct PacketStorage {
+    stores: Arc<PacketStores>,
+    service: Mutex<StorageAndRetrievalService>,
+    app: PusAppBase,
+    tm_tx: Sender<Vec<u8>>,
+}
+
+impl PacketStorage {
+    /// Opens the packet stores. Service replies are sent to `tm_tx`, retrieved packets to `playback_tx`.
+    pub fn open(
+        config: &PacketStoresConfig,
+        tm_tx: Sender<Vec<u8>>,
+        playback_tx: Sender<Vec<u8>>,
+    ) -> Result<Self, PacketStoreError> {
+        let stores = Arc::new(PacketStores::open(config, playback_tx)?);
+        Ok(Self {
+            service: Mutex::new(StorageAndRetrievalService::new(stores.clone())),
+            stores,
+            app: PusAppBase::new(config.service_apid, STORAGE_SERVICE_COMPONENT_ID),
+            tm_tx,
+        })
+    }
+
+    pub fn stores(&self) -> &Arc<PacketStores> {
+        &self.stores
+    }
+
+    /// Handles the ST[15] commands in uplink data and returns the remaining packets.
+    ///
+    /// All TCs on the service APID are taken out, TCs for other services are dropped.
+    /// Data which is not a sequence of complete space packets is returned unchanged.
+    pub fn handle_uplink(&self, data: Vec<u8>) -> Vec<u8> {
+        let Some(packet_lengths) = split_packets(&data) else {
+            return data;
+        };
+
+        let mut remaining = Vec::with_capacity(data.len());
+        let mut offset = 0;
+        for len in packet_lengths {
+            let packet = &data[offset..offset + len];
+            offset += len;
+
+            let apid = u16::from_be_bytes([packet[0], packet[1]]) & 0x7FF;
+            let is_tc = packet[0] & 0x10 != 0;
+            if !is_tc || apid != self.app.apid {
+                remaining.extend_from_slice(packet);
+                continue;
+            }
+
+            let reply = self
+                .app
+                .new_reply(StorageAndRetrievalService::service(), self.tm_tx.clone());
+            if let Err(e) = self.service.lock().unwrap().handle_tc_bytes(packet, reply) {
+                log::warn!("Could not handle packet storage TC: {:?}", e);
+            }
+        }
+
+        remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn next_seq(stores: &PacketStores) -> u64 {
+        stores.summary()[0].next_seq
+    }
+
+    fn wait_for_next_seq(stores: &PacketStores, seq: u64) {
+        let start = Instant::now();
+        while next_seq(stores) < seq {
+            assert!(start.elapsed() < Duration::from_secs(5), "packets not recorded");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_full_record_queue_drops_packets() {
+        let directory = std::env::temp_dir().join(format!(
+            "rccn_usr_comm_stores_record_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        let config = PacketStoresConfig {
+            directory: directory.clone(),
+            service_apid: 15,
+            tm_vc_id: 0,
+            dump_vc_id: 1,
+            stores: vec![PacketStoreConfig {
+                id: 1,
+                max_bytes: 1024 * 1024,
+                segment_bytes: default_segment_bytes(),
+                packets: vec![PacketFilter::default()],
+                enabled: true,
+            }],
+        };
+        let (playback_tx, _playback_rx) = crossbeam_channel::unbounded();
+        let stores = PacketStores::open(&config, playback_tx).unwrap();
+        let packet = [0x08, 0x10, 0xC0, 0x00, 0x00, 0x03, 1, 2, 3, 4];
+
+        stores.record(&packet);
+        wait_for_next_seq(&stores, 1);
+        assert_eq!(stores.dropped_packets(), 0);
+
+        // Stall the recording thread, so that the queue fills up
+        let count = RECORD_QUEUE_LEN as u64 + 2;
+        {
+            let _stalled = stores.stores.lock().unwrap();
+            for _ in 0..count {
+                stores.record(&packet);
+            }
+        }
+        let dropped = stores.dropped_packets();
+        assert!(dropped >= 1);
+
+        wait_for_next_seq(&stores, 1 + count - dropped);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(next_seq(&stores), 1 + count - dropped);
+
+        drop(stores);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
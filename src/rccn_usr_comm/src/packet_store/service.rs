@@ -0,0 +1,115 @@
+//! Storage and retrieval service (ST[15]) for the packet stores of the comm application.
+//!
+//! The summary report TM[15,13] is laid out as follows, all values big endian:
+//!
+//! ```text
+//! | N (u8) | N x (store ID (u16), enabled (u8), fill percentage (u8),
+//!                 first sequence number (u64), next sequence number (u64),
+//!                 newest packet time in seconds since the Unix epoch, 0 if empty (u32)) |
+//! ```
+//!
+//! Failed commands report one of the [`StorageFailureCode`]s, with the store ID (u16) as
+//! failure data where the failure concerns a single store.
+
+use std::sync::Arc;
+
+use rccn_usr::service::{
+    AcceptanceResult, AcceptedTc, CommandFailure, PusService, SubserviceTmData,
+};
+
+use super::{command::Command, PacketStoreError, PacketStores, StoreSummary};
+
+const STORAGE_SERVICE: u8 = 15;
+const SUMMARY_REPORT_SUBSERVICE: u8 = 13;
+
+pub struct StorageAndRetrievalService {
+    stores: Arc<PacketStores>,
+}
+
+impl StorageAndRetrievalService {
+    pub fn new(stores: Arc<PacketStores>) -> Self {
+        Self { stores }
+    }
+}
+
+/// Failure codes of the ST[15] verification failure reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum StorageFailureCode {
+    UnknownStore = 1,
+    RetrievalRunning = 2,
+    StoreError = 3,
+    NoRetrievalRunning = 4,
+}
+
+/// Logs a failed request.
+fn logged(result: Result<(), PacketStoreError>) -> Result<(), PacketStoreError> {
+    result.inspect_err(|e| log::warn!("Packet store command failed: {}", e))
+}
+
+impl From<PacketStoreError> for CommandFailure {
+    fn from(e: PacketStoreError) -> Self {
+        match e {
+            PacketStoreError::UnknownStore(id) => {
+                CommandFailure::new(StorageFailureCode::UnknownStore as u8, id.to_be_bytes())
+            }
+            PacketStoreError::RetrievalRunning => {
+                CommandFailure::new(StorageFailureCode::RetrievalRunning as u8, [])
+            }
+            PacketStoreError::Store(id, _) => {
+                CommandFailure::new(StorageFailureCode::StoreError as u8, id.to_be_bytes())
+            }
+        }
+    }
+}
+
+fn summary_report(summary: &[StoreSummary]) -> Vec<u8> {
+    let mut data = vec![summary.len() as u8];
+    for store in summary {
+        let fill = (store.used_bytes * 100 / store.max_bytes.max(1)).min(100) as u8;
+        let newest = store.newest_time_ms.map_or(0, |t| (t / 1000) as u32);
+
+        data.extend_from_slice(&store.id.to_be_bytes());
+        data.push(store.enabled as u8);
+        data.push(fill);
+        data.extend_from_slice(&store.first_seq.to_be_bytes());
+        data.extend_from_slice(&store.next_seq.to_be_bytes());
+        data.extend_from_slice(&newest.to_be_bytes());
+    }
+    data
+}
+
+impl PusService for StorageAndRetrievalService {
+    type CommandT = Command;
+
+    fn service() -> u8 {
+        STORAGE_SERVICE
+    }
+
+    fn handle_tc(&mut self, mut tc: AcceptedTc, cmd: Self::CommandT) -> AcceptanceResult {
+        match cmd {
+            Command::EnableStorage(id) => {
+                tc.handle_result(|| logged(self.stores.set_enabled(id, true)))
+            }
+            Command::DisableStorage(id) => {
+                tc.handle_result(|| logged(self.stores.set_enabled(id, false)))
+            }
+            Command::StartRetrieval(id, range) => {
+                tc.handle_result(|| logged(self.stores.start_retrieval(id, range)))
+            }
+            Command::AbortRetrieval => tc.handle_result(|| {
+                if self.stores.abort_retrieval() {
+                    Ok(())
+                } else {
+                    Err(CommandFailure::new(StorageFailureCode::NoRetrievalRunning as u8, []))
+                }
+            }),
+            Command::ReportSummary => tc.handle_with_tm(|| {
+                Ok::<_, CommandFailure>(SubserviceTmData {
+                    subservice: SUMMARY_REPORT_SUBSERVICE,
+                    data: summary_report(&self.stores.summary()),
+                })
+            }),
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! A bounded, circular packet store on disk.
+//!
+//! The store is a directory of segment files, named after the sequence number of their first
+//! record so that they sort in recording order. Records are appended to the newest segment
+//! until it is full, then a new segment is started. When the store exceeds its size limit,
+//! the oldest segments are deleted.
+//!
+//! Each record is laid out as follows, all values big endian:
+//!
+//! ```text
+//! | sequence number (u64) | time, ms since the Unix epoch (u64) | packet length (u32) | packet |
+//! ```
+//!
+//! The sequence number counts the records of the store and continues after a restart.
+//! A record which was cut off, e.g. by a power loss, is dropped when the store is opened.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+const RECORD_HEADER_LEN: usize = 20;
+const SEGMENT_EXTENSION: &str = "seg";
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Packet of {0} bytes does not fit into a segment")]
+    PacketTooLarge(usize),
+}
+
+/// A stored packet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub seq: u64,
+    pub time_ms: u64,
+    pub packet: Vec<u8>,
+}
+
+/// Selects the records to retrieve from a store, both ends inclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetrievalRange {
+    Time { start_ms: u64, end_ms: u64 },
+    Sequence { first: u64, last: u64 },
+}
+
+impl RetrievalRange {
+    fn contains(&self, record: &Record) -> bool {
+        match *self {
+            Self::Time { start_ms, end_ms } => (start_ms..=end_ms).contains(&record.time_ms),
+            Self::Sequence { first, last } => (first..=last).contains(&record.seq),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    first_seq: u64,
+    len: u64,
+}
+
+pub struct PacketStore {
+    directory: PathBuf,
+    max_bytes: u64,
+    segment_bytes: u64,
+    /// Segments from oldest to newest
+    segments: Vec<Segment>,
+    /// Open handle of the newest segment
+    writer: Option<File>,
+    next_seq: u64,
+    /// Time of the newest record
+    newest_time_ms: Option<u64>,
+}
+
+impl PacketStore {
+    /// Opens the store in `directory`, creating it if necessary.
+    pub fn open(directory: &Path, max_bytes: u64, segment_bytes: u64) -> Result<Self, StoreError> {
+        fs::create_dir_all(directory)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == SEGMENT_EXTENSION) {
+                let first_seq = path.file_stem().and_then(|s| s.to_str()?.parse().ok());
+                if let Some(first_seq) = first_seq {
+                    let len = fs::metadata(&path)?.len();
+                    segments.push(Segment {
+                        path,
+                        first_seq,
+                        len,
+                    });
+                }
+            }
+        }
+        segments.sort_by_key(|s| s.first_seq);
+
+        let mut store = Self {
+            directory: directory.to_path_buf(),
+            max_bytes,
+            segment_bytes,
+            segments,
+            writer: None,
+            next_seq: 0,
+            newest_time_ms: None,
+        };
+        store.recover_newest_segment()?;
+        Ok(store)
+    }
+
+    /// Finds the next sequence number and drops a cut off record at the end of the newest segment.
+    fn recover_newest_segment(&mut self) -> Result<(), StoreError> {
+        let Some(newest) = self.segments.last_mut() else {
+            return Ok(());
+        };
+
+        let mut reader = SegmentReader::open(&newest.path)?;
+        let mut valid_len = 0;
+        self.next_seq = newest.first_seq;
+        while let Some(record) = reader.next_record()? {
+            valid_len += (RECORD_HEADER_LEN + record.packet.len()) as u64;
+            self.next_seq = record.seq + 1;
+            self.newest_time_ms = Some(record.time_ms);
+        }
+
+        if valid_len != newest.len {
+            OpenOptions::new()
+                .write(true)
+                .open(&newest.path)?
+                .set_len(valid_len)?;
+            newest.len = valid_len;
+        }
+
+        Ok(())
+    }
+
+    /// Appends a packet and returns its sequence number.
+    pub fn append(&mut self, time_ms: u64, packet: &[u8]) -> Result<u64, StoreError> {
+        let record_len = (RECORD_HEADER_LEN + packet.len()) as u64;
+        if record_len > self.segment_bytes {
+            return Err(StoreError::PacketTooLarge(packet.len()));
+        }
+
+        let segment_full = self
+            .segments
+            .last()
+            .is_none_or(|s| s.len + record_len > self.segment_bytes);
+        if segment_full {
+            self.start_segment()?;
+        }
+
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let path = &self.segments.last().expect("Segment was started").path;
+                self.writer.insert(OpenOptions::new().append(true).open(path)?)
+            }
+        };
+
+        let seq = self.next_seq;
+        let mut record = Vec::with_capacity(record_len as usize);
+        record.extend_from_slice(&seq.to_be_bytes());
+        record.extend_from_slice(&time_ms.to_be_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        record.extend_from_slice(packet);
+        writer.write_all(&record)?;
+
+        self.segments.last_mut().expect("Segment was started").len += record_len;
+        self.next_seq += 1;
+        self.newest_time_ms = Some(time_ms);
+
+        self.evict_oldest_segments()?;
+        Ok(seq)
+    }
+
+    fn start_segment(&mut self) -> Result<(), StoreError> {
+        let path = self
+            .directory
+            .join(format!("{:020}.{}", self.next_seq, SEGMENT_EXTENSION));
+        self.writer = Some(File::create(&path)?);
+        self.segments.push(Segment {
+            path,
+            first_seq: self.next_seq,
+            len: 0,
+        });
+        Ok(())
+    }
+
+    fn evict_oldest_segments(&mut self) -> Result<(), StoreError> {
+        while self.used_bytes() > self.max_bytes && self.segments.len() > 1 {
+            let oldest = self.segments.remove(0);
+            fs::remove_file(&oldest.path)?;
+        }
+        Ok(())
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.len).sum()
+    }
+
+    /// Sequence number of the oldest record still in the store
+    pub fn first_seq(&self) -> u64 {
+        self.segments.first().map_or(self.next_seq, |s| s.first_seq)
+    }
+
+    /// Sequence number the next record will get
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub fn newest_time_ms(&self) -> Option<u64> {
+        self.newest_time_ms
+    }
+
+    /// Returns a reader over the records in `range`, in recording order.
+    ///
+    /// The reader works on the segment files directly, so packets can be recorded while it is used.
+    /// Segments which are deleted before the reader gets to them are skipped.
+    pub fn reader(&self, range: RetrievalRange) -> StoreReader {
+        let mut segments: Vec<_> = self.segments.iter().collect();
+        if let RetrievalRange::Sequence { first, .. } = range {
+            // Skip segments which end before the first requested record
+            let start = segments
+                .iter()
+                .rposition(|s| s.first_seq <= first)
+                .unwrap_or(0);
+            segments.drain(..start);
+        }
+
+        StoreReader {
+            segments: segments.into_iter().map(|s| s.path.clone()).collect(),
+            current: None,
+            range,
+        }
+    }
+}
+
+struct SegmentReader {
+    reader: BufReader<File>,
+}
+
+impl SegmentReader {
+    fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+        })
+    }
+
+    /// Reads the next record, returns `None` at the end of the segment or at a cut off record.
+    fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        if !read_complete(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+
+        let seq = u64::from_be_bytes(header[0..8].try_into().unwrap());
+        let time_ms = u64::from_be_bytes(header[8..16].try_into().unwrap());
+        let len = u32::from_be_bytes(header[16..20].try_into().unwrap()) as usize;
+
+        let mut packet = vec![0u8; len];
+        if !read_complete(&mut self.reader, &mut packet)? {
+            return Ok(None);
+        }
+
+        Ok(Some(Record {
+            seq,
+            time_ms,
+            packet,
+        }))
+    }
+}
+
+/// Fills `buf`, returns `false` if the end of the file was reached before.
+fn read_complete(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the records of a retrieval, see [`PacketStore::reader`]
+pub struct StoreReader {
+    segments: std::collections::VecDeque<PathBuf>,
+    current: Option<SegmentReader>,
+    range: RetrievalRange,
+}
+
+impl StoreReader {
+    pub fn next_record(&mut self) -> Result<Option<Record>, StoreError> {
+        loop {
+            let reader = match &mut self.current {
+                Some(reader) => reader,
+                None => {
+                    let Some(path) = self.segments.pop_front() else {
+                        return Ok(None);
+                    };
+                    match SegmentReader::open(&path) {
+                        Ok(reader) => self.current.insert(reader),
+                        // The segment was evicted in the meantime
+                        Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            };
+
+            match reader.next_record()? {
+                None => self.current = None,
+                Some(record) if self.range.contains(&record) => return Ok(Some(record)),
+                Some(record) => {
+                    if let RetrievalRange::Sequence { last, .. } = self.range {
+                        if record.seq > last {
+                            return Ok(None);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory for a test store, removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "rccn_usr_comm_store_{}_{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read_all(store: &PacketStore, range: RetrievalRange) -> Vec<Record> {
+        let mut reader = store.reader(range);
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn packet(i: u64) -> Vec<u8> {
+        vec![i as u8; 10]
+    }
+
+    #[test]
+    fn test_retrieval_ranges() {
+        let dir = TestDir::new("ranges");
+        // Three records per segment
+        let mut store = PacketStore::open(&dir.0, 10_000, 3 * 30).unwrap();
+        for i in 0..20 {
+            assert_eq!(store.append(1000 + i * 10, &packet(i)).unwrap(), i);
+        }
+
+        let records = read_all(&store, RetrievalRange::Sequence { first: 7, last: 11 });
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), [7, 8, 9, 10, 11]);
+        assert_eq!(records[0].packet, packet(7));
+
+        let records = read_all(&store, RetrievalRange::Time { start_ms: 1095, end_ms: 1120 });
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), [10, 11, 12]);
+    }
+
+    #[test]
+    fn test_oldest_segments_are_evicted() {
+        let dir = TestDir::new("evict");
+        let mut store = PacketStore::open(&dir.0, 4 * 90, 3 * 30).unwrap();
+        for i in 0..20 {
+            store.append(i, &packet(i)).unwrap();
+        }
+
+        assert!(store.used_bytes() <= 4 * 90);
+        assert_eq!(store.first_seq(), 9);
+        let all = read_all(&store, RetrievalRange::Sequence { first: 0, last: u64::MAX });
+        assert_eq!(all.first().unwrap().seq, 9);
+        assert_eq!(all.last().unwrap().seq, 19);
+    }
+
+    #[test]
+    fn test_reopen_drops_cut_off_record() {
+        let dir = TestDir::new("reopen");
+        {
+            let mut store = PacketStore::open(&dir.0, 10_000, 1000).unwrap();
+            for i in 0..5 {
+                store.append(i, &packet(i)).unwrap();
+            }
+        }
+
+        // Cut the last record in half
+        let segment = fs::read_dir(&dir.0).unwrap().next().unwrap().unwrap().path();
+        let len = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new().write(true).open(&segment).unwrap().set_len(len - 15).unwrap();
+
+        let mut store = PacketStore::open(&dir.0, 10_000, 1000).unwrap();
+        assert_eq!(store.next_seq(), 4);
+        assert_eq!(store.newest_time_ms(), Some(3));
+        assert_eq!(store.append(100, &packet(100)).unwrap(), 4);
+
+        let all = read_all(&store, RetrievalRange::Sequence { first: 0, last: 10 });
+        assert_eq!(all.iter().map(|r| r.seq).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(all[4].packet, packet(100));
+    }
+}
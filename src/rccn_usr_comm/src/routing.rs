@@ -0,0 +1,204 @@
+//! Downlink routing of space packets to virtual channels by APID and PUS service.
+//!
+//! Packets received from an application are normally framed on the VC they arrived on.
+//! Routes select another VC for packets matching their APID, service type and subtype.
+//! The first matching route wins; packets matching no route stay on their VC.
+
+use rccn_usr::types::VcId;
+use serde::{Deserialize, Serialize};
+
+use crate::sequence_counter::split_packets;
+
+/// Offset of the service type in a PUS-C TM packet
+const PUS_TM_SERVICE_OFFSET: usize = 7;
+
+/// Selects space packets by APID and PUS service, unset fields match any packet
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct PacketFilter {
+    /// Only match packets with this APID
+    pub apid: Option<u16>,
+    /// Only match PUS TM packets with this service type
+    pub service: Option<u8>,
+    /// Only match PUS TM packets with this service subtype, requires `service`
+    pub subservice: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RouteConfig {
+    #[serde(flatten)]
+    pub filter: PacketFilter,
+    /// VC the matching packets are sent on
+    pub vc_id: VcId,
+}
+
+impl PacketFilter {
+    /// Checks a single, complete space packet against the filter.
+    pub fn matches(&self, packet: &[u8]) -> bool {
+        let apid = u16::from_be_bytes([packet[0], packet[1]]) & 0x7FF;
+        if self.apid.is_some_and(|a| a != apid) {
+            return false;
+        }
+
+        if self.service.is_none() && self.subservice.is_none() {
+            return true;
+        }
+
+        match pus_tm_service(packet) {
+            Some((service, subservice)) => {
+                self.service.is_none_or(|s| s == service)
+                    && self.subservice.is_none_or(|s| s == subservice)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns service type and subtype of a PUS TM packet
+fn pus_tm_service(packet: &[u8]) -> Option<(u8, u8)> {
+    let is_tm = packet[0] & 0x10 == 0;
+    let has_sec_header = packet[0] & 0x08 != 0;
+    if !is_tm || !has_sec_header {
+        return None;
+    }
+
+    let service = *packet.get(PUS_TM_SERVICE_OFFSET)?;
+    let subservice = *packet.get(PUS_TM_SERVICE_OFFSET + 1)?;
+    Some((service, subservice))
+}
+
+#[derive(Debug, Default)]
+pub struct Router {
+    routes: Vec<RouteConfig>,
+}
+
+impl Router {
+    pub fn new(routes: &[RouteConfig]) -> Self {
+        Self {
+            routes: routes.to_vec(),
+        }
+    }
+
+    /// Splits the packets in `data` received on `vc_id` by their downlink VC.
+    ///
+    /// Consecutive packets for the same VC stay together, and the order of the packets
+    /// on each VC is kept. Data which is not a sequence of complete space packets
+    /// is passed on to `vc_id` as a whole.
+    pub fn route(&self, vc_id: VcId, data: &[u8]) -> Vec<(VcId, Vec<u8>)> {
+        let packet_lengths = match split_packets(data) {
+            Some(lengths) if !self.routes.is_empty() => lengths,
+            _ => return vec![(vc_id, data.to_vec())],
+        };
+
+        let mut routed: Vec<(VcId, Vec<u8>)> = Vec::new();
+        let mut offset = 0;
+        for len in packet_lengths {
+            let packet = &data[offset..offset + len];
+            offset += len;
+
+            let target = self
+                .routes
+                .iter()
+                .find(|route| route.filter.matches(packet))
+                .map_or(vc_id, |route| route.vc_id);
+
+            match routed.last_mut() {
+                Some((last_vc, packets)) if *last_vc == target => {
+                    packets.extend_from_slice(packet)
+                }
+                _ => routed.push((target, packet.to_vec())),
+            }
+        }
+
+        routed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spacepackets::{
+        ecss::{
+            tm::{PusTmCreator, PusTmSecondaryHeader},
+            WritablePusPacket,
+        },
+        PacketId, PacketSequenceCtrl, PacketType, SequenceFlags, SpHeader,
+    };
+
+    fn sp_header(apid: u16) -> SpHeader {
+        SpHeader::new(
+            PacketId::new(PacketType::Tm, true, apid),
+            PacketSequenceCtrl::new(SequenceFlags::Unsegmented, 0),
+            0,
+        )
+    }
+
+    fn pus_tm(apid: u16, service: u8, subservice: u8) -> Vec<u8> {
+        PusTmCreator::new(
+            sp_header(apid),
+            PusTmSecondaryHeader::new(service, subservice, 0, 0, &[0; 7]),
+            &[1, 2, 3],
+            true,
+        )
+        .to_vec()
+        .unwrap()
+    }
+
+    fn route(apid: Option<u16>, service: Option<u8>, subservice: Option<u8>, vc_id: VcId) -> RouteConfig {
+        RouteConfig {
+            filter: PacketFilter {
+                apid,
+                service,
+                subservice,
+            },
+            vc_id,
+        }
+    }
+
+    #[test]
+    fn test_first_matching_route_wins() {
+        let router = Router::new(&[
+            route(Some(0x42), Some(1), None, 1),
+            route(None, Some(5), Some(1), 2),
+            route(Some(0x42), None, None, 3),
+        ]);
+
+        let verification = pus_tm(0x42, 1, 7);
+        let event = pus_tm(0x43, 5, 1);
+        let housekeeping = pus_tm(0x42, 3, 25);
+        let other = pus_tm(0x43, 3, 25);
+
+        assert_eq!(router.route(0, &verification), vec![(1, verification)]);
+        assert_eq!(router.route(0, &event), vec![(2, event)]);
+        assert_eq!(router.route(0, &housekeeping), vec![(3, housekeeping)]);
+        assert_eq!(router.route(0, &other), vec![(0, other)]);
+    }
+
+    #[test]
+    fn test_packets_are_split_by_vc() {
+        let router = Router::new(&[route(None, Some(1), None, 1)]);
+        let a = pus_tm(0x42, 3, 25);
+        let b = pus_tm(0x42, 1, 1);
+        let c = pus_tm(0x42, 1, 7);
+        let d = pus_tm(0x42, 3, 25);
+
+        let data = [a.clone(), b.clone(), c.clone(), d.clone()].concat();
+        assert_eq!(
+            router.route(0, &data),
+            vec![(0, a), (1, [b, c].concat()), (0, d)]
+        );
+    }
+
+    #[test]
+    fn test_non_packet_data_stays_on_vc() {
+        let router = Router::new(&[route(None, None, None, 1)]);
+        assert_eq!(router.route(0, &[0xFF, 0x00]), vec![(0, vec![0xFF, 0x00])]);
+
+        // Service rules don't match packets without PUS secondary header
+        let router = Router::new(&[route(None, Some(1), None, 1)]);
+        let mut raw = sp_header(0x42).to_vec();
+        raw[4..6].copy_from_slice(&8u16.to_be_bytes());
+        raw.extend_from_slice(&[1; 9]);
+        raw[0] &= !0x08;
+        assert_eq!(router.route(0, &raw), vec![(0, raw.clone())]);
+    }
+}
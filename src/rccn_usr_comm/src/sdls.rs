@@ -0,0 +1,665 @@
+//! Space Data Link Security (SDLS) protocol as specified in CCSDS 355.0-B.
+//!
+//! Every protected frame data field is laid out as
+//!
+//! ```text
+//! | SPI (2) | IV (12) | ARSN (arsn_length) | ciphertext | MAC (16) |
+//! ```
+//!
+//! using AES-256-GCM for authenticated encryption. The security parameter index (SPI)
+//! selects the security association (SA), which holds the key and the anti-replay
+//! sequence number (ARSN) state. The frame headers preceding the frame data field (the TC
+//! primary header on the uplink, the USLP primary header and data field header on the
+//! downlink) and the security header are authenticated along with the data.
+//!
+//! The ARSN state can be kept in a file across restarts. To avoid writing the file for every
+//! frame, each SA saves an upper bound [`ARSN_RESERVE`] ahead of its ARSN, and saves the next
+//! bound once it is reached. After a restart, the downlink continues after the saved bound,
+//! and the uplink only accepts ARSNs after it, so the ground has to skip ahead as well.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::{
+    aead::{AeadCore, AeadInPlace, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce, Tag,
+};
+use rccn_usr::types::VcId;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const SPI_LEN: usize = 2;
+const IV_LEN: usize = 12;
+const MAC_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// Number of ARSNs used before the ARSN file is written again
+pub const ARSN_RESERVE: u64 = 1000;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SdlsError {
+    #[error("Frame data field too short for the security header and trailer")]
+    FrameTooShort,
+    #[error("Invalid key for SPI {0}, expected {KEY_LEN} hex encoded bytes")]
+    InvalidKey(u16),
+    #[error("Unknown SPI {0}")]
+    UnknownSpi(u16),
+    #[error("SPI {spi} is not valid for {direction:?} frames on VC {vc_id}")]
+    SpiNotValid {
+        spi: u16,
+        vc_id: VcId,
+        direction: SaDirection,
+    },
+    #[error("No uplink security association for VC {0}")]
+    NoSecurityAssociation(VcId),
+    #[error("Authentication failed")]
+    AuthenticationFailed,
+    #[error("ARSN {received} rejected, last accepted ARSN is {last}")]
+    Replay { received: u64, last: u64 },
+    #[error("ARSN file {path}: {message}")]
+    ArsnFile { path: PathBuf, message: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SaDirection {
+    Uplink,
+    Downlink,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SecurityAssociationConfig {
+    /// Security parameter index transmitted in the security header
+    pub spi: u16,
+    pub direction: SaDirection,
+    /// Virtual channels this security association may be used on
+    pub virtual_channels: Vec<VcId>,
+    /// AES-256 key, hex encoded
+    pub key: String,
+    /// Length of the anti-replay sequence number in bytes (1 to 8)
+    #[serde(default = "default_arsn_length")]
+    pub arsn_length: u8,
+    /// Maximum accepted increase of the ARSN between two uplink frames, modulo the ARSN range.
+    /// 0 accepts any increase up to half the ARSN range.
+    #[serde(default = "default_arsn_window")]
+    pub arsn_window: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SdlsConfig {
+    pub security_associations: Vec<SecurityAssociationConfig>,
+    /// File keeping the ARSN of every SA across restarts
+    pub arsn_file: Option<PathBuf>,
+}
+
+fn default_arsn_length() -> u8 {
+    4
+}
+
+fn default_arsn_window() -> u64 {
+    16
+}
+
+impl SecurityAssociationConfig {
+    /// Decodes the hex encoded key.
+    pub fn key_bytes(&self) -> Result<[u8; KEY_LEN], SdlsError> {
+        let hex = self.key.trim();
+        let mut key = [0u8; KEY_LEN];
+        if hex.len() != 2 * KEY_LEN || !hex.is_ascii() {
+            return Err(SdlsError::InvalidKey(self.spi));
+        }
+        for (byte, chunk) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let chunk = std::str::from_utf8(chunk).map_err(|_| SdlsError::InvalidKey(self.spi))?;
+            *byte = u8::from_str_radix(chunk, 16).map_err(|_| SdlsError::InvalidKey(self.spi))?;
+        }
+        Ok(key)
+    }
+
+    fn header_len(&self) -> usize {
+        SPI_LEN + IV_LEN + self.arsn_length as usize
+    }
+}
+
+struct SecurityAssociation {
+    config: SecurityAssociationConfig,
+    cipher: Aes256Gcm,
+    /// Last accepted ARSN on the uplink, last sent ARSN on the downlink
+    arsn: u64,
+    /// ARSNs which may still be used before the ARSN file has to be written
+    reserved: u64,
+}
+
+impl SecurityAssociation {
+    fn arsn_modulus(&self) -> u128 {
+        1u128 << (8 * self.config.arsn_length as u32)
+    }
+
+    /// Adds `n` to `arsn`, wrapping around at the ARSN length.
+    fn arsn_add(&self, arsn: u64, n: u64) -> u64 {
+        ((arsn as u128 + n as u128) % self.arsn_modulus()) as u64
+    }
+
+    /// How far a received ARSN is ahead of the last accepted one, modulo the ARSN range.
+    fn arsn_increase(&self, received: u64) -> u64 {
+        let modulus = self.arsn_modulus();
+        ((received as u128 + modulus - self.arsn as u128) % modulus) as u64
+    }
+
+    /// Checks a received ARSN against the last accepted one and the window.
+    fn arsn_in_window(&self, received: u64) -> bool {
+        let increase = self.arsn_increase(received) as u128;
+        let max_increase = match self.config.arsn_window {
+            0 => self.arsn_modulus() / 2 - 1,
+            window => window as u128,
+        };
+        increase != 0 && increase <= max_increase
+    }
+
+    /// The ARSN to continue with after a restart
+    fn saved_arsn(&self) -> u64 {
+        self.arsn_add(self.arsn, self.reserved)
+    }
+}
+
+/// Applies and checks SDLS protection on the frame data fields.
+pub struct SecurityLayer {
+    associations: HashMap<u16, SecurityAssociation>,
+    arsn_file: Option<PathBuf>,
+}
+
+impl SecurityLayer {
+    /// Creates the security associations, continuing with the ARSNs saved in the ARSN file if it exists.
+    pub fn new(config: &SdlsConfig) -> Result<Self, SdlsError> {
+        let saved = match &config.arsn_file {
+            Some(path) if path.exists() => read_arsn_file(path)?,
+            _ => BTreeMap::new(),
+        };
+
+        let mut associations = HashMap::new();
+        for sa in &config.security_associations {
+            let key = sa.key_bytes()?;
+            let mut association = SecurityAssociation {
+                config: sa.clone(),
+                cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+                arsn: 0,
+                reserved: 0,
+            };
+            if let Some(arsn) = saved.get(&sa.spi) {
+                association.arsn = association.arsn_add(*arsn, 0);
+            }
+            associations.insert(sa.spi, association);
+        }
+        Ok(Self {
+            associations,
+            arsn_file: config.arsn_file.clone(),
+        })
+    }
+
+    /// Writes the ARSN state of all SAs to the ARSN file, if one is configured.
+    fn save_arsns(&self) -> Result<(), SdlsError> {
+        let Some(path) = &self.arsn_file else {
+            return Ok(());
+        };
+        let arsns: BTreeMap<u16, u64> = self
+            .associations
+            .iter()
+            .map(|(spi, sa)| (*spi, sa.saved_arsn()))
+            .collect();
+        write_arsn_file(path, &arsns)
+    }
+
+    /// Verifies and decrypts the data field of a TC frame received on `vc_id`.
+    ///
+    /// `primary_header` is included in the authentication. Frames on VCs without an
+    /// uplink security association are rejected, so no unauthenticated data reaches
+    /// the virtual channels once SDLS is enabled.
+    pub fn unprotect_tc(
+        &mut self,
+        vc_id: VcId,
+        primary_header: &[u8],
+        data_field: &[u8],
+    ) -> Result<Vec<u8>, SdlsError> {
+        if !self.associations.values().any(|sa| {
+            sa.config.direction == SaDirection::Uplink && sa.config.virtual_channels.contains(&vc_id)
+        }) {
+            return Err(SdlsError::NoSecurityAssociation(vc_id));
+        }
+
+        if data_field.len() < SPI_LEN {
+            return Err(SdlsError::FrameTooShort);
+        }
+        let spi = u16::from_be_bytes([data_field[0], data_field[1]]);
+        let sa = self
+            .associations
+            .get_mut(&spi)
+            .ok_or(SdlsError::UnknownSpi(spi))?;
+        if sa.config.direction != SaDirection::Uplink || !sa.config.virtual_channels.contains(&vc_id) {
+            return Err(SdlsError::SpiNotValid {
+                spi,
+                vc_id,
+                direction: SaDirection::Uplink,
+            });
+        }
+
+        let header_len = sa.config.header_len();
+        if data_field.len() < header_len + MAC_LEN {
+            return Err(SdlsError::FrameTooShort);
+        }
+        let (header, rest) = data_field.split_at(header_len);
+        let (ciphertext, mac) = rest.split_at(rest.len() - MAC_LEN);
+
+        let iv = &header[SPI_LEN..SPI_LEN + IV_LEN];
+        let received_arsn = header[SPI_LEN + IV_LEN..]
+            .iter()
+            .fold(0u64, |acc, &b| (acc << 8) | b as u64);
+
+        let mut aad = primary_header.to_vec();
+        aad.extend_from_slice(header);
+
+        let mut plaintext = ciphertext.to_vec();
+        sa.cipher
+            .decrypt_in_place_detached(
+                Nonce::from_slice(iv),
+                &aad,
+                &mut plaintext,
+                Tag::from_slice(mac),
+            )
+            .map_err(|_| SdlsError::AuthenticationFailed)?;
+
+        // Only authenticated frames may advance the anti-replay state
+        if !sa.arsn_in_window(received_arsn) {
+            return Err(SdlsError::Replay {
+                received: received_arsn,
+                last: sa.arsn,
+            });
+        }
+        let increase = sa.arsn_increase(received_arsn);
+        let needs_reserve = increase > sa.reserved && self.arsn_file.is_some();
+        let last = (sa.arsn, sa.reserved);
+        sa.arsn = received_arsn;
+        sa.reserved = if needs_reserve {
+            ARSN_RESERVE
+        } else {
+            sa.reserved.saturating_sub(increase)
+        };
+
+        // A frame whose ARSN is not covered by the file would be accepted again after a restart
+        if needs_reserve {
+            if let Err(e) = self.save_arsns() {
+                if let Some(sa) = self.associations.get_mut(&spi) {
+                    (sa.arsn, sa.reserved) = last;
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(plaintext)
+    }
+
+    /// The downlink security association of `vc_id`
+    fn downlink_sa(&self, vc_id: VcId) -> Option<&SecurityAssociation> {
+        self.associations.values().find(|sa| {
+            sa.config.direction == SaDirection::Downlink && sa.config.virtual_channels.contains(&vc_id)
+        })
+    }
+
+    /// Number of bytes [`SecurityLayer::protect`] adds to the data of `vc_id`.
+    pub fn protection_overhead(&self, vc_id: VcId) -> usize {
+        self.downlink_sa(vc_id)
+            .map_or(0, |sa| sa.config.header_len() + MAC_LEN)
+    }
+
+    /// Protects data to be sent on `vc_id` with the downlink security association of that VC.
+    ///
+    /// `frame_header` are the bytes of the frame before the data, which are authenticated
+    /// along with it. Data for VCs without a downlink security association is returned unchanged.
+    pub fn protect(
+        &mut self,
+        vc_id: VcId,
+        frame_header: &[u8],
+        data: &[u8],
+    ) -> Result<Vec<u8>, SdlsError> {
+        let Some(spi) = self.downlink_sa(vc_id).map(|sa| sa.config.spi) else {
+            return Ok(data.to_vec());
+        };
+
+        // Save the bound for the next ARSNs before using them
+        let needs_reserve = self.associations[&spi].reserved == 0 && self.arsn_file.is_some();
+        if needs_reserve {
+            if let Some(sa) = self.associations.get_mut(&spi) {
+                sa.reserved = ARSN_RESERVE;
+            }
+            if let Err(e) = self.save_arsns() {
+                if let Some(sa) = self.associations.get_mut(&spi) {
+                    sa.reserved = 0;
+                }
+                return Err(e);
+            }
+        }
+
+        let Some(sa) = self.associations.get_mut(&spi) else {
+            return Ok(data.to_vec());
+        };
+        sa.arsn = sa.arsn_add(sa.arsn, 1);
+        sa.reserved = sa.reserved.saturating_sub(1);
+        let iv = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let mut protected = Vec::with_capacity(sa.config.header_len() + data.len() + MAC_LEN);
+        protected.extend_from_slice(&sa.config.spi.to_be_bytes());
+        protected.extend_from_slice(&iv);
+        protected.extend_from_slice(&sa.arsn.to_be_bytes()[8 - sa.config.arsn_length as usize..]);
+        let header_len = protected.len();
+        protected.extend_from_slice(data);
+
+        let (header, payload) = protected.split_at_mut(header_len);
+        let mut aad = frame_header.to_vec();
+        aad.extend_from_slice(header);
+        let mac = sa
+            .cipher
+            .encrypt_in_place_detached(&iv, &aad, payload)
+            .map_err(|_| SdlsError::AuthenticationFailed)?;
+        protected.extend_from_slice(&mac);
+
+        Ok(protected)
+    }
+}
+
+fn read_arsn_file(path: &Path) -> Result<BTreeMap<u16, u64>, SdlsError> {
+    let arsn_file_error = |message: String| SdlsError::ArsnFile {
+        path: path.to_path_buf(),
+        message,
+    };
+    let content = fs::read_to_string(path).map_err(|e| arsn_file_error(e.to_string()))?;
+    serde_yaml::from_str(&content).map_err(|e| arsn_file_error(e.to_string()))
+}
+
+/// Replaces the ARSN file, so a crash while writing leaves the previous state.
+fn write_arsn_file(path: &Path, arsns: &BTreeMap<u16, u64>) -> Result<(), SdlsError> {
+    let arsn_file_error = |message: String| SdlsError::ArsnFile {
+        path: path.to_path_buf(),
+        message,
+    };
+    let content = serde_yaml::to_string(arsns).map_err(|e| arsn_file_error(e.to_string()))?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content).map_err(|e| arsn_file_error(e.to_string()))?;
+    fs::rename(&tmp_path, path).map_err(|e| arsn_file_error(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const HEADER: [u8; 5] = [0x00, 0xAB, 0x04, 0x20, 0x00];
+
+    fn sa(spi: u16, direction: SaDirection, vc_id: VcId) -> SecurityAssociationConfig {
+        SecurityAssociationConfig {
+            spi,
+            direction,
+            virtual_channels: vec![vc_id],
+            key: KEY.into(),
+            arsn_length: 4,
+            arsn_window: 16,
+        }
+    }
+
+    fn sdls_config(arsn_file: Option<PathBuf>) -> SdlsConfig {
+        SdlsConfig {
+            security_associations: vec![
+                sa(1, SaDirection::Uplink, 1),
+                sa(2, SaDirection::Downlink, 1),
+            ],
+            arsn_file,
+        }
+    }
+
+    fn layer() -> SecurityLayer {
+        SecurityLayer::new(&sdls_config(None)).unwrap()
+    }
+
+    /// ARSN file path for a test, removed when dropped
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "rccn_usr_comm_arsn_{}_{}.yaml",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Protects a TC data field the way the ground segment does
+    fn protect_tc(spi: u16, arsn: u32, data: &[u8]) -> Vec<u8> {
+        let key = sa(spi, SaDirection::Uplink, 1).key_bytes().unwrap();
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let iv = [7u8; IV_LEN];
+
+        let mut header = spi.to_be_bytes().to_vec();
+        header.extend_from_slice(&iv);
+        header.extend_from_slice(&arsn.to_be_bytes());
+
+        let mut aad = HEADER.to_vec();
+        aad.extend_from_slice(&header);
+
+        let mut payload = data.to_vec();
+        let mac = cipher
+            .encrypt_in_place_detached(Nonce::from_slice(&iv), &aad, &mut payload)
+            .unwrap();
+
+        let mut field = header;
+        field.extend_from_slice(&payload);
+        field.extend_from_slice(&mac);
+        field
+    }
+
+    #[test]
+    fn test_uplink_roundtrip() {
+        let mut layer = layer();
+        let data = [0x18, 0x44, 0xC0, 0x00, 0x00, 0x01, 0xAA, 0xBB];
+
+        let field = protect_tc(1, 1, &data);
+        assert_eq!(field.len(), 2 + IV_LEN + 4 + data.len() + MAC_LEN);
+        assert_eq!(layer.unprotect_tc(1, &HEADER, &field).unwrap(), data);
+    }
+
+    #[test]
+    fn test_tampered_frames_are_rejected() {
+        let mut layer = layer();
+        let field = protect_tc(1, 1, &[1, 2, 3, 4]);
+
+        // Every single bit flip in the data field must be detected
+        for byte in 2..field.len() {
+            let mut tampered = field.clone();
+            tampered[byte] ^= 0x01;
+            assert_eq!(
+                layer.unprotect_tc(1, &HEADER, &tampered),
+                Err(SdlsError::AuthenticationFailed),
+                "byte {byte}"
+            );
+        }
+
+        // The primary header is authenticated as well
+        let mut header = HEADER;
+        header[1] ^= 0x01;
+        assert_eq!(
+            layer.unprotect_tc(1, &header, &field),
+            Err(SdlsError::AuthenticationFailed)
+        );
+
+        assert!(layer.unprotect_tc(1, &HEADER, &field).is_ok());
+    }
+
+    #[test]
+    fn test_replayed_frames_are_rejected() {
+        let mut layer = layer();
+        let first = protect_tc(1, 5, &[1]);
+        let second = protect_tc(1, 6, &[2]);
+
+        assert!(layer.unprotect_tc(1, &HEADER, &first).is_ok());
+        assert!(layer.unprotect_tc(1, &HEADER, &second).is_ok());
+        assert_eq!(
+            layer.unprotect_tc(1, &HEADER, &first),
+            Err(SdlsError::Replay {
+                received: 5,
+                last: 6
+            })
+        );
+
+        // Outside of the anti-replay window
+        assert_eq!(
+            layer.unprotect_tc(1, &HEADER, &protect_tc(1, 23, &[3])),
+            Err(SdlsError::Replay {
+                received: 23,
+                last: 6
+            })
+        );
+    }
+
+    #[test]
+    fn test_arsn_window_wraps_around() {
+        let mut layer = layer();
+        layer.associations.get_mut(&1).unwrap().arsn = 0xFFFF_FFF0;
+
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 0xFFFF_FFFE, &[1])).is_ok());
+        // 5 ahead of the last ARSN, modulo 2^32
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 3, &[2])).is_ok());
+        assert_eq!(
+            layer.unprotect_tc(1, &HEADER, &protect_tc(1, 0xFFFF_FFFF, &[3])),
+            Err(SdlsError::Replay {
+                received: 0xFFFF_FFFF,
+                last: 3
+            })
+        );
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 20, &[4])).is_err());
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 19, &[4])).is_ok());
+
+        // Without a window, anything up to half the ARSN range ahead is accepted
+        let mut layer = SecurityLayer::new(&sdls_config(None)).unwrap();
+        let sa = layer.associations.get_mut(&1).unwrap();
+        sa.config.arsn_window = 0;
+        sa.arsn = 0xFFFF_FF00;
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 0x7FFF_FEFF, &[5])).is_ok());
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 0xFFFF_FF00, &[6])).is_err());
+    }
+
+    #[test]
+    fn test_arsns_are_kept_across_restarts() {
+        let file = TestFile::new("restart");
+        let config = sdls_config(Some(file.0.clone()));
+
+        let mut layer = SecurityLayer::new(&config).unwrap();
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 5, &[1])).is_ok());
+        let downlink_arsn = |protected: &[u8]| {
+            u32::from_be_bytes(protected[2 + IV_LEN..2 + IV_LEN + 4].try_into().unwrap()) as u64
+        };
+        assert_eq!(downlink_arsn(&layer.protect(1, &HEADER, &[1]).unwrap()), 1);
+
+        // The file is only written again once a reserved block is used up
+        let saved = fs::read_to_string(&file.0).unwrap();
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, 6, &[2])).is_ok());
+        assert_eq!(downlink_arsn(&layer.protect(1, &HEADER, &[1]).unwrap()), 2);
+        assert_eq!(fs::read_to_string(&file.0).unwrap(), saved);
+        drop(layer);
+
+        // The uplink only accepts ARSNs after the bound saved before the restart
+        let mut layer = SecurityLayer::new(&config).unwrap();
+        assert_eq!(
+            layer.unprotect_tc(1, &HEADER, &protect_tc(1, 7, &[3])),
+            Err(SdlsError::Replay {
+                received: 7,
+                last: 5 + ARSN_RESERVE
+            })
+        );
+        let next_uplink_arsn = 5 + ARSN_RESERVE as u32 + 1;
+        assert!(layer.unprotect_tc(1, &HEADER, &protect_tc(1, next_uplink_arsn, &[3])).is_ok());
+
+        // The downlink continues after the ARSNs reserved before the restart
+        assert_eq!(
+            downlink_arsn(&layer.protect(1, &HEADER, &[1]).unwrap()),
+            ARSN_RESERVE + 1
+        );
+    }
+
+    #[test]
+    fn test_key_selection_by_spi() {
+        let mut layer = layer();
+
+        assert_eq!(
+            layer.unprotect_tc(1, &HEADER, &protect_tc(9, 1, &[1])),
+            Err(SdlsError::UnknownSpi(9))
+        );
+        // SPI 2 is a downlink SA
+        assert!(matches!(
+            layer.unprotect_tc(1, &HEADER, &protect_tc(2, 1, &[1])),
+            Err(SdlsError::SpiNotValid { spi: 2, .. })
+        ));
+        // Unauthenticated commanding on a VC without SA
+        assert_eq!(
+            layer.unprotect_tc(0, &HEADER, &protect_tc(1, 1, &[1])),
+            Err(SdlsError::NoSecurityAssociation(0))
+        );
+    }
+
+    #[test]
+    fn test_downlink_protection() {
+        let mut layer = layer();
+        let data = [1, 2, 3, 4, 5];
+
+        let protected = layer.protect(1, &HEADER, &data).unwrap();
+        assert_eq!(protected.len(), layer.protection_overhead(1) + data.len());
+        assert_eq!(protected[..2], [0, 2]);
+        assert_eq!(protected[2 + IV_LEN..2 + IV_LEN + 4], [0, 0, 0, 1]);
+
+        let header_len = 2 + IV_LEN + 4;
+        let (header, rest) = protected.split_at(header_len);
+        let (ciphertext, mac) = rest.split_at(rest.len() - MAC_LEN);
+        let key = sa(2, SaDirection::Downlink, 1).key_bytes().unwrap();
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let decrypt = |frame_header: &[u8]| {
+            let mut aad = frame_header.to_vec();
+            aad.extend_from_slice(header);
+            let mut plaintext = ciphertext.to_vec();
+            cipher
+                .decrypt_in_place_detached(
+                    Nonce::from_slice(&header[2..2 + IV_LEN]),
+                    &aad,
+                    &mut plaintext,
+                    Tag::from_slice(mac),
+                )
+                .map(|()| plaintext)
+        };
+        assert_eq!(decrypt(&HEADER).unwrap(), data);
+
+        // The frame header is authenticated, e.g. the frame can't be moved to another VC
+        let mut other_vc = HEADER;
+        other_vc[2] ^= 0x04;
+        assert!(decrypt(&other_vc).is_err());
+
+        // The ARSN increases with every frame, VCs without SA are not protected
+        assert_eq!(layer.protect(1, &HEADER, &data).unwrap()[2 + IV_LEN + 3], 2);
+        assert_eq!(layer.protect(0, &HEADER, &data).unwrap(), data);
+        assert_eq!(layer.protection_overhead(0), 0);
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let mut config = sa(3, SaDirection::Uplink, 0);
+        config.key = "0011".into();
+        assert_eq!(config.key_bytes(), Err(SdlsError::InvalidKey(3)));
+        config.key = KEY.replace('0', "g");
+        assert_eq!(config.key_bytes(), Err(SdlsError::InvalidKey(3)));
+    }
+}
@@ -0,0 +1,277 @@
+//! CCSDS space packet sequence counting for the downlink.
+//!
+//! Applications create their TM with a sequence count of 0, since they cannot know
+//! about the packets produced by other applications on the same APID. This module
+//! keeps one counter per APID and rewrites the count of every outgoing packet
+//! right before it is framed.
+//!
+//! Whether a packet ends with a CRC which has to be updated can not be told from its
+//! content, so it is configured per VC as [`PacketCrc`].
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use spacepackets::{
+    PacketId, PacketSequenceCtrl, PacketType, SequenceFlags, SpHeader, CRC_CCITT_FALSE,
+};
+
+const SP_HEADER_LEN: usize = 6;
+const SEQ_COUNT_MODULUS: u16 = 1 << 14;
+const IDLE_APID: u16 = 0x7FF;
+
+/// Which packets with a secondary header end with a CRC-16 packet error control field
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PacketCrc {
+    /// All packets or none
+    All(bool),
+    /// Only the packets with these APIDs
+    Apids(Vec<u16>),
+}
+
+impl Default for PacketCrc {
+    /// PUS packets, as created by the applications, have a CRC
+    fn default() -> Self {
+        Self::All(true)
+    }
+}
+
+impl PacketCrc {
+    pub fn has_crc(&self, apid: u16) -> bool {
+        match self {
+            Self::All(all) => *all,
+            Self::Apids(apids) => apids.contains(&apid),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SequenceCounters {
+    counters: HashMap<u16, u16>,
+}
+
+impl SequenceCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the next sequence count for `apid` and advances the counter.
+    pub fn next(&mut self, apid: u16) -> u16 {
+        let counter = self.counters.entry(apid).or_insert(0);
+        let current = *counter;
+        *counter = (current + 1) % SEQ_COUNT_MODULUS;
+        current
+    }
+
+    /// Wraps `data` in a TM space packet without secondary header, with the next sequence
+    /// count of `apid`. `data` must not be empty.
+    pub fn wrap(&mut self, apid: u16, data: &[u8]) -> Vec<u8> {
+        let header = SpHeader::new(
+            PacketId::new(PacketType::Tm, false, apid),
+            PacketSequenceCtrl::new(SequenceFlags::Unsegmented, self.next(apid)),
+            // The packet data length field is the length minus one
+            (data.len() - 1) as u16,
+        );
+
+        let mut packet = header.to_vec();
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// Rewrites the sequence count of every space packet contained in `data`.
+    ///
+    /// Packets with a secondary header and a CRC according to `crc` get their CRC recomputed
+    /// after the count has been changed. If `data` is not a sequence of complete space packets,
+    /// it is left untouched and `false` is returned.
+    pub fn stamp_packets(&mut self, data: &mut [u8], crc: &PacketCrc) -> bool {
+        let packet_lengths = match split_packets(data) {
+            Some(lengths) => lengths,
+            None => return false,
+        };
+
+        let mut offset = 0;
+        for len in packet_lengths {
+            self.stamp_packet(&mut data[offset..offset + len], crc);
+            offset += len;
+        }
+
+        true
+    }
+
+    fn stamp_packet(&mut self, packet: &mut [u8], crc: &PacketCrc) {
+        let apid = u16::from_be_bytes([packet[0], packet[1]]) & 0x7FF;
+        if apid == IDLE_APID {
+            return;
+        }
+
+        let has_sec_header = packet[0] & 0x08 != 0;
+        let has_crc = has_sec_header && packet.len() >= SP_HEADER_LEN + 2 && crc.has_crc(apid);
+
+        let count = self.next(apid);
+        packet[2] = (packet[2] & 0xC0) | (count >> 8) as u8;
+        packet[3] = count as u8;
+
+        if has_crc {
+            let crc_pos = packet.len() - 2;
+            let crc = CRC_CCITT_FALSE.checksum(&packet[..crc_pos]);
+            packet[crc_pos..].copy_from_slice(&crc.to_be_bytes());
+        }
+    }
+}
+
+/// Returns the lengths of the space packets in `data`, or `None` if `data`
+/// does not consist of complete space packets only.
+pub(crate) fn split_packets(data: &[u8]) -> Option<Vec<usize>> {
+    let mut lengths = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let header = data.get(offset..offset + SP_HEADER_LEN)?;
+
+        // Only version 1 space packets (version number 0) are supported
+        if header[0] >> 5 != 0 {
+            return None;
+        }
+
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize + 1 + SP_HEADER_LEN;
+        if offset + len > data.len() {
+            return None;
+        }
+
+        lengths.push(len);
+        offset += len;
+    }
+
+    if lengths.is_empty() {
+        None
+    } else {
+        Some(lengths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spacepackets::{
+        ecss::{
+            tm::{PusTmCreator, PusTmReader, PusTmSecondaryHeader},
+            WritablePusPacket,
+        },
+        CcsdsPacket,
+    };
+
+    fn pus_tm(apid: u16, data: &[u8]) -> Vec<u8> {
+        PusTmCreator::new(
+            SpHeader::new(
+                PacketId::new(PacketType::Tm, true, apid),
+                PacketSequenceCtrl::new(SequenceFlags::Unsegmented, 0),
+                0,
+            ),
+            PusTmSecondaryHeader::new_simple(3, 25, &[]),
+            data,
+            true,
+        )
+        .to_vec()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_counts_per_apid() {
+        let mut counters = SequenceCounters::new();
+
+        for expected in 0..3 {
+            let mut a = pus_tm(0x10, &[1, 2, 3]);
+            assert!(counters.stamp_packets(&mut a, &PacketCrc::default()));
+            let (tm, _) = PusTmReader::new(&a, 0).unwrap();
+            assert_eq!(tm.seq_count(), expected);
+        }
+
+        let mut b = pus_tm(0x20, &[4, 5]);
+        assert!(counters.stamp_packets(&mut b, &PacketCrc::default()));
+        let (tm, _) = PusTmReader::new(&b, 0).unwrap();
+        assert_eq!(tm.seq_count(), 0);
+        assert_eq!(tm.apid(), 0x20);
+    }
+
+    #[test]
+    fn test_wrapped_packet_length() {
+        let mut counters = SequenceCounters::new();
+        let data = [1, 2, 3, 4, 5];
+
+        for expected in 0..2 {
+            let packet = counters.wrap(0x42, &data);
+            assert_eq!(split_packets(&packet), Some(vec![SP_HEADER_LEN + data.len()]));
+            assert_eq!(u16::from_be_bytes([packet[4], packet[5]]), data.len() as u16 - 1);
+            assert_eq!(u16::from_be_bytes([packet[0], packet[1]]) & 0x7FF, 0x42);
+            assert_eq!(u16::from_be_bytes([packet[2], packet[3]]) & 0x3FFF, expected);
+            assert_eq!(packet[SP_HEADER_LEN..], data);
+        }
+    }
+
+    #[test]
+    fn test_multiple_packets_in_one_buffer() {
+        let mut counters = SequenceCounters::new();
+
+        let mut data = pus_tm(0x10, &[1]);
+        data.extend(pus_tm(0x10, &[2, 3]));
+        let first_len = pus_tm(0x10, &[1]).len();
+
+        assert!(counters.stamp_packets(&mut data, &PacketCrc::default()));
+
+        // Both packets still pass the CRC check performed by the reader
+        let (first, _) = PusTmReader::new(&data[..first_len], 0).unwrap();
+        let (second, _) = PusTmReader::new(&data[first_len..], 0).unwrap();
+        assert_eq!(first.seq_count(), 0);
+        assert_eq!(second.seq_count(), 1);
+    }
+
+    #[test]
+    fn test_non_packet_data_untouched() {
+        let mut counters = SequenceCounters::new();
+
+        let mut data = vec![0xFF, 0x01, 0x02];
+        assert!(!counters.stamp_packets(&mut data, &PacketCrc::default()));
+        assert_eq!(data, [0xFF, 0x01, 0x02]);
+
+        // Truncated packet
+        let mut data = pus_tm(0x10, &[1, 2, 3]);
+        data.pop();
+        let original = data.clone();
+        assert!(!counters.stamp_packets(&mut data, &PacketCrc::default()));
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn test_crc_only_updated_where_configured() {
+        let mut counters = SequenceCounters::new();
+        counters.next(0x10);
+
+        // A packet whose last two bytes only look like a CRC is not changed there
+        let mut no_crc = pus_tm(0x10, &[1, 2, 3]);
+        no_crc.truncate(no_crc.len() - 2);
+        no_crc[5] -= 2;
+        let tail = no_crc[no_crc.len() - 2..].to_vec();
+        assert!(counters.stamp_packets(&mut no_crc, &PacketCrc::Apids(vec![0x20])));
+        assert_eq!(no_crc[3], 1);
+        assert_eq!(no_crc[no_crc.len() - 2..], tail);
+
+        let mut with_crc = pus_tm(0x20, &[1, 2, 3]);
+        assert!(counters.stamp_packets(&mut with_crc, &PacketCrc::Apids(vec![0x20])));
+        assert_eq!(CRC_CCITT_FALSE.checksum(&with_crc), 0);
+
+        let config: PacketCrc = serde_yaml::from_str("[0x20, 0x21]").unwrap();
+        assert!(config.has_crc(0x21) && !config.has_crc(0x10));
+        let config: PacketCrc = serde_yaml::from_str("false").unwrap();
+        assert!(!config.has_crc(0x21));
+    }
+
+    #[test]
+    fn test_counter_wraps() {
+        let mut counters = SequenceCounters::new();
+        for _ in 0..SEQ_COUNT_MODULUS - 1 {
+            counters.next(0x10);
+        }
+        assert_eq!(counters.next(0x10), SEQ_COUNT_MODULUS - 1);
+        assert_eq!(counters.next(0x10), 0);
+    }
+}
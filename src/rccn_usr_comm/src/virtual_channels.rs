@@ -0,0 +1,207 @@
+//! The virtual channels of a master channel, which can be added, removed and changed at runtime.
+//!
+//! Every VC runs its own transports, so a VC can be torn down or rebuilt without touching
+//! the others. Framing state like FARMs and frame counters is kept by the frame processor.
+
+use std::collections::HashMap;
+
+use crossbeam_channel::{Receiver, Sender};
+use rccn_usr::{
+    transport::{
+        manager::TransportManagerError, ros2::SharedNode, TransportManager,
+        VirtualChannelTransports,
+    },
+    types::VcId,
+};
+
+use crate::config::VirtualChannelConfig;
+
+/// Difference between two virtual channel configurations
+#[derive(Debug, PartialEq)]
+pub enum VcChange {
+    Added(VcId),
+    Removed(VcId),
+    /// Name, splitter or transports changed, the transports are rebuilt
+    TransportsChanged(VcId),
+    /// Only the framing options changed
+    FramingChanged(VcId),
+}
+
+/// Lists the changes needed to get from the VCs in `old` to the VCs in `new`.
+pub fn diff(old: &[VirtualChannelConfig], new: &[VirtualChannelConfig]) -> Vec<VcChange> {
+    let mut changes = Vec::new();
+
+    for vc in old {
+        if !new.iter().any(|n| n.vc.id == vc.vc.id) {
+            changes.push(VcChange::Removed(vc.vc.id));
+        }
+    }
+
+    for vc in new {
+        match old.iter().find(|o| o.vc.id == vc.vc.id) {
+            None => changes.push(VcChange::Added(vc.vc.id)),
+            Some(o) if o.vc != vc.vc => changes.push(VcChange::TransportsChanged(vc.vc.id)),
+            Some(o) if o.framing != vc.framing => changes.push(VcChange::FramingChanged(vc.vc.id)),
+            Some(_) => {}
+        }
+    }
+
+    changes
+}
+
+struct RunningVirtualChannel {
+    config: VirtualChannelConfig,
+    transports: VirtualChannelTransports,
+}
+
+pub struct VirtualChannels {
+    node: SharedNode,
+    channels: HashMap<VcId, RunningVirtualChannel>,
+}
+
+impl VirtualChannels {
+    /// Starts the transports of all `configs`.
+    pub fn start(
+        node: SharedNode,
+        configs: &[VirtualChannelConfig],
+    ) -> Result<Self, TransportManagerError> {
+        let mut vcs = Self {
+            node,
+            channels: HashMap::new(),
+        };
+
+        for config in configs {
+            vcs.start_channel(config)?;
+        }
+
+        Ok(vcs)
+    }
+
+    fn start_channel(&mut self, config: &VirtualChannelConfig) -> Result<(), TransportManagerError> {
+        let transports = TransportManager::start_virtual_channel(self.node.clone(), &config.vc)?;
+        self.channels.insert(
+            config.vc.id,
+            RunningVirtualChannel {
+                config: config.clone(),
+                transports,
+            },
+        );
+        Ok(())
+    }
+
+    fn stop_channel(&mut self, vc_id: VcId) {
+        if let Some(channel) = self.channels.remove(&vc_id) {
+            channel.transports.stop();
+        }
+    }
+
+    /// Brings the running VCs in line with `configs` and returns the changes made.
+    ///
+    /// VCs whose transports can not be started are left out and logged.
+    pub fn update(&mut self, configs: &[VirtualChannelConfig]) -> Vec<VcChange> {
+        let current: Vec<_> = self.channels.values().map(|c| c.config.clone()).collect();
+        let changes = diff(&current, configs);
+
+        // Stop the old transports first, so their ports and topics can be used again
+        for change in &changes {
+            if let VcChange::Removed(id) | VcChange::TransportsChanged(id) = change {
+                self.stop_channel(*id);
+            }
+        }
+
+        for change in &changes {
+            let id = match change {
+                VcChange::Added(id) | VcChange::TransportsChanged(id) | VcChange::FramingChanged(id) => *id,
+                VcChange::Removed(_) => continue,
+            };
+            let config = configs
+                .iter()
+                .find(|c| c.vc.id == id)
+                .expect("Changed VCs are part of the new config");
+
+            match change {
+                VcChange::FramingChanged(_) => {
+                    if let Some(channel) = self.channels.get_mut(&id) {
+                        channel.config = config.clone();
+                    }
+                }
+                _ => {
+                    if let Err(e) = self.start_channel(config) {
+                        log::error!("Could not start transports of VC {}: {}", config.vc.name, e);
+                    }
+                }
+            }
+        }
+
+        changes
+    }
+
+    pub fn config(&self, vc_id: VcId) -> Option<&VirtualChannelConfig> {
+        self.channels.get(&vc_id).map(|c| &c.config)
+    }
+
+    /// Channel to pass uplink data to the VC, if it has a TX transport
+    pub fn sender(&self, vc_id: VcId) -> Option<&Sender<Vec<u8>>> {
+        self.channels.get(&vc_id)?.transports.tx.as_ref()
+    }
+
+    /// IDs of all VCs which receive uplink data
+    pub fn uplink_vc_ids(&self) -> Vec<VcId> {
+        self.channels
+            .iter()
+            .filter(|(_, c)| c.transports.tx.is_some())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Channels of downlink data, for all VCs with an RX transport
+    pub fn receivers(&self) -> Vec<(VcId, Receiver<Vec<u8>>)> {
+        self.channels
+            .iter()
+            .filter_map(|(id, c)| Some((*id, c.transports.rx.clone()?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VcFramingConfig;
+    use rccn_usr::{
+        config::VirtualChannel,
+        transport::{config::UdpRxTransport, RxTransport},
+    };
+
+    fn vc(id: VcId, listen: &str) -> VirtualChannelConfig {
+        VirtualChannelConfig {
+            vc: VirtualChannel {
+                id,
+                name: format!("vc{}", id),
+                splitter: None,
+                tx_transport: None,
+                rx_transport: Some(RxTransport::Udp(UdpRxTransport {
+                    listen: listen.into(),
+                })),
+            },
+            framing: VcFramingConfig::default(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![vc(0, "127.0.0.1:2000"), vc(1, "127.0.0.1:2001"), vc(2, "127.0.0.1:2002")];
+        let mut new = vec![vc(0, "127.0.0.1:2000"), vc(2, "127.0.0.1:3002"), vc(3, "127.0.0.1:2003")];
+        new[0].framing.space_packet_apid = Some(0x44);
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                VcChange::Removed(1),
+                VcChange::FramingChanged(0),
+                VcChange::TransportsChanged(2),
+                VcChange::Added(3),
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+    }
+}
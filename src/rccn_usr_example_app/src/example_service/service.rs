@@ -1,9 +1,20 @@
-use std::{thread::sleep, time::Duration};
+use std::{thread, time::Duration};
 
-use rccn_usr::service::{AcceptanceResult, AcceptedTc, PusService};
+use futures::channel::oneshot;
+use rccn_usr::service::{AcceptedTc, AsyncPusService, TcFuture};
 
 use super::command;
 
+/// Waits for `duration` without blocking the thread pool the commands run on
+async fn sleep(duration: Duration) {
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        thread::sleep(duration);
+        let _ = tx.send(());
+    });
+    let _ = rx.await;
+}
+
 pub struct ExampleService {
 }
 
@@ -13,20 +24,25 @@ impl ExampleService {
     }
 }
 
-impl PusService for ExampleService {
+impl AsyncPusService for ExampleService {
     type CommandT = command::Command;
 
-    fn handle_tc(&mut self, tc: AcceptedTc, cmd: Self::CommandT) -> AcceptanceResult {
-        match cmd {
-            command::Command::GeneratedCommandTest(args) => tc.handle(||{
-                sleep(Duration::from_millis(2000));
-                println!("Generated command test args: {args:?}");
-                true
-            })
-        }
+    fn handle_tc(&mut self, tc: AcceptedTc, cmd: Self::CommandT) -> TcFuture {
+        Box::pin(async move {
+            match cmd {
+                command::Command::GeneratedCommandTest(args) => {
+                    tc.handle_async(async {
+                        sleep(Duration::from_millis(2000)).await;
+                        println!("Generated command test args: {args:?}");
+                        true
+                    })
+                    .await
+                }
+            }
+        })
     }
     
     fn service() -> u8 {
         130
     }
-}
\ No newline at end of file
+}
@@ -6,6 +6,8 @@ use rccn_usr_pus::app::PusApp;
 mod example_service;
 
 const APID: u16 = 42;
+/// Commands of the example service which may run at the same time
+const EXAMPLE_SERVICE_MAX_CONCURRENT: usize = 4;
 
 fn main() -> Result<()> {
     let node = new_shared_ros2_node("rccn_usr_example_app", &"/")?;
@@ -14,7 +16,7 @@ fn main() -> Result<()> {
     app.add_virtual_channel(&VirtualChannel::on_ros2_topic(0, "bus_realtime"))?;
 
     let service = ExampleService::new();
-    app.register_service(service);
+    app.register_async_service(service, EXAMPLE_SERVICE_MAX_CONCURRENT)?;
 
     app.run();
     Ok(())
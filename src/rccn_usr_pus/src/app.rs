@@ -1,65 +1,393 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use crossbeam::channel::Select;
+use futures::executor::ThreadPool;
 use rccn_usr::{
-    config::VirtualChannel, service::{AcceptanceResult, CommandReplyBase, PusAppBase, PusService}, transport::{manager::TransportManagerError, ros2::SharedNode, TransportManager}, types::{Receiver, Sender, VcId}
+    config::VirtualChannel, service::{accept_tc, AcceptanceError, AcceptanceResult, AcceptedTc, AsyncPusService, CommandExecutionStatus, CommandReplyBase, PusAppBase, PusService}, transport::{manager::TransportManagerError, ros2::SharedNode, TransportManager}, types::{MsgCounterPolicy, Receiver, Sender, VcId}
+};
+use satrs::{
+    pool::SharedStaticMemoryPool,
+    spacepackets::{
+        ecss::{tc::PusTcReader, EcssEnumU8, PusPacket},
+        CcsdsPacket, MAX_APID,
+    },
+};
+use thiserror::Error;
+
+use crate::{
+    command_gate::{CommandGate, GateDecision},
+    in_flight::InFlightCommands,
+    test_service::service::TestService,
 };
 
-type ServiceHandler = Box<dyn FnMut(&[u8], CommandReplyBase) -> AcceptanceResult + Send>;
+/// How often the run loop checks in-flight commands for timeouts
+const WATCHDOG_PERIOD: Duration = Duration::from_millis(100);
+
+type ServiceHandler = Box<dyn FnMut(&PusTcReader, CommandReplyBase) -> AcceptanceResult + Send>;
+
+#[derive(Error, Debug)]
+pub enum PusAppError {
+    #[error("Service {service} is already registered on APID {apid}")]
+    DuplicateService { apid: u16, service: u8 },
+    #[error("APID {0} is not served by this app")]
+    UnknownApid(u16),
+    #[error("APID {0} is already served by this app")]
+    DuplicateApid(u16),
+    #[error("APID {0} is not a valid 11 bit APID")]
+    InvalidApid(u16),
+    #[error("Service {service} on APID {apid} would receive the arm command of the command gate")]
+    ArmCommandConflict { apid: u16, service: u8 },
+    #[error("Could not create thread pool for async services: {0}")]
+    ThreadPool(#[from] std::io::Error),
+}
 
 pub struct PusApp {
     transport_manager: TransportManager,
-    handlers: Vec<(u8, ServiceHandler)>,
-    base: PusAppBase,
+    /// Handlers by APID and service type
+    handlers: HashMap<(u16, u8), ServiceHandler>,
+    /// Each APID has its own verification reporter and message counter
+    bases: HashMap<u16, PusAppBase>,
+    /// The APID the app was created with, which also rejects TCs for unknown APIDs
+    default_apid: u16,
+    gate: Option<CommandGate>,
+    /// Executes the commands of async services, created with the first one
+    pool: Option<ThreadPool>,
+    in_flight: InFlightCommands,
+    /// Number of commands whose verification or service reports could not be sent
+    send_failures: Arc<AtomicUsize>,
 }
 
 impl PusApp {
+    /// Creates an app serving `apid`, with the ST[17] test service registered
+    /// (see [`PusApp::remove_test_service`]).
     pub fn new(apid: u16, ros2_node_prefix: String) -> Self {
-        Self {
+        let mut app = Self {
             transport_manager: TransportManager::new(ros2_node_prefix).unwrap(),
-            handlers: Vec::new(),
-            base: PusAppBase::new(apid, 0),
-        }
+            handlers: HashMap::new(),
+            bases: HashMap::from([(apid, PusAppBase::new(apid, 0))]),
+            default_apid: apid,
+            gate: None,
+            pool: None,
+            in_flight: InFlightCommands::default(),
+            send_failures: Arc::new(AtomicUsize::new(0)),
+        };
+        app.add_test_service(apid);
+        app
     }
 
     pub fn new_with_ros2_node(apid: u16, node: SharedNode) -> Self {
-        Self {
+        let mut app = Self {
             transport_manager: TransportManager::new_with_ros2_node(node).unwrap(),
-            handlers: Vec::new(),
-            base: PusAppBase::new(apid, 0)
+            handlers: HashMap::new(),
+            bases: HashMap::from([(apid, PusAppBase::new(apid, 0))]),
+            default_apid: apid,
+            gate: None,
+            pool: None,
+            in_flight: InFlightCommands::default(),
+            send_failures: Arc::new(AtomicUsize::new(0)),
+        };
+        app.add_test_service(apid);
+        app
+    }
+
+    /// Serves `apid` in addition to the APID the app was created with,
+    /// with the ST[17] test service registered.
+    pub fn add_apid(&mut self, apid: u16) -> Result<(), PusAppError> {
+        if apid > MAX_APID {
+            return Err(PusAppError::InvalidApid(apid));
+        }
+        if self.bases.contains_key(&apid) {
+            return Err(PusAppError::DuplicateApid(apid));
         }
+        let default_base = &self.bases[&self.default_apid];
+        let mut base = PusAppBase::new(apid, 0);
+        base.tm_pool = default_base.tm_pool.clone();
+        base.time_ref_status = default_base.time_ref_status.clone();
+        base.set_msg_counter_policy(default_base.msg_counters.lock().unwrap().policy());
+        self.bases.insert(apid, base);
+        self.add_test_service(apid);
+        Ok(())
     }
 
-    pub fn register_service<S: PusService + 'static + Send>(&mut self, mut service: S) {
-        let handler: ServiceHandler =
-            Box::new(move |bytes, base| service.handle_tc_bytes(bytes, base));
+    /// Registers the built-in test service on an APID.
+    fn add_test_service(&mut self, apid: u16) {
+        self.register_service_for_apid(apid, TestService)
+            .expect("Test service registered twice");
+    }
+
+    /// Removes the built-in ST[17] test service of an APID, so another test service can be
+    /// registered on it.
+    ///
+    /// Removes whichever ST[17] handler the APID has, so it must be called before registering
+    /// the replacement.
+    pub fn remove_test_service(&mut self, apid: u16) -> Result<(), PusAppError> {
+        if !self.bases.contains_key(&apid) {
+            return Err(PusAppError::UnknownApid(apid));
+        }
+        self.handlers.remove(&(apid, TestService::service()));
+        Ok(())
+    }
+
+    /// Adds the handler of a service type on an APID, there can only be one per service type.
+    fn add_handler(
+        &mut self,
+        apid: u16,
+        service: u8,
+        handler: ServiceHandler,
+    ) -> Result<(), PusAppError> {
+        if !self.bases.contains_key(&apid) {
+            return Err(PusAppError::UnknownApid(apid));
+        }
+        if self.handlers.contains_key(&(apid, service)) {
+            return Err(PusAppError::DuplicateService { apid, service });
+        }
+        if self.gate.as_ref().is_some_and(|gate| gate.arm_command().0 == service) {
+            return Err(PusAppError::ArmCommandConflict { apid, service });
+        }
+        self.handlers.insert((apid, service), handler);
+        Ok(())
+    }
 
-        self.handlers.push((S::service(), handler));
+    /// Registers the handler of a service type, there can only be one per service type.
+    ///
+    /// Its commands are not tracked as in flight, even if they return
+    /// [`CommandExecutionStatus::Started`].
+    pub fn register_service<S: PusService + 'static + Send>(
+        &mut self,
+        service: S,
+    ) -> Result<(), PusAppError> {
+        self.register_service_for_apid(self.default_apid, service)
+    }
+
+    /// Registers a service on an APID added with [`PusApp::add_apid`].
+    pub fn register_service_for_apid<S: PusService + 'static + Send>(
+        &mut self,
+        apid: u16,
+        mut service: S,
+    ) -> Result<(), PusAppError> {
+        let handler: ServiceHandler = Box::new(move |tc, base| service.handle_pus_tc(tc, base));
+        self.add_handler(apid, S::service(), handler)
+    }
+
+    /// Registers an async service, running at most `max_concurrent` of its commands at a time.
+    ///
+    /// The commands are executed on a thread pool shared by all async services, so the app keeps
+    /// accepting TCs while they run. Further commands are rejected with [`AcceptanceError::ServiceBusy`].
+    /// The commands are tracked as in flight until they finish, see [`PusApp::in_flight`].
+    pub fn register_async_service<S: AsyncPusService + 'static + Send>(
+        &mut self,
+        service: S,
+        max_concurrent: usize,
+    ) -> Result<(), PusAppError> {
+        self.register_async_service_for_apid(self.default_apid, service, max_concurrent)
+    }
+
+    /// Registers an async service on an APID added with [`PusApp::add_apid`].
+    pub fn register_async_service_for_apid<S: AsyncPusService + 'static + Send>(
+        &mut self,
+        apid: u16,
+        mut service: S,
+        max_concurrent: usize,
+    ) -> Result<(), PusAppError> {
+        if !self.bases.contains_key(&apid) {
+            return Err(PusAppError::UnknownApid(apid));
+        }
+
+        let pool = match &self.pool {
+            Some(pool) => pool.clone(),
+            None => self.pool.insert(ThreadPool::new()?).clone(),
+        };
+        let running = Arc::new(AtomicUsize::new(0));
+        let in_flight = self.in_flight.clone();
+        let send_failures = self.send_failures.clone();
+
+        let handler: ServiceHandler = Box::new(move |tc, base| {
+            if running.load(Ordering::SeqCst) >= max_concurrent {
+                return Self::reject(&base, tc, AcceptanceError::ServiceBusy(S::service()));
+            }
+
+            let (accepted, cmd) = accept_tc::<S::CommandT>(tc, base.clone(), S::service())?;
+            let future = in_flight.track(tc, base, service.handle_tc(accepted, cmd));
+
+            running.fetch_add(1, Ordering::SeqCst);
+            let running = running.clone();
+            let send_failures = send_failures.clone();
+            pool.spawn_ok(async move {
+                let result = future.await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                if let Err(e) = result {
+                    Self::log_error(&send_failures, &e);
+                }
+            });
+
+            Ok(CommandExecutionStatus::Started)
+        });
+        self.add_handler(apid, S::service(), handler)
+    }
+
+    /// Sets the timeout of the async commands of a service type, after which they are aborted.
+    pub fn set_service_timeout(&mut self, service: u8, timeout: Duration) {
+        self.in_flight.set_timeout(service, None, timeout);
+    }
+
+    /// Sets the timeout of the async commands of a service subtype, instead of the service timeout.
+    pub fn set_subservice_timeout(&mut self, service: u8, subservice: u8, timeout: Duration) {
+        self.in_flight.set_timeout(service, Some(subservice), timeout);
+    }
+
+    /// Returns a handle to list and cancel the commands of async services which are in flight.
+    pub fn in_flight(&self) -> InFlightCommands {
+        self.in_flight.clone()
+    }
+
+    /// Returns the number of commands whose verification or service reports could not be sent,
+    /// e.g. because the downlink is stuck. It keeps counting while the app runs.
+    pub fn send_failures(&self) -> Arc<AtomicUsize> {
+        self.send_failures.clone()
+    }
+
+    /// Returns the spacecraft time reference status set in the TM of all APIDs,
+    /// it can be updated while the app runs, e.g. when the time is synchronized.
+    pub fn time_reference_status(&self) -> Arc<AtomicU8> {
+        self.bases[&self.default_apid].time_ref_status.clone()
+    }
+
+    /// Sets which TM of an APID share a message type counter, for all APIDs.
+    /// Should be set before the app runs, as it restarts the counters.
+    pub fn set_msg_counter_policy(&mut self, policy: MsgCounterPolicy) {
+        for base in self.bases.values() {
+            base.set_msg_counter_policy(policy);
+        }
+    }
+
+    /// Sends the TM which services add to `pool`, see [`CommandReplyBase::send_stored_tm`].
+    pub fn set_tm_pool(&mut self, pool: SharedStaticMemoryPool) {
+        for base in self.bases.values_mut() {
+            base.tm_pool = Some(pool.clone());
+        }
+    }
+
+    /// Logs an error of a command, counting the ones caused by TM which could not be sent.
+    fn log_error(send_failures: &AtomicUsize, err: &AcceptanceError) {
+        match err {
+            AcceptanceError::SendVerificationTmFailed | AcceptanceError::SendTmFailed => {
+                let count = send_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                println!("Could not send TM of command ({count} send failures so far): {err:?}");
+            }
+            _ => println!("Command was not accepted: {err:?}"),
+        }
+    }
+
+    /// Checks all incoming TCs against `gate` before they are passed on to the services.
+    ///
+    /// Fails if a service is registered for the service type of the arm command,
+    /// since the gate handles the arm command itself.
+    pub fn set_command_gate(&mut self, gate: CommandGate) -> Result<(), PusAppError> {
+        let (arm_service, _) = gate.arm_command();
+        if let Some((apid, service)) = self.handlers.keys().find(|(_, s)| *s == arm_service) {
+            return Err(PusAppError::ArmCommandConflict {
+                apid: *apid,
+                service: *service,
+            });
+        }
+        self.gate = Some(gate);
+        Ok(())
     }
 
     pub fn add_virtual_channel(&mut self, vc: &VirtualChannel) -> Result<(), TransportManagerError> {
         self.transport_manager.add_virtual_channel(vc)
     }
 
+    /// Sends an ST[01] acceptance failure for a TC rejected by the app itself and returns `err`.
+    fn reject(reply: &CommandReplyBase, tc: &PusTcReader, err: AcceptanceError) -> AcceptanceResult {
+        println!(
+            "Rejected TC[{},{}]: {:?}",
+            tc.service(),
+            tc.subservice(),
+            err
+        );
+        let token = reply.app.verification_reporter.clone().add_tc(tc);
+        let err_code: EcssEnumU8 = err.clone().into();
+        if let Err(e) = reply.send_acceptance_failure(token, &err_code, &[]) {
+            println!("Error sending acceptance failure TM: {e}");
+        }
+        Err(err)
+    }
+
+    /// Applies the command gate to a TC.
+    ///
+    /// Returns the result if the TC was handled by the gate: an arm command, or a rejected command.
+    fn apply_gate(
+        gate: &mut CommandGate,
+        tc: &PusTcReader,
+        reply: CommandReplyBase,
+    ) -> Option<AcceptanceResult> {
+        match gate.check(tc) {
+            GateDecision::Accept => None,
+            GateDecision::Armed(service, subservice) => {
+                println!("Armed critical command TC[{service},{subservice}]");
+                let token = reply.app.verification_reporter.clone().add_tc(tc);
+                let result = reply
+                    .send_acceptance_success(token)
+                    .map_err(|_| AcceptanceError::SendVerificationTmFailed)
+                    .and_then(|accepted| AcceptedTc::new(reply, accepted).handle(|| true));
+                Some(result)
+            }
+            GateDecision::Reject(err) => Some(Self::reject(&reply, tc, err)),
+        }
+    }
+
+    /// Parses a TC and passes it on to the handler of its APID and service.
+    ///
+    /// TCs for an APID not served by the app get an acceptance failure from the default APID,
+    /// TCs for a service without handler from their APID.
+    /// Bytes which are not a PUS TC are dropped without a reply.
     fn handle_tc_internal(
-        app_base: &PusAppBase,
-        handlers: &mut Vec<(u8, ServiceHandler)>,
+        bases: &HashMap<u16, PusAppBase>,
+        default_apid: u16,
+        handlers: &mut HashMap<(u16, u8), ServiceHandler>,
+        gate: &mut Option<CommandGate>,
         data: &[u8],
         tx: Sender,
-    ) -> Vec<AcceptanceResult> {
-        handlers
-            .iter_mut()
-            // Call each service handler
-            .map(|(service_id, handler)| {
-                let reply_base = app_base.new_reply(*service_id, tx.clone());
-                handler(data, reply_base)
-            })
-            // Gather all the AcceptanceResults
-            .collect()
+    ) -> AcceptanceResult {
+        let (tc, _) = PusTcReader::new(data).map_err(AcceptanceError::PusError)?;
+
+        let apid = tc.sp_header().apid();
+        let Some(app_base) = bases.get(&apid) else {
+            let reply = bases[&default_apid].new_reply(tc.service(), tx).for_tc(&tc);
+            return Self::reject(&reply, &tc, AcceptanceError::UnknownApid(apid));
+        };
+        let reply = app_base.new_reply(tc.service(), tx).for_tc(&tc);
+
+        if let Some(gate) = gate {
+            if let Some(result) = Self::apply_gate(gate, &tc, reply.clone()) {
+                return result;
+            }
+        }
+
+        match handlers.get_mut(&(apid, tc.service())) {
+            Some(handler) => handler(&tc, reply),
+            None => Self::reject(&reply, &tc, AcceptanceError::UnknownService(tc.service())),
+        }
     }
 
     // Mainly for testing purposes
-    pub fn handle_tc(&mut self, data: &[u8], tx: Sender) -> Vec<AcceptanceResult> {
-        Self::handle_tc_internal(&self.base, &mut self.handlers, data, tx)
+    pub fn handle_tc(&mut self, data: &[u8], tx: Sender) -> AcceptanceResult {
+        Self::handle_tc_internal(
+            &self.bases,
+            self.default_apid,
+            &mut self.handlers,
+            &mut self.gate,
+            data,
+            tx,
+        )
     }
 
     pub fn run(mut self) {
@@ -82,20 +410,37 @@ impl PusApp {
         }
 
         loop {
+            self.in_flight.expire(Instant::now());
+
             // Wait until a RX channel is available, get VC info
-            let op = select.select();
-            let (vc, rx, tx) = vc_info[op.index()];
+            let Ok(op) = select.select_timeout(WATCHDOG_PERIOD) else {
+                continue;
+            };
+            let index = op.index();
+            let (vc, rx, tx) = vc_info[index];
 
             // Attempt to receive from the channel
             match op.recv(rx) {
                 Ok(msg) => {
                     println!("PUS APP received command on vc id {vc}");
 
-                    Self::handle_tc_internal(&self.base, &mut self.handlers, &msg, tx.clone());
-
-                    // TODO: check that exactly one service handled the command succesfully
+                    let result = Self::handle_tc_internal(
+                        &self.bases,
+                        self.default_apid,
+                        &mut self.handlers,
+                        &mut self.gate,
+                        &msg,
+                        tx.clone(),
+                    );
+                    if let Err(e) = result {
+                        Self::log_error(&self.send_failures, &e);
+                    }
+                }
+                Err(_) => {
+                    // Keep serving the other virtual channels
+                    println!("Virtual channel {vc} disconnected");
+                    select.remove(index);
                 }
-                Err(_) => todo!(),
             }
         }
     }
@@ -106,13 +451,17 @@ mod tests {
     use std::sync::{Arc, Mutex};
 
     use super::*;
+    use crate::command_gate::{CommandPattern, GateAction};
     use crate::parameter_management_service::{
         service::ParameterManagementService, ParameterError, PusParameters,
     };
-    use crossbeam::channel::bounded;
-    use rccn_usr::service::{util::create_pus_tc, CommandExecutionStatus};
+    use crossbeam::channel::{bounded, unbounded};
+    use rccn_usr::service::{
+        util::create_pus_tc, CommandParseError, CommandParseResult, ServiceCommand, TcFuture,
+    };
     use rccn_usr_pus_macros::PusParameters;
     use satrs::spacepackets::ecss::WritablePusPacket;
+    use std::time::Duration;
     use xtce_rs::bitbuffer::{BitBuffer, BitWriter};
 
     #[derive(PusParameters)]
@@ -129,7 +478,7 @@ mod tests {
         let mut app = PusApp::new(1, "test".into());
         let parameters = Arc::new(Mutex::new(TestParameters { value: 42 }));
         let service = ParameterManagementService::new(parameters);
-        app.register_service(service);
+        app.register_service(service).unwrap();
 
         // Create a test TC for parameter reporting
         let mut tc_data = [0u8; 128];
@@ -144,14 +493,314 @@ mod tests {
         let tc_bytes = tc.to_vec().unwrap();
 
         // Handle TC
-        let results = app.handle_tc(&tc_bytes, tm_tx);
+        let result = app.handle_tc(&tc_bytes, tm_tx);
 
-        // Check that a service returned Completed
-        assert!(results
-            .iter()
-            .any(|r| matches!(r, Ok(CommandExecutionStatus::Completed))));
+        // Check that the service returned Completed
+        assert!(matches!(result, Ok(CommandExecutionStatus::Completed)));
 
         // Check that 4 messages were sent to TM rx (accepted, started, completed, parameter TM)
         assert_eq!(tm_rx.len(), 4);
     }
+
+    #[test]
+    fn test_command_gate_rejects_before_services() {
+        let (tm_tx, tm_rx) = bounded(8);
+
+        let mut app = PusApp::new(1, "test".into());
+        let parameters = Arc::new(Mutex::new(TestParameters { value: 42 }));
+        app.register_service(ParameterManagementService::new(parameters))
+            .unwrap();
+        app.set_command_gate(
+            CommandGate::new(GateAction::Allow)
+                .rule(CommandPattern::subservice(20, 3), GateAction::Critical),
+        )
+        .unwrap();
+
+        // One parameter, hash 0x1234, values are sent as 64 bits
+        let mut app_data = vec![0, 1, 0, 0, 0x12, 0x34];
+        app_data.extend_from_slice(&7u64.to_be_bytes());
+        let set_value = create_pus_tc(1, 20, 3, &app_data).to_vec().unwrap();
+
+        // Not armed: only an acceptance failure is sent, the parameter is not touched
+        let result = app.handle_tc(&set_value, tm_tx.clone());
+        assert!(matches!(result, Err(AcceptanceError::CommandNotArmed(20, 3))));
+        assert_eq!(tm_rx.len(), 1);
+        let failure = tm_rx.recv().unwrap();
+        assert_eq!(failure[7..9], [1, 2]);
+
+        // Arm, then fire
+        let arm = create_pus_tc(1, 128, 1, &[20, 3]).to_vec().unwrap();
+        let result = app.handle_tc(&arm, tm_tx.clone());
+        assert!(matches!(result, Ok(CommandExecutionStatus::Completed)));
+        let result = app.handle_tc(&set_value, tm_tx.clone());
+        assert!(matches!(result, Ok(CommandExecutionStatus::Completed)));
+
+        // Commands which are not critical can not be armed
+        let arm = create_pus_tc(1, 128, 1, &[20, 1]).to_vec().unwrap();
+        let result = app.handle_tc(&arm, tm_tx);
+        assert!(matches!(result, Err(AcceptanceError::CommandNotCritical(20, 1))));
+
+        // The arm command must not hide a service
+        assert!(matches!(
+            app.set_command_gate(CommandGate::new(GateAction::Allow).with_arm_command(20, 1)),
+            Err(PusAppError::ArmCommandConflict { apid: 1, service: 20 })
+        ));
+    }
+
+    #[test]
+    fn test_unknown_service_and_apid_are_rejected() {
+        let (tm_tx, tm_rx) = bounded(4);
+
+        let mut app = PusApp::new(1, "test".into());
+        let parameters = Arc::new(Mutex::new(TestParameters { value: 42 }));
+        app.register_service(ParameterManagementService::new(parameters.clone()))
+            .unwrap();
+        assert!(matches!(
+            app.register_service(ParameterManagementService::new(parameters)),
+            Err(PusAppError::DuplicateService { apid: 1, service: 20 })
+        ));
+
+        let tc = create_pus_tc(1, 8, 1, &[]).to_vec().unwrap();
+        let result = app.handle_tc(&tc, tm_tx.clone());
+        assert!(matches!(result, Err(AcceptanceError::UnknownService(8))));
+
+        let tc = create_pus_tc(2, 20, 1, &[0, 0]).to_vec().unwrap();
+        let result = app.handle_tc(&tc, tm_tx);
+        assert!(matches!(result, Err(AcceptanceError::UnknownApid(2))));
+
+        // One acceptance failure each
+        assert_eq!(tm_rx.len(), 2);
+        for tm in tm_rx.try_iter() {
+            assert_eq!(tm[7..9], [1, 2]);
+        }
+    }
+
+    struct WaitCommand;
+
+    impl ServiceCommand for WaitCommand {
+        fn from_pus_tc(_tc: &PusTcReader) -> CommandParseResult<Self> {
+            Ok(Self)
+        }
+    }
+
+    /// Calls `f` every 10 ms until it returns `Some`, for at most a second.
+    fn poll_until<T>(mut f: impl FnMut() -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            if let Some(value) = f() {
+                return Some(value);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Sends `tc` until its async service has a free slot, as the slot of a finished
+    /// command is freed on the thread pool.
+    fn handle_when_not_busy(app: &mut PusApp, tc: &[u8]) -> Option<AcceptanceResult> {
+        let (tm_tx, _tm_rx) = unbounded();
+        poll_until(|| match app.handle_tc(tc, tm_tx.clone()) {
+            Err(AcceptanceError::ServiceBusy(_)) => None,
+            result => Some(result),
+        })
+    }
+
+    /// Async service whose commands run until the test releases them
+    struct WaitService {
+        release: crossbeam::channel::Receiver<()>,
+    }
+
+    impl AsyncPusService for WaitService {
+        type CommandT = WaitCommand;
+
+        fn service() -> u8 {
+            130
+        }
+
+        fn handle_tc(&mut self, tc: AcceptedTc, _cmd: WaitCommand) -> TcFuture {
+            let release = self.release.clone();
+            Box::pin(tc.handle_async(async move { release.recv().is_ok() }))
+        }
+    }
+
+    #[test]
+    fn test_async_service_concurrency_limit() {
+        let (tm_tx, tm_rx) = bounded(16);
+        let (release_tx, release_rx) = bounded(0);
+
+        let mut app = PusApp::new(1, "test".into());
+        app.register_async_service(WaitService { release: release_rx }, 1)
+            .unwrap();
+        let tc = create_pus_tc(1, 130, 1, &[]).to_vec().unwrap();
+
+        // The first command keeps running, the second one is rejected
+        assert!(matches!(
+            app.handle_tc(&tc, tm_tx.clone()),
+            Ok(CommandExecutionStatus::Started)
+        ));
+        assert!(matches!(
+            app.handle_tc(&tc, tm_tx.clone()),
+            Err(AcceptanceError::ServiceBusy(130))
+        ));
+
+        // After the first command completes, the service accepts commands again
+        release_tx.send(()).unwrap();
+        assert!(tm_rx.iter().any(|tm| tm[7..9] == [1, 7]));
+        assert!(matches!(
+            handle_when_not_busy(&mut app, &tc),
+            Some(Ok(CommandExecutionStatus::Started))
+        ));
+    }
+
+    #[test]
+    fn test_async_send_failures_are_counted() {
+        // Only room for the acceptance report, the start report can't be sent
+        let (tm_tx, _tm_rx) = bounded(1);
+        let (_release_tx, release_rx) = bounded(0);
+
+        let mut app = PusApp::new(1, "test".into());
+        app.register_async_service(WaitService { release: release_rx }, 1)
+            .unwrap();
+        let tc = create_pus_tc(1, 130, 1, &[]).to_vec().unwrap();
+
+        assert!(matches!(
+            app.handle_tc(&tc, tm_tx),
+            Ok(CommandExecutionStatus::Started)
+        ));
+        let send_failures = app.send_failures();
+        poll_until(|| (send_failures.load(Ordering::Relaxed) > 0).then_some(()));
+        assert_eq!(send_failures.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_services_on_multiple_apids() {
+        let (tm_tx, tm_rx) = bounded(16);
+
+        let mut app = PusApp::new(1, "test".into());
+        app.add_apid(2).unwrap();
+        assert!(matches!(app.add_apid(2), Err(PusAppError::DuplicateApid(2))));
+        assert!(matches!(app.add_apid(0x800), Err(PusAppError::InvalidApid(0x800))));
+        let payload = Arc::new(Mutex::new(TestParameters { value: 42 }));
+        let maintenance = Arc::new(Mutex::new(TestParameters { value: 7 }));
+        app.register_service(ParameterManagementService::new(payload))
+            .unwrap();
+        app.register_service_for_apid(2, ParameterManagementService::new(maintenance))
+            .unwrap();
+        assert!(matches!(
+            app.register_async_service_for_apid(3, HangService, 1),
+            Err(PusAppError::UnknownApid(3))
+        ));
+
+        let report = |apid| {
+            create_pus_tc(apid, 20, 1, &[0, 1, 0, 0, 0x12, 0x34])
+                .to_vec()
+                .unwrap()
+        };
+        let apid_of = |tm: &[u8]| u16::from_be_bytes([tm[0], tm[1]]) & 0x7FF;
+        let msg_counter_of = |tm: &[u8]| u16::from_be_bytes([tm[9], tm[10]]);
+
+        assert!(matches!(
+            app.handle_tc(&report(1), tm_tx.clone()),
+            Ok(CommandExecutionStatus::Completed)
+        ));
+        assert!(matches!(
+            app.handle_tc(&report(2), tm_tx),
+            Ok(CommandExecutionStatus::Completed)
+        ));
+
+        // Accepted, started, completed and the parameter report for each APID,
+        // the message counters are counted per APID
+        let tms: Vec<_> = tm_rx.try_iter().collect();
+        assert_eq!(tms.len(), 8);
+        for (i, tm) in tms.iter().enumerate() {
+            assert_eq!(apid_of(tm), if i < 4 { 1 } else { 2 });
+            assert_eq!(msg_counter_of(tm), (i % 4) as u16);
+        }
+        // Parameter value of the maintenance APID
+        assert_eq!(tms[7][tms[7].len() - 6..tms[7].len() - 2], 7u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_default_test_service() {
+        let (tm_tx, tm_rx) = bounded(16);
+
+        let mut app = PusApp::new(1, "test".into());
+        app.add_apid(2).unwrap();
+
+        let are_you_alive = create_pus_tc(2, 17, 1, &[]).to_vec().unwrap();
+        assert!(matches!(
+            app.handle_tc(&are_you_alive, tm_tx.clone()),
+            Ok(CommandExecutionStatus::Completed)
+        ));
+        assert_eq!(tm_rx.try_iter().nth(3).unwrap()[7..9], [17, 2]);
+
+        // The on-board connection test is not supported by the built-in service
+        let connection_test = create_pus_tc(1, 17, 3, &2u16.to_be_bytes()).to_vec().unwrap();
+        assert!(matches!(
+            app.handle_tc(&connection_test, tm_tx.clone()),
+            Err(AcceptanceError::CommandParseError(CommandParseError::UnknownSubservice(3)))
+        ));
+        assert_eq!(tm_rx.try_iter().next().unwrap()[7..9], [1, 2]);
+
+        // The built-in service can be replaced
+        assert!(matches!(
+            app.register_service(TestService),
+            Err(PusAppError::DuplicateService { apid: 1, service: 17 })
+        ));
+        app.remove_test_service(1).unwrap();
+        app.register_service(TestService).unwrap();
+        let are_you_alive = create_pus_tc(1, 17, 1, &[]).to_vec().unwrap();
+        assert!(matches!(
+            app.handle_tc(&are_you_alive, tm_tx),
+            Ok(CommandExecutionStatus::Completed)
+        ));
+    }
+
+    /// Async service whose commands never finish
+    struct HangService;
+
+    impl AsyncPusService for HangService {
+        type CommandT = WaitCommand;
+
+        fn service() -> u8 {
+            131
+        }
+
+        fn handle_tc(&mut self, tc: AcceptedTc, _cmd: WaitCommand) -> TcFuture {
+            // Started before the future runs, so a timeout is always a completion failure
+            let started = tc.start();
+            Box::pin(async move {
+                let _started = started;
+                futures::future::pending().await
+            })
+        }
+    }
+
+    #[test]
+    fn test_in_flight_command_timeout() {
+        let (tm_tx, tm_rx) = bounded(16);
+
+        let mut app = PusApp::new(1, "test".into());
+        app.register_async_service(HangService, 1).unwrap();
+        app.set_service_timeout(131, Duration::from_secs(5));
+        let tc = create_pus_tc(1, 131, 1, &[]).to_vec().unwrap();
+
+        assert!(matches!(
+            app.handle_tc(&tc, tm_tx.clone()),
+            Ok(CommandExecutionStatus::Started)
+        ));
+        let in_flight = app.in_flight().list();
+        assert_eq!(in_flight.len(), 1);
+
+        // The timed out command gets a completion failure and frees its slot
+        assert_eq!(app.in_flight().expire(in_flight[0].deadline.unwrap()), 1);
+        assert!(tm_rx.iter().any(|tm| tm[7..9] == [1, 8]));
+        assert!(poll_until(|| app.in_flight().list().is_empty().then_some(())).is_some());
+        assert!(matches!(
+            handle_when_not_busy(&mut app, &tc),
+            Some(Ok(CommandExecutionStatus::Started))
+        ));
+    }
 }
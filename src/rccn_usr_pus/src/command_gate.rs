@@ -0,0 +1,323 @@
+//! Acceptance rules for incoming TCs, with arm/fire gating of critical commands.
+//!
+//! Every TC is checked against the rules in order, and the first matching rule decides
+//! whether the command is allowed, denied or critical. Commands matching no rule get the
+//! default action, which makes the rules a whitelist with [`GateAction::Deny`] as default.
+//!
+//! Critical commands are only accepted right after an arm command for the same APID, service type
+//! and subtype, received within the arm window. The arm command is sent to the APID of the critical
+//! command, and is itself checked against the rules, so it has to be allowed like any other command.
+//! Only critical commands can be armed. Arming is consumed by the next critical command, whether it
+//! is the armed one or not.
+//!
+//! The arm command has the following application data:
+//!
+//! ```text
+//! | service type (u8) | service subtype (u8) |
+//! ```
+//!
+//! # Example
+//! ```
+//! use std::time::Duration;
+//! use rccn_usr_pus::command_gate::{CommandGate, CommandPattern, GateAction};
+//!
+//! // Only allow ST[17] and ST[20], deployments (TC[130,1]) need to be armed first
+//! let gate = CommandGate::new(GateAction::Deny)
+//!     .rule(CommandPattern::service(17), GateAction::Allow)
+//!     .rule(CommandPattern::service(20), GateAction::Allow)
+//!     .rule(CommandPattern::subservice(128, 1), GateAction::Allow)
+//!     .rule(CommandPattern::subservice(130, 1), GateAction::Critical)
+//!     .with_arm_window(Duration::from_secs(30));
+//! ```
+
+use std::time::{Duration, Instant};
+
+use rccn_usr::service::AcceptanceError;
+use satrs::spacepackets::{
+    ecss::{tc::PusTcReader, PusPacket},
+    CcsdsPacket,
+};
+
+/// Service type of the arm command, in the mission specific range
+pub const DEFAULT_ARM_SERVICE: u8 = 128;
+pub const DEFAULT_ARM_SUBSERVICE: u8 = 1;
+pub const DEFAULT_ARM_WINDOW: Duration = Duration::from_secs(10);
+
+/// What happens to a TC matching a rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GateAction {
+    Allow,
+    Deny,
+    /// Only allowed after a matching arm command
+    Critical,
+}
+
+/// Selects TCs by APID, service type and subtype, unset fields match any TC
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CommandPattern {
+    pub apid: Option<u16>,
+    pub service: Option<u8>,
+    pub subservice: Option<u8>,
+}
+
+impl CommandPattern {
+    pub fn service(service: u8) -> Self {
+        Self {
+            service: Some(service),
+            ..Default::default()
+        }
+    }
+
+    pub fn subservice(service: u8, subservice: u8) -> Self {
+        Self {
+            service: Some(service),
+            subservice: Some(subservice),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_apid(mut self, apid: u16) -> Self {
+        self.apid = Some(apid);
+        self
+    }
+
+    fn matches(&self, apid: u16, service: u8, subservice: u8) -> bool {
+        self.apid.is_none_or(|a| a == apid)
+            && self.service.is_none_or(|s| s == service)
+            && self.subservice.is_none_or(|s| s == subservice)
+    }
+}
+
+/// Outcome of checking a TC against the gate
+#[derive(Debug)]
+pub enum GateDecision {
+    /// Pass the TC on to its service
+    Accept,
+    /// The TC was an arm command and has armed the given service type and subtype on its APID
+    Armed(u8, u8),
+    Reject(AcceptanceError),
+}
+
+pub struct CommandGate {
+    rules: Vec<(CommandPattern, GateAction)>,
+    default_action: GateAction,
+    arm_command: (u8, u8),
+    arm_window: Duration,
+    /// Armed APID, service type and subtype, and when they were armed
+    armed: Option<(u16, u8, u8, Instant)>,
+}
+
+impl CommandGate {
+    /// Creates a gate without rules, all TCs get `default_action`.
+    pub fn new(default_action: GateAction) -> Self {
+        Self {
+            rules: Vec::new(),
+            default_action,
+            arm_command: (DEFAULT_ARM_SERVICE, DEFAULT_ARM_SUBSERVICE),
+            arm_window: DEFAULT_ARM_WINDOW,
+            armed: None,
+        }
+    }
+
+    /// Adds a rule, rules are checked in the order they were added.
+    pub fn rule(mut self, pattern: CommandPattern, action: GateAction) -> Self {
+        self.rules.push((pattern, action));
+        self
+    }
+
+    /// Sets the service type and subtype of the arm command.
+    pub fn with_arm_command(mut self, service: u8, subservice: u8) -> Self {
+        self.arm_command = (service, subservice);
+        self
+    }
+
+    /// Service type and subtype of the arm command, which are not passed on to a service.
+    pub fn arm_command(&self) -> (u8, u8) {
+        self.arm_command
+    }
+
+    /// Sets how long a critical command stays armed.
+    pub fn with_arm_window(mut self, window: Duration) -> Self {
+        self.arm_window = window;
+        self
+    }
+
+    pub fn check(&mut self, tc: &PusTcReader) -> GateDecision {
+        self.check_at(tc, Instant::now())
+    }
+
+    /// The action of the first rule matching a TC, or the default action.
+    fn action(&self, apid: u16, service: u8, subservice: u8) -> GateAction {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(apid, service, subservice))
+            .map_or(self.default_action, |(_, action)| *action)
+    }
+
+    fn check_at(&mut self, tc: &PusTcReader, now: Instant) -> GateDecision {
+        let apid = tc.sp_header().apid();
+        let (service, subservice) = (tc.service(), tc.subservice());
+
+        match self.action(apid, service, subservice) {
+            GateAction::Allow => {}
+            GateAction::Deny => {
+                return GateDecision::Reject(AcceptanceError::CommandDenied(service, subservice))
+            }
+            GateAction::Critical => match self.armed.take() {
+                Some((a, s, ss, armed_at))
+                    if (a, s, ss) == (apid, service, subservice)
+                        && now.duration_since(armed_at) <= self.arm_window => {}
+                _ => {
+                    return GateDecision::Reject(AcceptanceError::CommandNotArmed(
+                        service, subservice,
+                    ))
+                }
+            },
+        }
+
+        if (service, subservice) != self.arm_command {
+            return GateDecision::Accept;
+        }
+
+        let [armed_service, armed_subservice, ..] = *tc.app_data() else {
+            return GateDecision::Reject(AcceptanceError::ArgumentError);
+        };
+        if self.action(apid, armed_service, armed_subservice) != GateAction::Critical {
+            return GateDecision::Reject(AcceptanceError::CommandNotCritical(
+                armed_service,
+                armed_subservice,
+            ));
+        }
+        self.armed = Some((apid, armed_service, armed_subservice, now));
+        GateDecision::Armed(armed_service, armed_subservice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rccn_usr::service::util::create_pus_tc;
+    use satrs::spacepackets::ecss::WritablePusPacket;
+
+    fn check(gate: &mut CommandGate, service: u8, subservice: u8, data: &[u8], now: Instant) -> GateDecision {
+        check_apid(gate, 1, service, subservice, data, now)
+    }
+
+    fn check_apid(
+        gate: &mut CommandGate,
+        apid: u16,
+        service: u8,
+        subservice: u8,
+        data: &[u8],
+        now: Instant,
+    ) -> GateDecision {
+        let bytes = create_pus_tc(apid, service, subservice, data).to_vec().unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+        gate.check_at(&tc, now)
+    }
+
+    #[test]
+    fn test_whitelist() {
+        let mut gate = CommandGate::new(GateAction::Deny)
+            .rule(CommandPattern::subservice(20, 3), GateAction::Deny)
+            .rule(CommandPattern::service(20), GateAction::Allow)
+            .rule(CommandPattern::service(17).with_apid(2), GateAction::Allow);
+        let now = Instant::now();
+
+        assert!(matches!(check(&mut gate, 20, 1, &[], now), GateDecision::Accept));
+        assert!(matches!(
+            check(&mut gate, 20, 3, &[], now),
+            GateDecision::Reject(AcceptanceError::CommandDenied(20, 3))
+        ));
+        assert!(matches!(
+            check(&mut gate, 17, 1, &[], now),
+            GateDecision::Reject(AcceptanceError::CommandDenied(17, 1))
+        ));
+    }
+
+    #[test]
+    fn test_critical_command_needs_arming() {
+        let mut gate = CommandGate::new(GateAction::Allow)
+            .rule(CommandPattern::subservice(130, 1), GateAction::Critical)
+            .with_arm_window(Duration::from_secs(5));
+        let now = Instant::now();
+        let not_armed = |d| matches!(d, GateDecision::Reject(AcceptanceError::CommandNotArmed(130, 1)));
+
+        assert!(not_armed(check(&mut gate, 130, 1, &[], now)));
+
+        // Armed and fired within the window
+        assert!(matches!(check(&mut gate, 128, 1, &[130, 1], now), GateDecision::Armed(130, 1)));
+        assert!(matches!(
+            check(&mut gate, 130, 1, &[], now + Duration::from_secs(5)),
+            GateDecision::Accept
+        ));
+        // Arming is used up
+        assert!(not_armed(check(&mut gate, 130, 1, &[], now)));
+
+        // Fired too late
+        check(&mut gate, 128, 1, &[130, 1], now);
+        assert!(not_armed(check(&mut gate, 130, 1, &[], now + Duration::from_secs(6))));
+
+        // Other commands don't need arming
+        assert!(matches!(check(&mut gate, 130, 2, &[], now), GateDecision::Accept));
+        assert!(matches!(
+            check(&mut gate, 128, 1, &[130], now),
+            GateDecision::Reject(AcceptanceError::ArgumentError)
+        ));
+    }
+
+    #[test]
+    fn test_arming_is_per_apid() {
+        let mut gate = CommandGate::new(GateAction::Allow)
+            .rule(CommandPattern::subservice(130, 1), GateAction::Critical);
+        let now = Instant::now();
+
+        assert!(matches!(
+            check_apid(&mut gate, 1, 128, 1, &[130, 1], now),
+            GateDecision::Armed(130, 1)
+        ));
+        assert!(matches!(
+            check_apid(&mut gate, 2, 130, 1, &[], now),
+            GateDecision::Reject(AcceptanceError::CommandNotArmed(130, 1))
+        ));
+
+        check_apid(&mut gate, 2, 128, 1, &[130, 1], now);
+        assert!(matches!(check_apid(&mut gate, 2, 130, 1, &[], now), GateDecision::Accept));
+    }
+
+    #[test]
+    fn test_arm_command_is_checked_against_the_rules() {
+        let mut gate = CommandGate::new(GateAction::Deny)
+            .rule(CommandPattern::subservice(130, 1), GateAction::Critical)
+            .rule(CommandPattern::subservice(128, 1).with_apid(1), GateAction::Allow);
+        let now = Instant::now();
+
+        assert!(matches!(
+            check_apid(&mut gate, 2, 128, 1, &[130, 1], now),
+            GateDecision::Reject(AcceptanceError::CommandDenied(128, 1))
+        ));
+        assert!(matches!(
+            check_apid(&mut gate, 1, 128, 1, &[130, 1], now),
+            GateDecision::Armed(130, 1)
+        ));
+    }
+
+    #[test]
+    fn test_only_critical_commands_can_be_armed() {
+        let mut gate = CommandGate::new(GateAction::Allow)
+            .rule(CommandPattern::subservice(130, 1), GateAction::Critical)
+            .rule(CommandPattern::service(131), GateAction::Deny);
+        let now = Instant::now();
+
+        for (service, subservice) in [(130, 2), (131, 1), (128, 1)] {
+            assert!(
+                matches!(
+                    check(&mut gate, 128, 1, &[service, subservice], now),
+                    GateDecision::Reject(AcceptanceError::CommandNotCritical(s, ss))
+                        if (s, ss) == (service, subservice)
+                ),
+                "TC[{service},{subservice}]"
+            );
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Tracking of the commands executed by async services.
+//!
+//! A command of an async service is in flight from its acceptance until its future finishes.
+//! Commands running longer than the timeout of their service type or subtype, and cancelled
+//! commands, are aborted and get a failure report with the [`AbortReason`] as failure code.
+//! This is a completion failure (TM[1,8]) if the start of the command was reported, otherwise
+//! a start failure (TM[1,4]).
+//!
+//! The failure is only reported if the future was aborted before it finished, so a command
+//! never gets both a completion success and an abort failure.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::future::{abortable, AbortHandle};
+use rccn_usr::service::{
+    AcceptanceError, CommandExecutionStatus, CommandFailure, CommandReplyBase, TcFuture,
+};
+use satrs::{
+    pus::verification::{RequestId, TcStateAccepted, TcStateStarted, VerificationToken},
+    spacepackets::ecss::{tc::PusTcReader, EcssEnumU8, PusPacket},
+};
+
+/// Identifies an in-flight command, assigned in the order the commands were accepted
+pub type CommandId = u64;
+
+/// Failure codes of aborted commands, in the range of codes which services must not use
+/// (see [`CommandFailure::FIRST_RESERVED_CODE`])
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum AbortReason {
+    Timeout = CommandFailure::FIRST_RESERVED_CODE,
+    Cancelled = CommandFailure::FIRST_RESERVED_CODE + 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct InFlightCommand {
+    pub id: CommandId,
+    pub service: u8,
+    pub subservice: u8,
+    pub request_id: RequestId,
+    pub accepted_at: Instant,
+    /// When the command times out, if there is a timeout for it
+    pub deadline: Option<Instant>,
+}
+
+struct Entry {
+    command: InFlightCommand,
+    abort: AbortHandle,
+    /// Set when the command is aborted, for the failure report
+    reason: Option<AbortReason>,
+}
+
+impl Entry {
+    fn abort(&mut self, reason: AbortReason) {
+        if self.reason.is_none() {
+            self.reason = Some(reason);
+            self.abort.abort();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: CommandId,
+    commands: HashMap<CommandId, Entry>,
+    /// Timeouts by service type and optional subtype
+    timeouts: HashMap<(u8, Option<u8>), Duration>,
+}
+
+impl Registry {
+    /// A subtype timeout takes precedence over the timeout of its service type.
+    fn timeout(&self, service: u8, subservice: u8) -> Option<Duration> {
+        self.timeouts
+            .get(&(service, Some(subservice)))
+            .or_else(|| self.timeouts.get(&(service, None)))
+            .copied()
+    }
+}
+
+/// Shared handle to the in-flight commands of a [`PusApp`](crate::app::PusApp)
+#[derive(Clone, Default)]
+pub struct InFlightCommands {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl InFlightCommands {
+    /// Sets the timeout of all commands of a service type, or only of one subtype.
+    /// Only applies to commands accepted afterwards.
+    pub fn set_timeout(&self, service: u8, subservice: Option<u8>, timeout: Duration) {
+        self.registry
+            .lock()
+            .unwrap()
+            .timeouts
+            .insert((service, subservice), timeout);
+    }
+
+    /// Returns the commands in flight, oldest first.
+    pub fn list(&self) -> Vec<InFlightCommand> {
+        let mut commands: Vec<_> = self
+            .registry
+            .lock()
+            .unwrap()
+            .commands
+            .values()
+            .map(|entry| entry.command.clone())
+            .collect();
+        commands.sort_by_key(|command| command.id);
+        commands
+    }
+
+    /// Aborts a command, returns `false` if it is not in flight (anymore).
+    pub fn cancel(&self, id: CommandId) -> bool {
+        match self.registry.lock().unwrap().commands.get_mut(&id) {
+            Some(entry) => {
+                entry.abort(AbortReason::Cancelled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Aborts all commands whose deadline has passed at `now`, returns how many.
+    pub fn expire(&self, now: Instant) -> usize {
+        let mut registry = self.registry.lock().unwrap();
+        let mut expired = 0;
+        for entry in registry.commands.values_mut() {
+            if entry.reason.is_none() && entry.command.deadline.is_some_and(|d| d <= now) {
+                println!(
+                    "TC[{},{}] {} timed out",
+                    entry.command.service, entry.command.subservice, entry.command.request_id
+                );
+                entry.abort(AbortReason::Timeout);
+                expired += 1;
+            }
+        }
+        expired
+    }
+
+    /// Tracks the execution of an accepted TC, returns the future to execute instead of `future`.
+    pub(crate) fn track(
+        &self,
+        tc: &PusTcReader,
+        mut base: CommandReplyBase,
+        future: TcFuture,
+    ) -> TcFuture {
+        let (future, abort) = abortable(future);
+        let request_id = RequestId::new(tc);
+
+        let id = {
+            let mut registry = self.registry.lock().unwrap();
+            let id = registry.next_id;
+            registry.next_id += 1;
+
+            let accepted_at = Instant::now();
+            let command = InFlightCommand {
+                id,
+                service: tc.service(),
+                subservice: tc.subservice(),
+                request_id,
+                accepted_at,
+                deadline: registry
+                    .timeout(tc.service(), tc.subservice())
+                    .map(|timeout| accepted_at + timeout),
+            };
+            registry.commands.insert(
+                id,
+                Entry {
+                    command,
+                    abort,
+                    reason: None,
+                },
+            );
+            id
+        };
+
+        let commands = self.clone();
+        Box::pin(async move {
+            let result = future.await;
+            let entry = commands.registry.lock().unwrap().commands.remove(&id);
+
+            if let Ok(result) = result {
+                return result;
+            }
+            let reason = entry
+                .and_then(|entry| entry.reason)
+                .unwrap_or(AbortReason::Cancelled);
+
+            base.app.timestamp_helper.update_from_now();
+            let code = EcssEnumU8::new(reason as u8);
+            // The TC has been accepted before it was tracked
+            let sent = if base.execution_started() {
+                let token = VerificationToken::<TcStateStarted>::new_started_state(request_id);
+                base.send_completion_failure(token, &code, &[])
+            } else {
+                let token = VerificationToken::<TcStateAccepted>::new_accepted_state(request_id);
+                base.send_start_failure(token, &code, &[])
+            };
+            match sent {
+                Ok(()) => Ok(CommandExecutionStatus::Failed),
+                Err(e) => {
+                    println!("Error sending abort failure TM: {e}");
+                    Err(AcceptanceError::SendVerificationTmFailed)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, future};
+    use rccn_usr::service::{util::create_pus_tc, AcceptedTc, PusAppBase};
+    use satrs::spacepackets::ecss::WritablePusPacket;
+
+    /// Tracks a command which never finishes, if `start` is set its start has been reported.
+    fn track_pending(
+        commands: &InFlightCommands,
+        subservice: u8,
+        start: bool,
+    ) -> (TcFuture, rccn_usr::types::Receiver) {
+        let (tx, rx) = crossbeam::channel::unbounded();
+        let bytes = create_pus_tc(1, 130, subservice, &[]).to_vec().unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+        let base = PusAppBase::new(1, 0).new_reply(130, tx);
+
+        let token = VerificationToken::<TcStateAccepted>::new_accepted_state(RequestId::new(&tc));
+        let accepted = AcceptedTc::new(base.clone(), token);
+        let future: TcFuture = if start {
+            let started = accepted.start().unwrap();
+            Box::pin(async move {
+                let _started = started;
+                future::pending().await
+            })
+        } else {
+            Box::pin(future::pending())
+        };
+        (commands.track(&tc, base, future), rx)
+    }
+
+    #[test]
+    fn test_timeout_and_cancel() {
+        let commands = InFlightCommands::default();
+        commands.set_timeout(130, None, Duration::from_secs(10));
+        commands.set_timeout(130, Some(2), Duration::from_secs(1));
+
+        let (first, first_rx) = track_pending(&commands, 1, true);
+        let (second, second_rx) = track_pending(&commands, 2, false);
+        let list = commands.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(
+            list[1].deadline.unwrap() - list[1].accepted_at,
+            Duration::from_secs(1)
+        );
+
+        // Only the second command has expired, before its start was reported
+        assert_eq!(commands.expire(list[1].deadline.unwrap()), 1);
+        assert!(matches!(block_on(second), Ok(CommandExecutionStatus::Failed)));
+        let failure = second_rx.try_recv().unwrap();
+        assert_eq!(failure[7..9], [1, 4]);
+        assert_eq!(failure[6 + 7 + 8 + 4], AbortReason::Timeout as u8);
+        assert_eq!(commands.list().len(), 1);
+
+        assert!(commands.cancel(list[0].id));
+        assert!(matches!(block_on(first), Ok(CommandExecutionStatus::Failed)));
+        let reports: Vec<_> = first_rx.try_iter().collect();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0][7..9], [1, 3]);
+        assert_eq!(reports[1][7..9], [1, 8]);
+        assert_eq!(reports[1][6 + 7 + 8 + 4], AbortReason::Cancelled as u8);
+        assert!(commands.list().is_empty());
+        assert!(!commands.cancel(list[0].id));
+    }
+}
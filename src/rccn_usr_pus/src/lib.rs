@@ -1,2 +1,5 @@
 pub mod parameter_management_service;
-pub mod app;
\ No newline at end of file
+pub mod app;
+pub mod command_gate;
+pub mod in_flight;
+pub mod test_service;
\ No newline at end of file
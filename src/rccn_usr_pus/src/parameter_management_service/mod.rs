@@ -3,6 +3,7 @@ pub mod service;
 
 use std::sync::{Arc, Mutex};
 
+use rccn_usr::service::CommandFailure;
 use xtce_rs::bitbuffer::{BitBuffer, BitWriter, WriteError};
 
 // TODO we cannot use thiserror:Error because xtce_rs::bitbuffer::WriteError does not implement Error
@@ -10,6 +11,38 @@ use xtce_rs::bitbuffer::{BitBuffer, BitWriter, WriteError};
 pub enum ParameterError {
     UnknownParameter(u32),
     WriteError(WriteError),
+    /// The number of parameters given in the command doesn't match its data
+    ParameterCountMismatch { expected: u16, actual: u16 },
+}
+
+/// Failure codes of the ST[20] verification failure reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum ParameterFailureCode {
+    UnknownParameter = 1,
+    ReportTooLarge = 2,
+    ParameterCountMismatch = 3,
+}
+
+/// The failure data is the parameter hash for `UnknownParameter` and the expected
+/// and actual number of parameters (u16 each) for `ParameterCountMismatch`.
+impl From<ParameterError> for CommandFailure {
+    fn from(e: ParameterError) -> Self {
+        match e {
+            ParameterError::UnknownParameter(hash) => CommandFailure::new(
+                ParameterFailureCode::UnknownParameter as u8,
+                hash.to_be_bytes(),
+            ),
+            ParameterError::WriteError(_) => {
+                CommandFailure::new(ParameterFailureCode::ReportTooLarge as u8, [])
+            }
+            ParameterError::ParameterCountMismatch { expected, actual } => {
+                let mut data = expected.to_be_bytes().to_vec();
+                data.extend_from_slice(&actual.to_be_bytes());
+                CommandFailure::new(ParameterFailureCode::ParameterCountMismatch as u8, data)
+            }
+        }
+    }
 }
 
 pub trait PusParameters {
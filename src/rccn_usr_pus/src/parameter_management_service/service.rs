@@ -10,7 +10,8 @@
 
 use rccn_usr::
     service::{
-        AcceptanceResult, AcceptedTc, CommandExecutionStatus, PusService,
+        AcceptanceError, AcceptanceResult, AcceptedTc, CommandExecutionStatus, CommandFailure,
+        PusService,
         SubserviceTmData,
     }
 ;
@@ -111,9 +112,13 @@ impl ParameterManagementService {
     /// * `parameter_set_data` - Raw bytes containing new parameter values
     ///
     /// # Returns
-    /// * `true` if all parameters were updated successfully
-    /// * `false` if any parameter update failed
-    pub fn set_parameter_values(&self, n: u16, parameter_set_data: &Vec<u8>) -> bool {
+    /// * `Ok(())` if all parameters were updated successfully
+    /// * `Err(ParameterError::UnknownParameter)` with the first unknown parameter hash
+    pub fn set_parameter_values(
+        &self,
+        n: u16,
+        parameter_set_data: &Vec<u8>,
+    ) -> Result<(), ParameterError> {
         let mut bb = BitBuffer::wrap(&parameter_set_data);
         let mut params = self.parameters.lock().unwrap();
 
@@ -121,10 +126,10 @@ impl ParameterManagementService {
             let hash = bb.get_bits(32) as u32;
 
             if !params.set_parameter_from_be_bytes(hash, &mut bb) {
-                return false;
+                return Err(ParameterError::UnknownParameter(hash));
             }
         }
-        true
+        Ok(())
     }
 }
 
@@ -151,10 +156,21 @@ impl PusService for ParameterManagementService {
             }) => {
                 // Make sure the command is properly constructed.
                 if number_of_parameters != parameter_hashes.len() as u16 {
-                    base.send_start_failure(tc.token, &EcssEnumU8::new(0), &[])
-                        .unwrap();
-
-                    return Ok(CommandExecutionStatus::Failed);
+                    let failure = CommandFailure::from(ParameterError::ParameterCountMismatch {
+                        expected: number_of_parameters,
+                        actual: parameter_hashes.len() as u16,
+                    });
+                    return match base.send_start_failure(
+                        tc.token,
+                        &EcssEnumU8::new(failure.code),
+                        &failure.data,
+                    ) {
+                        Ok(()) => Ok(CommandExecutionStatus::Failed),
+                        Err(e) => {
+                            println!("Error sending start failure TM: {e}");
+                            Err(AcceptanceError::SendVerificationTmFailed)
+                        }
+                    };
                 }
 
                 tc.handle_with_tm(|| {
@@ -165,7 +181,9 @@ impl PusService for ParameterManagementService {
                 number_of_parameters,
                 parameter_set_data,
             }) => {
-                tc.handle(|| self.set_parameter_values(number_of_parameters, &parameter_set_data))
+                tc.handle_result(|| {
+                    self.set_parameter_values(number_of_parameters, &parameter_set_data)
+                })
             }
         }
     }
@@ -184,7 +202,9 @@ mod tests {
     use satrs::spacepackets::ecss::{tm::PusTmReader, PusPacket, WritablePusPacket};
     use xtce_rs::bitbuffer::{BitBuffer, BitWriter};
 
-    use crate::parameter_management_service::{src_buffer_to_u64, ParameterError, PusParameters};
+    use crate::parameter_management_service::{
+        src_buffer_to_u64, ParameterError, ParameterFailureCode, PusParameters,
+    };
 
     use super::ParameterManagementService;
 
@@ -302,6 +322,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unknown_parameter_failure_carries_hash() {
+        let mut common = TestCommon::new(AggregateParameters {
+            test_params: TestParameters { a: 0, b: 0.0, c: 0 },
+        });
+
+        let mut tc_data = [0u8; 128];
+        let mut tc_buffer = BitWriter::wrap(&mut tc_data);
+        tc_buffer.write_bits(1, 16).unwrap();
+        tc_buffer.write_bits(0xABCD0001, 32).unwrap();
+        tc_buffer.write_bits(0, 64).unwrap();
+
+        let tc = create_pus_tc(1, 20, 3, &tc_data);
+
+        assert_eq!(
+            common
+                .service
+                .handle_tc_bytes(&tc.to_vec().unwrap(), common.reply_base.clone())
+                .unwrap(),
+            CommandExecutionStatus::Failed
+        );
+
+        // Accepted and started, then the completion failure
+        for _ in 0..2 {
+            common.tm_rx.try_recv().unwrap();
+        }
+        let failure_bytes = common.tm_rx.try_recv().unwrap();
+        let (failure, _) = PusTmReader::new(&failure_bytes, 8).unwrap();
+        assert_eq!(failure.service(), 1);
+        assert_eq!(failure.subservice(), 8);
+
+        // The failure code and the hash follow the TC packet ID and sequence control
+        assert_eq!(
+            failure.source_data()[4..9],
+            [ParameterFailureCode::UnknownParameter as u8, 0xAB, 0xCD, 0x00, 0x01]
+        );
+    }
+
     pub struct TestCommon {
         tm_rx: Receiver,
         service: ParameterManagementService,
@@ -319,11 +377,7 @@ mod tests {
                 tm_rx,
                 service,
                 parameters: shared_parameters,
-                reply_base: CommandReplyBase {
-                    app: PusAppBase::new(1, 0),
-                    service: 20,
-                    tx: tm_tx
-                },
+                reply_base: PusAppBase::new(1, 0).new_reply(20, tm_tx),
             }
         }
 
@@ -0,0 +1,44 @@
+//! Commands of the test service (ST[17]).
+//!
+//! The are-you-alive connection test TC[17,1] has no application data.
+
+use rccn_usr::service::{CommandParseError, CommandParseResult, ServiceCommand};
+use satrs::spacepackets::ecss::{tc::PusTcReader, PusPacket};
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    AreYouAlive,
+}
+
+impl ServiceCommand for Command {
+    fn from_pus_tc(tc: &PusTcReader) -> CommandParseResult<Self> {
+        match tc.subservice() {
+            1 => Ok(Self::AreYouAlive),
+            _ => Err(CommandParseError::UnknownSubservice(tc.subservice())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rccn_usr::service::util::create_pus_tc;
+    use satrs::spacepackets::ecss::WritablePusPacket;
+
+    fn parse(subservice: u8, data: &[u8]) -> CommandParseResult<Command> {
+        let bytes = create_pus_tc(1, 17, subservice, data).to_vec().unwrap();
+        let (tc, _) = PusTcReader::new(&bytes).unwrap();
+        Command::from_pus_tc(&tc)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(1, &[]).unwrap(), Command::AreYouAlive);
+        for subservice in [2, 3] {
+            assert!(matches!(
+                parse(subservice, &[]),
+                Err(CommandParseError::UnknownSubservice(s)) if s == subservice
+            ));
+        }
+    }
+}
@@ -0,0 +1,10 @@
+//! Test service (ST[17]) to check that an application is alive.
+//!
+//! [`PusApp`](crate::app::PusApp) registers the built-in [`service::TestService`] on all of its
+//! APIDs. It only implements the are-you-alive connection test: the app has no way to receive the
+//! TM of other application processes, which the on-board connection test TC[17,3] would need to
+//! wait for. Applications which can reach other processes replace the built-in service with
+//! [`PusApp::remove_test_service`](crate::app::PusApp::remove_test_service).
+
+pub mod command;
+pub mod service;
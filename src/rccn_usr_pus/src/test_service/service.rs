@@ -0,0 +1,72 @@
+//! Implementation of ECSS PUS Service 17 - Test Service
+//!
+//! - TC[17,1] is answered with the are-you-alive report TM[17,2], which has no data
+//!
+//! The on-board connection test TC[17,3] is rejected as an unknown subservice, see the
+//! [module documentation](super).
+
+use rccn_usr::service::{
+    AcceptanceResult, AcceptedTc, CommandFailure, PusService, SubserviceTmData,
+};
+
+use super::command::Command;
+
+const TEST_SERVICE: u8 = 17;
+const ARE_YOU_ALIVE_REPORT_SUBSERVICE: u8 = 2;
+
+pub struct TestService;
+
+impl PusService for TestService {
+    type CommandT = Command;
+
+    fn service() -> u8 {
+        TEST_SERVICE
+    }
+
+    fn handle_tc(&mut self, mut tc: AcceptedTc, cmd: Self::CommandT) -> AcceptanceResult {
+        match cmd {
+            Command::AreYouAlive => tc.handle_with_tm(|| {
+                Ok::<_, CommandFailure>(SubserviceTmData {
+                    subservice: ARE_YOU_ALIVE_REPORT_SUBSERVICE,
+                    data: vec![],
+                })
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::channel::unbounded;
+    use rccn_usr::service::{util::create_pus_tc, CommandExecutionStatus, PusAppBase};
+    use satrs::spacepackets::ecss::{tm::PusTmReader, PusPacket, WritablePusPacket};
+
+    /// Handles a TC, returns the status and the (service, subservice, source data) of the TM
+    fn handle(
+        service: &mut TestService,
+        subservice: u8,
+        data: &[u8],
+    ) -> (CommandExecutionStatus, Vec<(u8, u8, Vec<u8>)>) {
+        let (tx, rx) = unbounded();
+        let base = PusAppBase::new(1, 0).new_reply(17, tx);
+        let tc = create_pus_tc(1, 17, subservice, data).to_vec().unwrap();
+        let status = service.handle_tc_bytes(&tc, base).unwrap();
+        let tms = rx
+            .try_iter()
+            .map(|bytes| {
+                let (tm, _) = PusTmReader::new(&bytes, 8).unwrap();
+                (tm.service(), tm.subservice(), tm.source_data().to_vec())
+            })
+            .collect();
+        (status, tms)
+    }
+
+    #[test]
+    fn test_are_you_alive() {
+        let (status, tms) = handle(&mut TestService, 1, &[]);
+        assert_eq!(status, CommandExecutionStatus::Completed);
+        assert_eq!(tms.len(), 4);
+        assert_eq!(tms[3], (17, 2, vec![]));
+    }
+}
@@ -1,10 +1,20 @@
+use std::time::Duration;
+
 use anyhow::Result;
-use rccn_usr::{config::VirtualChannel, transport::ros2::new_shared_ros2_node};
+use rccn_usr::{
+    config::VirtualChannel, service::AsyncPusService, transport::ros2::new_shared_ros2_node,
+};
 use rccn_usr_pus::app::PusApp;
 use stress_service::service::StressTestService;
 
 mod stress_service;
 
+/// Stress tests which may run at the same time
+const STRESS_TEST_MAX_CONCURRENT: usize = 1;
+
+/// Stress tests last at most u16::MAX seconds, plus some margin for the action server
+const STRESS_TEST_TIMEOUT: Duration = Duration::from_secs(u16::MAX as u64 + 60);
+
 fn main() -> Result<()> {
     let node = new_shared_ros2_node("vacuum_test_node", &"/")?;
     let mut app = PusApp::new_with_ros2_node(42, node.clone());
@@ -12,7 +22,8 @@ fn main() -> Result<()> {
     app.add_virtual_channel(&VirtualChannel::on_ros2_topic(0, "bus_realtime"))?;
 
     let stress_service = StressTestService::new(node.clone());
-    app.register_service(stress_service);
+    app.register_async_service(stress_service, STRESS_TEST_MAX_CONCURRENT)?;
+    app.set_service_timeout(StressTestService::service(), STRESS_TEST_TIMEOUT);
 
     app.run();
     Ok(())
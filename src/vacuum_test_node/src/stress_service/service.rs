@@ -1,31 +1,67 @@
 use crate::stress_service::command::StressServiceCommand;
-use futures::{executor::ThreadPool, task::SpawnExt};
+use futures::{
+    future::{self, Either},
+    StreamExt,
+};
 use rccn_usr::{
     r2r::{self, thermal_test_msgs::action::StressTest},
-    service::{AcceptanceResult, AcceptedTc, CommandExecutionStatus, PusService},
+    service::{AcceptedTc, AsyncPusService, CommandFailure, StepReporter, TcFuture},
     transport::ros2::SharedNode,
 };
-use satrs::spacepackets::ecss::EcssEnumU8;
+use satrs::spacepackets::ecss::EcssEnumU16;
+
+/// Failure data is limited to this length by the verification reporter
+const MAX_FAILURE_DATA_LEN: usize = 100;
 
 pub struct StressTestService {
-    pool: ThreadPool,
     node: SharedNode,
 }
 
 impl StressTestService {
     pub fn new(node: SharedNode) -> Self {
-        Self {
-            pool: ThreadPool::new().unwrap(),
-            node,
-        }
+        Self { node }
+    }
+}
+
+/// Failure codes of the ST[142] verification failure reports, the failure data is the
+/// message explaining the failure
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum StressTestFailureCode {
+    GoalFailed = 1,
+    ActionError = 2,
+}
+
+#[derive(Debug)]
+enum StressTestError {
+    Ros(r2r::Error),
+    /// The stress test finished unsuccessfully, with the message of its result
+    GoalFailed(String),
+}
+
+impl From<r2r::Error> for StressTestError {
+    fn from(e: r2r::Error) -> Self {
+        Self::Ros(e)
     }
 }
 
-async fn send_stress_test_goal(
+impl From<StressTestError> for CommandFailure {
+    fn from(e: StressTestError) -> Self {
+        let (code, message) = match e {
+            StressTestError::GoalFailed(message) => (StressTestFailureCode::GoalFailed, message),
+            StressTestError::Ros(e) => (StressTestFailureCode::ActionError, e.to_string()),
+        };
+        let mut data = message.into_bytes();
+        data.truncate(MAX_FAILURE_DATA_LEN);
+        CommandFailure::new(code as u8, data)
+    }
+}
+
+async fn run_stress_test(
     node: SharedNode,
-    tc: AcceptedTc,
+    mut steps: StepReporter,
     cmd: StressServiceCommand,
-) -> Result<(), r2r::Error> {
+) -> Result<(), StressTestError> {
     let client = node
         .lock()
         .expect("could not lock node to create client")
@@ -46,7 +82,7 @@ async fn send_stress_test_goal(
         StressServiceCommand::TcTest(d) => ("TC_TEST", d.seconds),
     };
 
-    let (_goal, done, _feedback) = client
+    let (_goal, done, feedback) = client
         .send_goal_request(StressTest::Goal {
             test_type: test_type.to_string(),
             duration: duration as i32,
@@ -54,40 +90,48 @@ async fn send_stress_test_goal(
         })?
         .await?;
 
-    // Goal has started successfully
-    let token = tc.base.send_start_success(tc.token).unwrap();
+    // Every feedback message of the action is reported as an execution step. Once the
+    // step IDs run out, further feedback is only logged.
+    let mut step: Option<u16> = Some(0);
+    let report_progress = feedback.for_each(|feedback| {
+        step = step.and_then(|step| step.checked_add(1));
+        println!("Stress test feedback: {}", feedback.status);
+        if let Some(step) = step {
+            if let Err(e) = steps.step_success(EcssEnumU16::new(step)) {
+                println!("Error sending step success telemetry: {e}");
+            }
+        }
+        future::ready(())
+    });
+
+    let (_status, result) = match future::select(Box::pin(done), Box::pin(report_progress)).await {
+        Either::Left((done, _)) => done?,
+        // The feedback stream ended before the result
+        Either::Right(((), done)) => done.await?,
+    };
 
-    let (_status, result) = done.await?;
     if result.success {
         println!("Goal succeded: {}", result.message);
-        tc.base.send_completion_success(token).unwrap();
+        Ok(())
     } else {
         println!("Goal failed: {}", result.message);
-        tc.base
-            .send_completion_failure(token, &EcssEnumU8::new(1), &[])
-            .unwrap();
+        Err(StressTestError::GoalFailed(result.message))
     }
-
-    Ok(())
 }
 
-impl PusService for StressTestService {
+impl AsyncPusService for StressTestService {
     type CommandT = StressServiceCommand;
 
     fn service() -> u8 {
         142
     }
 
-    fn handle_tc(&mut self, tc: AcceptedTc, cmd: Self::CommandT) -> AcceptanceResult {
+    fn handle_tc(&mut self, tc: AcceptedTc, cmd: Self::CommandT) -> TcFuture {
         println!("Stress service command {:?}", cmd);
 
+        // The start is reported before the goal is sent, so failures of the action are
+        // completion failures
         let node = self.node.clone();
-        self.pool
-            .spawn(async move {
-                let _ = send_stress_test_goal(node, tc, cmd).await;
-            })
-            .unwrap();
-
-        Ok(CommandExecutionStatus::Started)
+        Box::pin(tc.handle_async_result(move |steps| run_stress_test(node, steps, cmd)))
     }
 }